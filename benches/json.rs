@@ -0,0 +1,66 @@
+//! Compares writing [`serde_json::Value`] documents and reading them with
+//! [`DukContext::get_json_value`](kg_js::DukContext::get_json_value) with a round-trip through
+//! JSON text and `JSON.parse`/`JSON.stringify`.
+//!
+//! Run with `cargo bench --features serde_json --bench json`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use kg_js::{JsEngine, JsonFormat};
+use serde_json::{json, Value};
+
+const ITERATIONS: u32 = 2000;
+
+fn document() -> Value {
+    let items: Vec<Value> = (0..100)
+        .map(|i| json!({
+            "id": i,
+            "name": format!("item {}", i),
+            "price": i as f64 * 1.25,
+            "tags": ["a", "b", "c"],
+            "active": i % 2 == 0,
+            "parent": null,
+        }))
+        .collect();
+    json!({"items": items, "total": 100, "title": "benchmark"})
+}
+
+fn measure<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    println!("{:<24} {:>10.1?}/iter", name, elapsed);
+    elapsed
+}
+
+fn main() {
+    let engine = JsEngine::new().unwrap();
+    let value = document();
+
+    let write = measure("write Value", || {
+        engine.write(black_box(&value)).unwrap();
+        engine.pop();
+    });
+    let parse = measure("to_string + JSON.parse", || {
+        let text = serde_json::to_string(black_box(&value)).unwrap();
+        engine.json_decode(&text, JsonFormat::Json).unwrap();
+        engine.pop();
+    });
+    println!("write is {:.1}x faster than JSON.parse", parse.as_secs_f64() / write.as_secs_f64());
+
+    engine.write(&value).unwrap();
+    let read = measure("read Value", || {
+        black_box(engine.get_json_value(-1).unwrap());
+    });
+    let stringify = measure("JSON.stringify + parse", || {
+        let text = engine.json_encode(-1, JsonFormat::Json, None).unwrap();
+        black_box(serde_json::from_str::<Value>(&text).unwrap());
+    });
+    println!("read takes {:.1}x the time of JSON.stringify", read.as_secs_f64() / stringify.as_secs_f64());
+}
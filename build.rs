@@ -6,7 +6,7 @@ fn main() {
     let mut files: Vec<PathBuf> = std::fs::read_dir(DUKTAPE_SRC)
         .unwrap()
         .map(|e| e.unwrap().path())
-        .filter(|p| p.extension().map_or(false, |s| s == "c" || s == "h"))
+        .filter(|p| p.extension().is_some_and(|s| s == "c" || s == "h"))
         .collect();
 
     files.sort();
@@ -16,10 +16,21 @@ fn main() {
         println!("cargo:rerun-if-changed={}", p.display());
     }
 
+    // checked against the hard-coded Duktape class tables and opcodes
+    let header = std::fs::read_to_string(format!("{}/duktape.h", DUKTAPE_SRC)).unwrap();
+    let version = header
+        .lines()
+        .find_map(|l| l.strip_prefix("#define DUK_VERSION "))
+        .map(|v| v.trim().trim_end_matches('L'))
+        .unwrap();
+    println!("cargo:rustc-env=DUK_VERSION={}", version);
+
     cc::Build::new()
         .files(files
             .into_iter()
-            .filter(|p| p.extension().map_or(false, |s| s == "c")))
+            .filter(|p| p.extension().is_some_and(|s| s == "c"))
+            // included by internals.c
+            .filter(|p| p.file_name().is_some_and(|s| s != "duktape.c")))
         .flag_if_supported("-Wimplicit-fallthrough=2")
         .compile("libduktape.a");
 }
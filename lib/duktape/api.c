@@ -1,5 +1,7 @@
 #include "api.h"
 
+#include <string.h>
+
 unsigned int duk_api_version() {
     return DUK_VERSION;
 }
@@ -23,33 +25,185 @@ void* duk_api_get_heap_udata(duk_context* ctx) {
 }
 
 
-static duk_ret_t duk__console_log_helper(duk_context *ctx, const char *error_name) {
-    duk_int_t fun = duk_get_current_magic(ctx);
+#define DUK__CONSOLE_ASSERT 1
+#define DUK__CONSOLE_LOG 2
+#define DUK__CONSOLE_WARN 6
+#define DUK__CONSOLE_TIME 10
+#define DUK__CONSOLE_COUNT 11
+#define DUK__CONSOLE_GROUP 12
+#define DUK__CONSOLE_TABLE 13
+#define DUK__CONSOLE_TIME_LOG 14
+#define DUK__CONSOLE_TIME_END 15
+#define DUK__CONSOLE_COUNT_RESET 16
+#define DUK__CONSOLE_GROUP_COLLAPSED 17
+#define DUK__CONSOLE_GROUP_END 18
+
+/* Factory of the formatting helpers, compiled on first use. It is called with the built-ins listed in
+ * duk__console_builtins, captured on initialization so that they keep working when removed from the
+ * global object. */
+static const char *duk__console_helpers_src =
+    "(function(S,K,A,H,pI,pF,J){"
+    "function fmt(f,v){return typeof v==='string'?v:(v!==null&&typeof v==='object')?f(v):S(v);}"
+    "return {"
+    "printf:function(f){"
+        "var a=arguments,i=2,s=S(a[1]).replace(/%([sdifoOjc%])/g,function(m,c){"
+            "if(c==='%')return '%';"
+            "if(i>=a.length)return m;"
+            "var v=a[i++];"
+            "switch(c){"
+            "case 's':return fmt(f,v);"
+            "case 'd':case 'i':return typeof v==='symbol'?'NaN':S(pI(v,10));"
+            "case 'f':return typeof v==='symbol'?'NaN':S(pF(v));"
+            "case 'j':try{return S(J(v));}catch(e){return '[Circular]';}"
+            "case 'c':return '';"
+            "default:return f(v);"
+            "}"
+        "});"
+        "for(;i<a.length;i++)s+=' '+fmt(f,a[i]);"
+        "return s;"
+    "},"
+    "table:function(f,data,cols){"
+        "var keys=cols?cols.map(S):[],rows=[],vals=false,w=[];"
+        "K(data).forEach(function(r){"
+            "var row=data[r],cells={};"
+            "if(row!==null&&typeof row==='object'){"
+                "K(row).forEach(function(c){"
+                    "if(cols&&keys.indexOf(c)<0)return;"
+                    "if(keys.indexOf(c)<0)keys.push(c);"
+                    "cells[c]=fmt(f,row[c]);"
+                "});"
+                "rows.push([r,cells]);"
+            "}else{vals=true;rows.push([r,cells,fmt(f,row)]);}"
+        "});"
+        "var grid=[['(index)'].concat(keys)];"
+        "if(vals)grid[0].push('Values');"
+        "rows.forEach(function(r){"
+            "var l=[S(r[0])];"
+            "keys.forEach(function(k){l.push(H.call(r[1],k)?r[1][k]:'');});"
+            "if(vals)l.push(r.length>2?r[2]:'');"
+            "grid.push(l);"
+        "});"
+        "grid[0].forEach(function(_,c){w[c]=0;grid.forEach(function(l){if(l[c].length>w[c])w[c]=l[c].length;});});"
+        "function line(l){return '| '+l.map(function(c,k){return c+A(w[k]-c.length+1).join(' ');}).join(' | ')+' |';}"
+        "var out=grid.map(line);"
+        "out.splice(1,0,'|'+w.map(function(n){return A(n+3).join('-');}).join('|')+'|');"
+        "return out.join('\\n');"
+    "},"
+    "indent:function(s,n){var p=A(n+1).join('  ');return p+S(s).split('\\n').join('\\n'+p);}"
+    "};"
+    "})";
+
+/* Object and property paths of the built-ins passed to the helper factory, in order. */
+static const char *duk__console_builtins[][3] = {
+    { "String", NULL, NULL },
+    { "Object", "keys", NULL },
+    { "Array", NULL, NULL },
+    { "Object", "prototype", "hasOwnProperty" },
+    { "parseInt", NULL, NULL },
+    { "parseFloat", NULL, NULL },
+    { "JSON", "stringify", NULL },
+};
+
+#define DUK__CONSOLE_BUILTIN_COUNT (sizeof(duk__console_builtins) / sizeof(duk__console_builtins[0]))
+
+/* Pushes console[key]; the console object is looked up in the global object of the calling context. */
+static void duk__console_get_state(duk_context *ctx, const char *key) {
+    duk_get_global_string(ctx, "console");
+    duk_get_prop_string(ctx, -1, key);
+    duk_remove(ctx, -2);
+}
+
+static void duk__console_put_state(duk_context *ctx, const char *key) {
+    duk_get_global_string(ctx, "console");
+    duk_insert(ctx, -2);
+    duk_put_prop_string(ctx, -2, key);
+    duk_pop(ctx);
+}
 
+/* Pushes the formatting helpers, creating them on first use. */
+static void duk__console_push_helpers(duk_context *ctx) {
+    duk_uarridx_t i;
+
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_helpers"));
+    if (!duk_is_undefined(ctx, -1)) {
+        return;
+    }
+    duk_pop(ctx);
+    duk_eval_string(ctx, duk__console_helpers_src);
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_builtins"));
+    for (i = 0; i < DUK__CONSOLE_BUILTIN_COUNT; i++) {
+        duk_get_prop_index(ctx, -1 - (duk_idx_t) i, i);
+    }
+    duk_remove(ctx, -1 - (duk_idx_t) DUK__CONSOLE_BUILTIN_COUNT);
+    duk_call(ctx, (duk_idx_t) DUK__CONSOLE_BUILTIN_COUNT);
+    duk_dup_top(ctx);
+    duk__console_put_state(ctx, DUK_HIDDEN_SYMBOL("console_helpers"));
+}
+
+/* Calls helpers[name] with the values at [base, top) as arguments, replacing them with the result. */
+static void duk__console_call_helper(duk_context *ctx, const char *name, duk_idx_t base) {
+    duk_idx_t nargs = duk_get_top(ctx) - base;
+    duk__console_push_helpers(ctx);
+    duk_get_prop_string(ctx, -1, name);
+    duk_remove(ctx, -2);
+    duk_insert(ctx, base);
+    duk_call(ctx, nargs);
+}
+
+/* Passes arguments at [0, top) to the console callbacks. When `raw` is set, the first argument
+ * is never treated as a format string.
+ */
+static duk_ret_t duk__console_emit(duk_context *ctx, duk_int_t fun, const char *error_name, duk_bool_t raw) {
     duk_idx_t n = duk_get_top(ctx);
     duk_idx_t i;
+    void* udata;
 
     duk_get_global_string(ctx, "console");
+    duk_get_prop_string(ctx, -1, DUK_HIDDEN_SYMBOL("console_udata"));
+    udata = duk_get_pointer(ctx, -1);
+    duk_pop(ctx);
+    if (!udata) {
+        udata = duk_api_get_heap_udata(ctx);
+    }
     duk_get_prop_string(ctx, -1, DUK_HIDDEN_SYMBOL("console_callback"));
     duk_console_function callback = duk_require_pointer(ctx, -1);
     duk_pop(ctx);
+    duk_get_prop_string(ctx, -1, DUK_HIDDEN_SYMBOL("console_args_callback"));
+    duk_console_args_function args_callback = (duk_console_args_function) duk_get_pointer(ctx, -1);
+    duk_pop(ctx);
+    duk_get_prop_string(ctx, -1, DUK_HIDDEN_SYMBOL("console_indent"));
+    duk_uint_t indent = duk_get_uint(ctx, -1);
+    duk_pop(ctx);
+
+    if (args_callback) {
+        duk_pop(ctx);
+        args_callback(udata, (duk_uint_t) fun, ctx, n, indent);
+        return 0;
+    }
+
     duk_get_prop_string(ctx, -1, "format");
+    duk_remove(ctx, -2);  /* [ args... format ] */
 
-    for (i = 0; i < n; i++) {
-        if (duk_check_type_mask(ctx, i, DUK_TYPE_MASK_OBJECT)) {
-            /* Slow path formatting. */
-            duk_dup(ctx, -1);  /* console.format */
-            duk_dup(ctx, i);
-            duk_call(ctx, 1);
-            duk_replace(ctx, i);  /* arg[i] = console.format(arg[i]); */
+    if (!raw && n > 0 && duk_is_string(ctx, 0) && strchr(duk_get_string(ctx, 0), '%')) {
+        duk_insert(ctx, 0);  /* [ format args... ] */
+        duk__console_call_helper(ctx, "printf", 0);
+    } else {
+        for (i = 0; i < n; i++) {
+            if (duk_check_type_mask(ctx, i, DUK_TYPE_MASK_OBJECT)) {
+                /* Slow path formatting. */
+                duk_dup(ctx, -1);  /* console.format */
+                duk_dup(ctx, i);
+                duk_call(ctx, 1);
+                duk_replace(ctx, i);  /* arg[i] = console.format(arg[i]); */
+            }
         }
-    }
 
-    duk_pop_2(ctx);
+        duk_pop(ctx);
 
-    duk_push_string(ctx, " ");
-    duk_insert(ctx, 0);
-    duk_join(ctx, n);
+        duk_push_string(ctx, " ");
+        duk_insert(ctx, 0);
+        duk_join(ctx, n);
+    }
 
     if (error_name) {
         duk_push_error_object(ctx, DUK_ERR_ERROR, "%s", duk_require_string(ctx, -1));
@@ -59,14 +213,29 @@ static duk_ret_t duk__console_log_helper(duk_context *ctx, const char *error_nam
         duk_get_prop_string(ctx, -1, "stack");
     }
 
+    if (indent > 0) {
+        duk_push_uint(ctx, indent);
+        duk__console_call_helper(ctx, "indent", duk_get_top(ctx) - 2);
+    }
+
     duk_size_t len = 0;
     const char* msg = duk_to_lstring(ctx, -1, &len);
-    void* udata = duk_api_get_heap_udata(ctx);
-    callback(udata, fun, msg, len);
+    callback(udata, (duk_uint_t) fun, ctx, msg, len);
 
     return 0;
 }
 
+/* Emits the string on top of the stack as the only argument. */
+static duk_ret_t duk__console_emit_message(duk_context *ctx, duk_int_t fun) {
+    duk_insert(ctx, 0);
+    duk_set_top(ctx, 1);
+    return duk__console_emit(ctx, fun, NULL, 1);
+}
+
+static duk_ret_t duk__console_log_helper(duk_context *ctx, const char *error_name) {
+    return duk__console_emit(ctx, duk_get_current_magic(ctx), error_name, 0);
+}
+
 static duk_ret_t duk__console_assert(duk_context *ctx) {
     if (duk_to_boolean(ctx, 0)) {
         return 0;
@@ -100,6 +269,134 @@ static duk_ret_t duk__console_dir(duk_context *ctx) {
     return duk__console_log_helper(ctx, 0);
 }
 
+/* Coerces the label argument at index 0, defaulting to 'default'. */
+static const char *duk__console_label(duk_context *ctx) {
+    if (duk_get_top(ctx) == 0) {
+        duk_push_undefined(ctx);
+    }
+    if (duk_is_undefined(ctx, 0)) {
+        duk_push_string(ctx, "default");
+        duk_replace(ctx, 0);
+    }
+    return duk_to_string(ctx, 0);
+}
+
+static duk_ret_t duk__console_time(duk_context *ctx) {
+    const char *label = duk__console_label(ctx);
+    duk_set_top(ctx, 1);
+
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_timers"));
+    if (duk_has_prop_string(ctx, -1, label)) {
+        duk_push_sprintf(ctx, "Timer '%s' already exists", label);
+        return duk__console_emit_message(ctx, DUK__CONSOLE_WARN);
+    }
+    duk_push_number(ctx, duk_get_now(ctx));
+    duk_put_prop_string(ctx, -2, label);
+    return 0;
+}
+
+static duk_ret_t duk__console_time_log_helper(duk_context *ctx, duk_bool_t end) {
+    const char *label = duk__console_label(ctx);
+    duk_idx_t n = duk_get_top(ctx);
+
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_timers"));
+    if (!duk_get_prop_string(ctx, -1, label)) {
+        duk_push_sprintf(ctx, "Timer '%s' does not exist", label);
+        return duk__console_emit_message(ctx, DUK__CONSOLE_WARN);
+    }
+    duk_double_t elapsed = duk_get_now(ctx) - duk_get_number(ctx, -1);
+    duk_pop(ctx);
+    if (end) {
+        duk_del_prop_string(ctx, -1, label);
+    }
+    duk_pop(ctx);
+
+    duk_push_sprintf(ctx, "%s: %.3fms", label, (double) elapsed);
+    duk_replace(ctx, 0);  /* [ message data... ] */
+    duk_set_top(ctx, end ? 1 : n);
+    return duk__console_emit(ctx, duk_get_current_magic(ctx), NULL, 1);
+}
+
+static duk_ret_t duk__console_time_log(duk_context *ctx) {
+    return duk__console_time_log_helper(ctx, 0);
+}
+
+static duk_ret_t duk__console_time_end(duk_context *ctx) {
+    return duk__console_time_log_helper(ctx, 1);
+}
+
+static duk_ret_t duk__console_count(duk_context *ctx) {
+    const char *label = duk__console_label(ctx);
+    duk_set_top(ctx, 1);
+
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_counters"));
+    duk_get_prop_string(ctx, -1, label);
+    duk_uint_t count = duk_get_uint(ctx, -1) + 1;
+    duk_pop(ctx);
+    duk_push_uint(ctx, count);
+    duk_put_prop_string(ctx, -2, label);
+
+    duk_push_sprintf(ctx, "%s: %lu", label, (unsigned long) count);
+    return duk__console_emit_message(ctx, DUK__CONSOLE_COUNT);
+}
+
+static duk_ret_t duk__console_count_reset(duk_context *ctx) {
+    const char *label = duk__console_label(ctx);
+    duk_set_top(ctx, 1);
+
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_counters"));
+    if (!duk_has_prop_string(ctx, -1, label)) {
+        duk_push_sprintf(ctx, "Count for '%s' does not exist", label);
+        return duk__console_emit_message(ctx, DUK__CONSOLE_WARN);
+    }
+    duk_push_uint(ctx, 0);
+    duk_put_prop_string(ctx, -2, label);
+    return 0;
+}
+
+static duk_ret_t duk__console_group(duk_context *ctx) {
+    if (duk_get_top(ctx) > 0) {
+        duk__console_emit(ctx, duk_get_current_magic(ctx), NULL, 0);
+    }
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_indent"));
+    duk_push_uint(ctx, duk_get_uint(ctx, -1) + 1);
+    duk__console_put_state(ctx, DUK_HIDDEN_SYMBOL("console_indent"));
+    return 0;
+}
+
+static duk_ret_t duk__console_group_end(duk_context *ctx) {
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_indent"));
+    duk_uint_t indent = duk_get_uint(ctx, -1);
+    if (indent > 0) {
+        duk_push_uint(ctx, indent - 1);
+        duk__console_put_state(ctx, DUK_HIDDEN_SYMBOL("console_indent"));
+    }
+    return 0;
+}
+
+static duk_ret_t duk__console_table(duk_context *ctx) {
+    if (!duk_is_object(ctx, 0) || duk_is_function(ctx, 0)) {
+        return duk__console_emit(ctx, DUK__CONSOLE_LOG, NULL, 0);
+    }
+    duk__console_get_state(ctx, DUK_HIDDEN_SYMBOL("console_args_callback"));
+    duk_bool_t structured = duk_is_pointer(ctx, -1);
+    duk_pop(ctx);
+    if (structured) {
+        /* structured consumers receive the original data */
+        return duk__console_emit(ctx, DUK__CONSOLE_TABLE, NULL, 1);
+    }
+
+    duk_set_top(ctx, 2);
+    if (!duk_is_array(ctx, 1)) {
+        duk_push_undefined(ctx);
+        duk_replace(ctx, 1);
+    }
+    duk__console_get_state(ctx, "format");
+    duk_insert(ctx, 0);  /* [ format data columns ] */
+    duk__console_call_helper(ctx, "table", 0);
+    return duk__console_emit_message(ctx, DUK__CONSOLE_TABLE);
+}
+
 static void duk__console_reg_vararg_func(duk_context *ctx, duk_c_function func, const char *name, duk_uint_t flags) {
     duk_push_c_function(ctx, func, DUK_VARARGS);
     duk_push_string(ctx, "name");
@@ -109,27 +406,335 @@ static void duk__console_reg_vararg_func(duk_context *ctx, duk_c_function func,
     duk_put_prop_string(ctx, -2, name);
 }
 
-void duk_api_console_init(duk_context *ctx, duk_console_function console_cb) {
+/* Default console.format(), user can replace. Formats the value as JX and if that fails,
+ * falls back to ToString(v). */
+static duk_ret_t duk__console_format(duk_context *ctx) {
+    if (duk_api_encode(ctx, 0, DUK_API_FORMAT_JX, 0) != DUK_EXEC_SUCCESS) {
+        duk_pop(ctx);
+        duk_to_string(ctx, 0);
+        return 1;
+    }
+    return 1;
+}
+
+static duk_ret_t duk__console_finalizer(duk_context *ctx) {
+    duk_get_prop_string(ctx, 0, DUK_HIDDEN_SYMBOL("console_udata"));
+    void *udata = duk_get_pointer(ctx, -1);
+    duk_get_prop_string(ctx, 0, DUK_HIDDEN_SYMBOL("console_udata_free"));
+    duk_console_free_function udata_free = (duk_console_free_function) duk_get_pointer(ctx, -1);
+    duk_pop_2(ctx);
+
+    if (udata && udata_free) {
+        /* clear first, so that a rescued console does not release udata twice */
+        duk_push_pointer(ctx, NULL);
+        duk_put_prop_string(ctx, 0, DUK_HIDDEN_SYMBOL("console_udata"));
+        udata_free(udata);
+    }
+    return 0;
+}
+
+void duk_api_console_init(duk_context *ctx, duk_console_function console_cb, duk_console_args_function console_args_cb,
+                          void *console_udata, duk_console_free_function console_udata_free) {
+    duk_uarridx_t i;
+    duk_size_t j;
+
     duk_push_object(ctx);
+    if (console_udata) {
+        duk_push_pointer(ctx, console_udata);
+        duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_udata"));
+        duk_push_pointer(ctx, (void *) console_udata_free);
+        duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_udata_free"));
+        duk_push_c_function(ctx, duk__console_finalizer, 1);
+        duk_set_finalizer(ctx, -2);
+    }
     duk_push_pointer(ctx, console_cb);
     duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_callback"));
+    if (console_args_cb) {
+        duk_push_pointer(ctx, (void *) console_args_cb);
+        duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_args_callback"));
+    }
+    duk_push_object(ctx);
+    duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_timers"));
+    duk_push_object(ctx);
+    duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_counters"));
+    duk_push_uint(ctx, 0);
+    duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_indent"));
+    duk_push_array(ctx);
+    for (i = 0; i < DUK__CONSOLE_BUILTIN_COUNT; i++) {
+        duk_get_global_string(ctx, duk__console_builtins[i][0]);
+        for (j = 1; j < 3 && duk__console_builtins[i][j]; j++) {
+            duk_get_prop_string(ctx, -1, duk__console_builtins[i][j]);
+            duk_remove(ctx, -2);
+        }
+        duk_put_prop_index(ctx, -2, i);
+    }
+    duk_put_prop_string(ctx, -2, DUK_HIDDEN_SYMBOL("console_builtins"));
 
-    /* Custom function to format objects; user can replace.
-     * For now, try JX-formatting and if that fails, fall back
-     * to ToString(v).
-     */
-    duk_eval_string(ctx, "(function(E){return function format(v){try{return E('jx',v);}catch(e){return String(v);}};})(Duktape.enc)");
+    duk_push_c_function(ctx, duk__console_format, 1);
+    duk_push_string(ctx, "name");
+    duk_push_string(ctx, "format");
+    duk_def_prop(ctx, -3, DUK_DEFPROP_HAVE_VALUE | DUK_DEFPROP_FORCE);
     duk_put_prop_string(ctx, -2, "format");
 
-    duk__console_reg_vararg_func(ctx, duk__console_assert, "assert", 1);
-    duk__console_reg_vararg_func(ctx, duk__console_log, "log", 2);
+    duk__console_reg_vararg_func(ctx, duk__console_assert, "assert", DUK__CONSOLE_ASSERT);
+    duk__console_reg_vararg_func(ctx, duk__console_log, "log", DUK__CONSOLE_LOG);
     duk__console_reg_vararg_func(ctx, duk__console_log, "debug", 3);  /* alias to console.log */
     duk__console_reg_vararg_func(ctx, duk__console_trace, "trace", 4);
     duk__console_reg_vararg_func(ctx, duk__console_info, "info", 5);
-    duk__console_reg_vararg_func(ctx, duk__console_warn, "warn", 6);
+    duk__console_reg_vararg_func(ctx, duk__console_warn, "warn", DUK__CONSOLE_WARN);
     duk__console_reg_vararg_func(ctx, duk__console_error, "error", 7);
     duk__console_reg_vararg_func(ctx, duk__console_error, "exception", 8);  /* alias to console.error */
     duk__console_reg_vararg_func(ctx, duk__console_dir, "dir", 9);
+    duk__console_reg_vararg_func(ctx, duk__console_time, "time", DUK__CONSOLE_TIME);
+    duk__console_reg_vararg_func(ctx, duk__console_time_log, "timeLog", DUK__CONSOLE_TIME_LOG);
+    duk__console_reg_vararg_func(ctx, duk__console_time_end, "timeEnd", DUK__CONSOLE_TIME_END);
+    duk__console_reg_vararg_func(ctx, duk__console_count, "count", DUK__CONSOLE_COUNT);
+    duk__console_reg_vararg_func(ctx, duk__console_count_reset, "countReset", DUK__CONSOLE_COUNT_RESET);
+    duk__console_reg_vararg_func(ctx, duk__console_group, "group", DUK__CONSOLE_GROUP);
+    duk__console_reg_vararg_func(ctx, duk__console_group, "groupCollapsed", DUK__CONSOLE_GROUP_COLLAPSED);  /* alias to console.group */
+    duk__console_reg_vararg_func(ctx, duk__console_group_end, "groupEnd", DUK__CONSOLE_GROUP_END);
+    duk__console_reg_vararg_func(ctx, duk__console_table, "table", DUK__CONSOLE_TABLE);
 
     duk_put_global_string(ctx, "console");
 }
+
+
+/* Error stacks. */
+
+static duk_ret_t duk__api_get_error_stack(duk_context *ctx, void *udata) {
+    DUK_UNREF(udata);
+    duk_get_prop_string(ctx, -1, "stack");
+    return 1;
+}
+
+/* Pushes the "stack" property of the error on stack top, or the error thrown by reading it. */
+duk_int_t duk_api_get_error_stack(duk_context *ctx) {
+    duk_dup_top(ctx);
+    return duk_safe_call(ctx, duk__api_get_error_stack, NULL, 1, 1);
+}
+
+
+/* Property access. */
+
+typedef struct {
+    duk_idx_t obj_idx;
+    duk_uint_t op;
+    duk_size_t result;
+} duk__api_prop_op_args;
+
+static duk_ret_t duk__api_prop_op(duk_context *ctx, void *udata) {
+    duk__api_prop_op_args *args = (duk__api_prop_op_args *) udata;
+
+    switch (args->op) {
+    case DUK_API_PROP_GET:
+        args->result = duk_get_prop(ctx, args->obj_idx);
+        return 1;
+    case DUK_API_PROP_PUT:
+        args->result = duk_put_prop(ctx, args->obj_idx);
+        return 0;
+    case DUK_API_PROP_DEL:
+        args->result = duk_del_prop(ctx, args->obj_idx);
+        return 0;
+    case DUK_API_PROP_LENGTH:
+        args->result = duk_get_length(ctx, args->obj_idx);
+        return 0;
+    default:
+        args->result = duk_has_prop(ctx, args->obj_idx);
+        return 0;
+    }
+}
+
+duk_int_t duk_api_prop_op(duk_context *ctx, duk_idx_t obj_idx, duk_uint_t op, duk_size_t *result) {
+    duk__api_prop_op_args args;
+    duk_idx_t nargs = op == DUK_API_PROP_PUT ? 2 : (op == DUK_API_PROP_LENGTH ? 0 : 1);
+    duk_int_t rc;
+    args.obj_idx = duk_normalize_index(ctx, obj_idx);
+    args.op = op;
+    args.result = 0;
+
+    if (duk_api_in_protected_call(ctx)) {
+        /* an error is caught by the active protected call, e.g. the one running a native function */
+        duk__api_prop_op(ctx, &args);
+        *result = args.result;
+        return DUK_EXEC_SUCCESS;
+    }
+    rc = duk_safe_call(ctx, duk__api_prop_op, &args, nargs, 1);
+    if (rc == DUK_EXEC_SUCCESS && op != DUK_API_PROP_GET) {
+        duk_pop(ctx);
+    }
+    *result = args.result;
+    return rc;
+}
+
+
+/* Property descriptors. */
+
+typedef struct {
+    duk_idx_t obj_idx;
+    duk_uint_t flags;
+} duk__api_prop_args;
+
+static duk_ret_t duk__api_def_prop(duk_context *ctx, void *udata) {
+    duk__api_prop_args *args = (duk__api_prop_args *) udata;
+
+    duk_def_prop(ctx, args->obj_idx, args->flags);
+    return 0;
+}
+
+static duk_ret_t duk__api_get_prop_desc(duk_context *ctx, void *udata) {
+    duk__api_prop_args *args = (duk__api_prop_args *) udata;
+
+    duk_get_prop_desc(ctx, args->obj_idx, args->flags);
+    return 1;
+}
+
+/* Defines a property as duk_def_prop(), consuming the key and nvalues values (value, getter, setter)
+ * on stack top. Leaves undefined on success, or the error on failure. obj_idx must be absolute. */
+duk_int_t duk_api_def_prop(duk_context *ctx, duk_idx_t obj_idx, duk_uint_t flags, duk_idx_t nvalues) {
+    duk__api_prop_args args;
+    args.obj_idx = obj_idx;
+    args.flags = flags;
+
+    return duk_safe_call(ctx, duk__api_def_prop, &args, nvalues + 1, 1);
+}
+
+/* Replaces the key on stack top with the own property descriptor, undefined if there is none,
+ * or the error on failure. obj_idx must be absolute. */
+duk_int_t duk_api_get_prop_desc(duk_context *ctx, duk_idx_t obj_idx) {
+    duk__api_prop_args args;
+    args.obj_idx = obj_idx;
+    args.flags = 0;
+
+    return duk_safe_call(ctx, duk__api_get_prop_desc, &args, 1, 1);
+}
+
+
+/* Enumeration. */
+
+typedef struct {
+    duk_idx_t idx;
+    duk_uint_t flags;
+} duk__api_enum_args;
+
+static duk_ret_t duk__api_enum(duk_context *ctx, void *udata) {
+    duk__api_enum_args *args = (duk__api_enum_args *) udata;
+
+    duk_enum(ctx, args->idx, args->flags);
+    return 1;
+}
+
+static duk_ret_t duk__api_next(duk_context *ctx, void *udata) {
+    duk__api_enum_args *args = (duk__api_enum_args *) udata;
+
+    if (duk_next(ctx, args->idx, 1)) {
+        duk_push_true(ctx);
+    } else {
+        duk_push_undefined(ctx);
+        duk_push_undefined(ctx);
+        duk_push_false(ctx);
+    }
+    return 3;
+}
+
+/* Pushes an enumerator of the object at idx as duk_enum(), or the error on failure. */
+duk_int_t duk_api_enum(duk_context *ctx, duk_idx_t idx, duk_uint_t flags) {
+    duk__api_enum_args args;
+    args.idx = duk_normalize_index(ctx, idx);
+    args.flags = flags;
+
+    return duk_safe_call(ctx, duk__api_enum, &args, 0, 1);
+}
+
+/* Pushes key, value and a found flag from the enumerator at enum_idx, so property getters may throw
+ * safely. On failure, the error is followed by two undefined values. */
+duk_int_t duk_api_next(duk_context *ctx, duk_idx_t enum_idx) {
+    duk__api_enum_args args;
+    args.idx = duk_normalize_index(ctx, enum_idx);
+    args.flags = 0;
+
+    return duk_safe_call(ctx, duk__api_next, &args, 0, 3);
+}
+
+
+/* Array elements and object entries. */
+
+static duk_ret_t duk__api_push_entries(duk_context *ctx, void *udata) {
+    duk_idx_t idx = *(duk_idx_t *) udata;
+    duk_uarridx_t i, len;
+
+    duk_push_array(ctx);
+    if (duk_is_array(ctx, idx)) {
+        len = (duk_uarridx_t) duk_get_length(ctx, idx);
+        for (i = 0; i < len; i++) {
+            duk_get_prop_index(ctx, idx, i);
+            duk_put_prop_index(ctx, -2, i);
+        }
+    } else {
+        duk_enum(ctx, idx, DUK_ENUM_OWN_PROPERTIES_ONLY);
+        for (i = 0; duk_next(ctx, -1, 1); i += 2) {
+            duk_put_prop_index(ctx, -4, i + 1);
+            duk_put_prop_index(ctx, -3, i);
+        }
+        duk_pop(ctx);
+    }
+    return 1;
+}
+
+/* Pushes the elements of the array at idx, or keys and values of own enumerable properties of
+ * the object at idx, setting *count to the number of values pushed. All of them are read in a
+ * single safe call, so that getters and proxy traps may throw safely; on failure the error is
+ * pushed instead.
+ */
+duk_int_t duk_api_push_entries(duk_context *ctx, duk_idx_t idx, duk_size_t *count) {
+    duk_idx_t arr_idx;
+    duk_uarridx_t i, len;
+    duk_int_t rc;
+
+    idx = duk_normalize_index(ctx, idx);
+    *count = 0;
+    rc = duk_safe_call(ctx, duk__api_push_entries, &idx, 0, 1);
+    if (rc != DUK_EXEC_SUCCESS) {
+        return rc;
+    }
+    arr_idx = duk_get_top_index(ctx);
+    len = (duk_uarridx_t) duk_get_length(ctx, arr_idx);
+    if (!duk_check_stack(ctx, (duk_idx_t) len)) {
+        duk_pop(ctx);
+        duk_push_string(ctx, "RangeError: value stack limit");
+        return DUK_EXEC_ERROR;
+    }
+    /* the array is dense and created above, so reading its elements cannot throw */
+    for (i = 0; i < len; i++) {
+        duk_get_prop_index(ctx, arr_idx, i);
+    }
+    duk_remove(ctx, arr_idx);
+    *count = len;
+    return DUK_EXEC_SUCCESS;
+}
+
+
+/* Bytecode. */
+
+static duk_ret_t duk__api_dump_function(duk_context *ctx, void *udata) {
+    DUK_UNREF(udata);
+    duk_dump_function(ctx);
+    return 1;
+}
+
+/* Pushes the bytecode dump of the function at idx as a buffer, or the error on failure. */
+duk_int_t duk_api_dump_function(duk_context *ctx, duk_idx_t idx) {
+    duk_dup(ctx, idx);
+    return duk_safe_call(ctx, duk__api_dump_function, NULL, 1, 1);
+}
+
+static duk_ret_t duk__api_load_function(duk_context *ctx, void *udata) {
+    DUK_UNREF(udata);
+    duk_load_function(ctx);
+    return 1;
+}
+
+/* Pushes the function loaded from the bytecode buffer at idx, or the error on failure. */
+duk_int_t duk_api_load_function(duk_context *ctx, duk_idx_t idx) {
+    duk_dup(ctx, idx);
+    return duk_safe_call(ctx, duk__api_load_function, NULL, 1, 1);
+}
@@ -2,7 +2,16 @@
 
 #include "duktape.h"
 
-typedef void (*duk_console_function) (void* udata, duk_uint_t fun, const char *msg, duk_size_t msg_len);
+typedef void (*duk_console_function) (void* udata, duk_uint_t fun, duk_context *ctx, const char *msg, duk_size_t msg_len);
+/* Receives the original console arguments at value stack indices [0, nargs) of ctx. */
+typedef void (*duk_console_args_function) (void* udata, duk_uint_t fun, duk_context *ctx, duk_idx_t nargs, duk_uint_t indent);
+/* Releases console udata when the console object is finalized. */
+typedef void (*duk_console_free_function) (void* udata);
+
+#define DUK_API_FORMAT_JSON 0
+#define DUK_API_FORMAT_JX   1
+#define DUK_API_FORMAT_JC   2
+#define DUK_API_FORMAT_CBOR 3
 
 extern unsigned int duk_api_version();
 extern const char* duk_api_git_commit();
@@ -11,4 +20,114 @@ extern const char* duk_api_git_branch();
 
 extern void* duk_api_get_heap_udata(duk_context* ctx);
 
-extern void duk_api_console_init(duk_context *ctx, duk_console_function console_cb);
+/* When console_udata is NULL, callbacks receive the heap udata. */
+extern void duk_api_console_init(duk_context *ctx, duk_console_function console_cb, duk_console_args_function console_args_cb,
+                                 void *console_udata, duk_console_free_function console_udata_free);
+
+extern duk_int_t duk_api_encode(duk_context *ctx, duk_idx_t idx, duk_uint_t format, duk_int_t indent);
+extern duk_int_t duk_api_decode(duk_context *ctx, duk_uint_t format);
+extern duk_int_t duk_api_cbor_encode(duk_context *ctx, duk_idx_t idx);
+extern duk_int_t duk_api_cbor_decode(duk_context *ctx);
+
+extern duk_int_t duk_api_push_symbol(duk_context *ctx);
+
+extern duk_int_t duk_api_get_error_stack(duk_context *ctx);
+
+/* Returns true when an error thrown now would be caught by a protected call, false when it would be fatal. */
+extern duk_bool_t duk_api_in_protected_call(duk_context *ctx);
+
+#define DUK_API_PROP_GET 0
+#define DUK_API_PROP_PUT 1
+#define DUK_API_PROP_DEL 2
+#define DUK_API_PROP_HAS 3
+#define DUK_API_PROP_LENGTH 4
+
+/* Runs duk_get_prop(), duk_put_prop(), duk_del_prop() or duk_has_prop() on the object at obj_idx,
+ * consuming the key and, for DUK_API_PROP_PUT, the value on stack top; DUK_API_PROP_LENGTH runs
+ * duk_get_length() and consumes nothing. Outside of a protected call
+ * the operation runs in a safe call, so that errors thrown by getters, setters and proxy traps are
+ * not fatal; on failure the error is left on the stack instead of the result.
+ */
+extern duk_int_t duk_api_prop_op(duk_context *ctx, duk_idx_t obj_idx, duk_uint_t op, duk_size_t *result);
+extern duk_int_t duk_api_def_prop(duk_context *ctx, duk_idx_t obj_idx, duk_uint_t flags, duk_idx_t nvalues);
+extern duk_int_t duk_api_get_prop_desc(duk_context *ctx, duk_idx_t obj_idx);
+extern duk_int_t duk_api_enum(duk_context *ctx, duk_idx_t idx, duk_uint_t flags);
+extern duk_int_t duk_api_next(duk_context *ctx, duk_idx_t enum_idx);
+extern duk_int_t duk_api_push_entries(duk_context *ctx, duk_idx_t idx, duk_size_t *count);
+extern duk_int_t duk_api_dump_function(duk_context *ctx, duk_idx_t idx);
+extern duk_int_t duk_api_load_function(duk_context *ctx, duk_idx_t idx);
+
+/* Object class numbers are below DUK_API_CLASS_COUNT. */
+#define DUK_API_CLASS_COUNT 32
+
+typedef struct {
+    duk_size_t count;
+    duk_size_t bytes;
+} duk_api_heap_item;
+
+typedef struct {
+    duk_api_heap_item classes[DUK_API_CLASS_COUNT];
+    duk_api_heap_item strings;
+    duk_api_heap_item buffers;
+    duk_size_t finalize_pending;
+    duk_size_t refzero_pending;
+    /* Allocations left until the next voluntary mark-and-sweep, -1 if disabled. */
+    duk_int_t gc_countdown;
+} duk_api_heap_info;
+
+/* Counts the heap objects, strings and buffers, without allocating. */
+extern void duk_api_heap_stats(duk_context *ctx, duk_api_heap_info *stats);
+
+#define DUK_API_NODE_OBJECT 0
+#define DUK_API_NODE_STRING 1
+#define DUK_API_NODE_BUFFER 2
+
+/* Edge names are property keys for properties, getters and setters, and labels for internal
+ * references. Elements and hidden references, e.g. value stack slots, have an index instead.
+ */
+#define DUK_API_EDGE_PROPERTY 0
+#define DUK_API_EDGE_GETTER 1
+#define DUK_API_EDGE_SETTER 2
+#define DUK_API_EDGE_ELEMENT 3
+#define DUK_API_EDGE_INTERNAL 4
+#define DUK_API_EDGE_HIDDEN 5
+
+typedef struct {
+    void *udata;
+    /* Called for every heap allocated value. The name is the data of a string or the name of a
+     * function, otherwise NULL.
+     */
+    void (*node)(void *udata, void *ptr, duk_uint_t type, duk_uint_t class_num, duk_size_t size,
+                 const char *name, duk_size_t name_len);
+    /* Called for the references of the last reported node, or of the GC roots before the first
+     * node, with from set to NULL.
+     */
+    void (*edge)(void *udata, void *from, void *to, duk_uint_t type, const char *name, duk_size_t name_len,
+                 duk_size_t index);
+} duk_api_heap_visitor;
+
+/* Reports all heap allocated values and their references, without allocating. */
+extern void duk_api_heap_walk(duk_context *ctx, const duk_api_heap_visitor *visitor);
+
+/* Called with the heap udata from the interrupt hook of all heaps; a non-zero result aborts execution. */
+typedef duk_bool_t (*duk_api_interrupt_function)(void *udata);
+
+extern void duk_api_set_interrupt_handler(duk_api_interrupt_function func);
+/* Makes the running thread call the interrupt hook before its next bytecode instruction. */
+extern void duk_api_request_interrupt(duk_context *ctx);
+
+typedef struct {
+    /* Function name and file name, NULL if missing. Strings are owned by the heap. */
+    const char *name;
+    duk_size_t name_len;
+    const char *filename;
+    duk_size_t filename_len;
+    /* Line being executed, 0 for native functions. */
+    duk_uint32_t line;
+} duk_api_frame;
+
+/* Fills frames with the call stack of the running thread, innermost first, without allocating.
+ * Returns the number of frames, at most max.
+ */
+extern duk_size_t duk_api_sample_callstack(duk_context *ctx, duk_api_frame *frames, duk_size_t max);
+
@@ -11453,7 +11453,7 @@ void duk_bi_json_stringify_helper(duk_hthread *thr,
 DUK_INTERNAL_DECL duk_ret_t duk_textdecoder_decode_utf8_nodejs(duk_hthread *thr);
 
 #if defined(DUK_USE_ES6_PROXY)
-DUK_INTERNAL_DECL void duk_proxy_ownkeys_postprocess(duk_hthread *thr, duk_hobject *h_proxy_target, duk_uint_t flags);
+DUK_INTERNAL_DECL void duk_proxy_ownkeys_postprocess(duk_hthread *thr, duk_hobject *h_proxy_target, duk_hobject *h_proxy_handler, duk_uint_t flags);
 #endif
 
 #endif /* DUK_BUILTIN_PROTOS_H_INCLUDED */
@@ -42089,7 +42089,7 @@ DUK_INTERNAL duk_ret_t duk_bi_object_constructor_keys_shared(duk_hthread *thr) {
 	DUK_ASSERT(magic >= 0 && magic < (duk_int_t) (sizeof(duk__object_keys_enum_flags) / sizeof(duk_small_uint_t)));
 	enum_flags = duk__object_keys_enum_flags[magic];
 
-	duk_proxy_ownkeys_postprocess(thr, h_proxy_target, enum_flags);
+	duk_proxy_ownkeys_postprocess(thr, h_proxy_target, h_proxy_handler, enum_flags);
 	return 1;
 
 skip_proxy:
@@ -42364,9 +42364,36 @@ DUK_INTERNAL duk_ret_t duk_bi_promise_then(duk_hthread *thr) {
  * array of valid result keys (strings or symbols).  TypeError for invalid
  * values.  Flags are shared with duk_enum().
  */
-DUK_INTERNAL void duk_proxy_ownkeys_postprocess(duk_hthread *thr, duk_hobject *h_proxy_target, duk_uint_t flags) {
-	duk_uarridx_t i, len, idx;
+/* kg-js: checks the enumerability of the key on stack top with the 'getOwnPropertyDescriptor'
+ * trap when the handler has one, otherwise from the target object descriptor.
+ */
+DUK_LOCAL duk_bool_t duk__proxy_key_enumerable(duk_hthread *thr, duk_hobject *h_proxy_target, duk_hobject *h_proxy_handler) {
 	duk_propdesc desc;
+	duk_bool_t res = 0;
+
+	/* [ ... propname ] */
+	duk_push_hobject(thr, h_proxy_handler);
+	if (!duk_get_prop_literal(thr, -1, "getOwnPropertyDescriptor")) {
+		duk_pop_2(thr);
+		return duk_hobject_get_own_propdesc(thr, h_proxy_target, duk_known_hstring(thr, -1), &desc, 0 /*flags*/) &&
+		       (desc.flags & DUK_PROPDESC_FLAG_ENUMERABLE) != 0;
+	}
+	/* [ ... propname handler trap ] */
+	duk_insert(thr, -2);
+	duk_push_hobject(thr, h_proxy_target);
+	duk_dup(thr, -4);
+	duk_call_method(thr, 2 /*nargs*/); /* -> [ ... propname trap_result ] */
+	if (duk_is_object(thr, -1)) {
+		(void) duk_get_prop_stridx_short(thr, -1, DUK_STRIDX_ENUMERABLE);
+		res = duk_to_boolean(thr, -1);
+		duk_pop(thr);
+	}
+	duk_pop(thr);
+	return res;
+}
+
+DUK_INTERNAL void duk_proxy_ownkeys_postprocess(duk_hthread *thr, duk_hobject *h_proxy_target, duk_hobject *h_proxy_handler, duk_uint_t flags) {
+	duk_uarridx_t i, len, idx;
 
 	DUK_CTX_ASSERT_VALID(thr);
 	DUK_ASSERT(h_proxy_target != NULL);
@@ -42387,17 +42414,11 @@ DUK_INTERNAL void duk_proxy_ownkeys_postprocess(duk_hthread *thr, duk_hobject *h
 		}
 
 		if (!(flags & DUK_ENUM_INCLUDE_NONENUMERABLE)) {
-			/* No support for 'getOwnPropertyDescriptor' trap yet,
-			 * so check enumerability always from target object
-			 * descriptor.
+			/* kg-js: consult the 'getOwnPropertyDescriptor'
+			 * trap, see duk__proxy_key_enumerable().
 			 */
-			if (duk_hobject_get_own_propdesc(thr, h_proxy_target, duk_known_hstring(thr, -1), &desc, 0 /*flags*/)) {
-				if ((desc.flags & DUK_PROPDESC_FLAG_ENUMERABLE) == 0) {
-					DUK_DDD(DUK_DDDPRINT("ignore non-enumerable property: %!T", duk_get_tval(thr, -1)));
-					goto skip_key;
-				}
-			} else {
-				DUK_DDD(DUK_DDDPRINT("ignore non-existent property: %!T", duk_get_tval(thr, -1)));
+			if (!duk__proxy_key_enumerable(thr, h_proxy_target, h_proxy_handler)) {
+				DUK_DDD(DUK_DDDPRINT("ignore non-enumerable or non-existent property: %!T", duk_get_tval(thr, -1)));
 				goto skip_key;
 			}
 		}
@@ -57279,7 +57300,7 @@ DUK_INTERNAL void duk_hobject_enumerator_create(duk_hthread *thr, duk_small_uint
 	h_trap_result = duk_require_hobject(thr, -1);
 	DUK_UNREF(h_trap_result);
 
-	duk_proxy_ownkeys_postprocess(thr, h_proxy_target, enum_flags);
+	duk_proxy_ownkeys_postprocess(thr, h_proxy_target, h_proxy_handler, enum_flags);
 	/* -> [ ... enum_target res trap_result keys_array ] */
 
 	/* Copy cleaned up trap result keys into the enumerator object. */
@@ -63132,6 +63153,35 @@ DUK_INTERNAL void duk_hobject_object_get_own_property_descriptor(duk_hthread *th
 
 	DUK_ASSERT_VALSTACK_SPACE(thr, DUK__VALSTACK_SPACE);
 
+#if defined(DUK_USE_ES6_PROXY)
+	/* kg-js: calls the 'getOwnPropertyDescriptor' trap of a proxy, like
+	 * duk__proxy_key_enumerable().  The trap result is not checked
+	 * against the target, except that it must be an object or undefined.
+	 */
+	{
+		duk_hobject *h_target;
+		duk_hobject *h_handler;
+
+		if (duk_hobject_proxy_check(obj, &h_target, &h_handler) && !DUK_HSTRING_HAS_HIDDEN(key)) {
+			duk_push_hobject(thr, h_handler);
+			if (duk_get_prop_literal(thr, -1, "getOwnPropertyDescriptor")) {
+				/* [ ... key handler trap ] */
+				duk_insert(thr, -2);
+				duk_push_hobject(thr, h_target);
+				duk_dup(thr, -4);
+				duk_call_method(thr, 2 /*nargs*/); /* -> [ ... key trap_result ] */
+				if (!duk_is_object(thr, -1) && !duk_is_undefined(thr, -1)) {
+					DUK_ERROR_TYPE(thr, DUK_STR_PROXY_REJECTED);
+					DUK_WO_NORETURN(return;);
+				}
+				duk_remove_m2(thr);
+				return;
+			}
+			duk_pop_2(thr);
+		}
+	}
+#endif
+
 	if (!duk_hobject_get_own_propdesc(thr, obj, key, &pd, DUK_GETDESC_FLAG_PUSH_VALUE)) {
 		duk_push_undefined(thr);
 		duk_remove_m2(thr);
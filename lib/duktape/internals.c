@@ -0,0 +1,461 @@
+/* Helpers that use private Duktape functions and structures: the protected call check, the
+ * internal JSON encoder and decoder, the built-in Symbol(), heap inspection, heap walk, execution
+ * interrupt and call stack sampling. Duktape is compiled as part of this file to reach them, so this
+ * is the only C file depending on Duktape internals; api.c uses the public API only. Review every
+ * helper below, and the class tables in src/inspect.rs and src/clone.rs, when updating Duktape.
+ */
+#include "duktape.c"
+#include "api.h"
+
+#include <string.h>
+
+#if (DUK_VERSION != 20700L)
+#error "internals.c is written against Duktape 2.7.0"
+#endif
+
+/* Class numbers reported by duk_api_heap_stats() and duk_api_heap_walk(), named by src/inspect.rs. */
+#if (DUK_HOBJECT_CLASS_MAX != 29) || (DUK_HOBJECT_CLASS_THREAD != 18) || (DUK_HOBJECT_CLASS_FLOAT64ARRAY != 29)
+#error "Duktape object class numbers changed"
+#endif
+
+
+/* Protected calls. */
+
+duk_bool_t duk_api_in_protected_call(duk_context *ctx) {
+    return ((duk_hthread *) ctx)->heap->lj.jmpbuf_ptr != NULL;
+}
+
+
+/* Encoding. */
+
+typedef struct {
+    duk_idx_t idx;
+    duk_uint_t format;
+    duk_int_t indent;
+} duk__api_codec_args;
+
+static duk_small_uint_t duk__api_json_flags(duk_uint_t format) {
+    switch (format) {
+    case DUK_API_FORMAT_JX:
+        return DUK_JSON_FLAG_EXT_CUSTOM | DUK_JSON_FLAG_ASCII_ONLY | DUK_JSON_FLAG_AVOID_KEY_QUOTES;
+    case DUK_API_FORMAT_JC:
+        return DUK_JSON_FLAG_EXT_COMPATIBLE | DUK_JSON_FLAG_ASCII_ONLY;
+    default:
+        return 0;
+    }
+}
+
+/* Uses the internal encoder and decoder, as Duktape.enc() and Duktape.dec() do, so that scripts
+ * replacing JSON or Duktape bindings cannot change the result. */
+static duk_ret_t duk__api_encode(duk_context *ctx, void *udata) {
+    duk__api_codec_args *args = (duk__api_codec_args *) udata;
+    duk_idx_t value_idx;
+    duk_idx_t space_idx = DUK_INVALID_INDEX;
+
+    duk_dup(ctx, args->idx);
+    value_idx = duk_get_top_index(ctx);
+    if (args->format == DUK_API_FORMAT_CBOR) {
+        duk_cbor_encode(ctx, value_idx, 0);
+        return 1;
+    }
+    if (args->indent > 0) {
+        duk_push_int(ctx, args->indent);
+        space_idx = duk_get_top_index(ctx);
+    }
+    duk_bi_json_stringify_helper(ctx, value_idx, DUK_INVALID_INDEX, space_idx, duk__api_json_flags(args->format));
+    return 1;
+}
+
+static duk_ret_t duk__api_decode(duk_context *ctx, void *udata) {
+    duk__api_codec_args *args = (duk__api_codec_args *) udata;
+
+    if (args->format == DUK_API_FORMAT_CBOR) {
+        duk_cbor_decode(ctx, -1, 0);
+    } else {
+        duk_bi_json_parse_helper(ctx, duk_get_top_index(ctx), DUK_INVALID_INDEX, duk__api_json_flags(args->format));
+    }
+    return 1;
+}
+
+/* Pushes the encoded form of the value at idx (string, or buffer for CBOR), or the error on failure. */
+duk_int_t duk_api_encode(duk_context *ctx, duk_idx_t idx, duk_uint_t format, duk_int_t indent) {
+    duk__api_codec_args args;
+    args.idx = duk_normalize_index(ctx, idx);
+    args.format = format;
+    args.indent = indent;
+
+    return duk_safe_call(ctx, duk__api_encode, &args, 0, 1);
+}
+
+duk_int_t duk_api_cbor_encode(duk_context *ctx, duk_idx_t idx) {
+    return duk_api_encode(ctx, idx, DUK_API_FORMAT_CBOR, 0);
+}
+
+/* Replaces the encoded input on stack top with the decoded value, or the error on failure. */
+duk_int_t duk_api_decode(duk_context *ctx, duk_uint_t format) {
+    duk__api_codec_args args;
+    args.idx = DUK_INVALID_INDEX;
+    args.format = format;
+    args.indent = 0;
+
+    return duk_safe_call(ctx, duk__api_decode, &args, 1, 1);
+}
+
+duk_int_t duk_api_cbor_decode(duk_context *ctx) {
+    return duk_api_decode(ctx, DUK_API_FORMAT_CBOR);
+}
+
+
+/* Symbols. */
+
+static duk_ret_t duk__api_push_symbol(duk_context *ctx, void *udata) {
+    DUK_UNREF(udata);
+    /* the built-in Symbol(), unaffected by scripts replacing the global binding */
+    duk_push_c_function(ctx, duk_bi_symbol_constructor_shared, 1);
+    duk_insert(ctx, -2);
+    duk_call(ctx, 1);
+    return 1;
+}
+
+/* Replaces the description on stack top with a new symbol created by Symbol(description),
+ * or the error on failure.
+ */
+duk_int_t duk_api_push_symbol(duk_context *ctx) {
+    return duk_safe_call(ctx, duk__api_push_symbol, NULL, 1, 1);
+}
+
+
+/* Heap inspection. Sizes are computed like in duk_inspect_value(). */
+
+static duk_size_t duk__api_object_size(duk_hobject *h) {
+    duk_size_t size;
+    if (DUK_HOBJECT_IS_ARRAY(h)) {
+        size = sizeof(duk_harray);
+    } else if (DUK_HOBJECT_IS_COMPFUNC(h)) {
+        size = sizeof(duk_hcompfunc);
+    } else if (DUK_HOBJECT_IS_NATFUNC(h)) {
+        size = sizeof(duk_hnatfunc);
+    } else if (DUK_HOBJECT_IS_THREAD(h)) {
+        size = sizeof(duk_hthread);
+#if defined(DUK_USE_BUFFEROBJECT_SUPPORT)
+    } else if (DUK_HOBJECT_IS_BUFOBJ(h)) {
+        size = sizeof(duk_hbufobj);
+#endif
+    } else {
+        size = sizeof(duk_hobject);
+    }
+    return size + DUK_HOBJECT_P_ALLOC_SIZE(h);
+}
+
+static duk_size_t duk__api_buffer_size(duk_hbuffer *h) {
+    if (DUK_HBUFFER_HAS_DYNAMIC(h)) {
+        if (DUK_HBUFFER_HAS_EXTERNAL(h)) {
+            return sizeof(duk_hbuffer_external);
+        }
+        return sizeof(duk_hbuffer_dynamic) + DUK_HBUFFER_GET_SIZE(h);
+    }
+    return sizeof(duk_hbuffer_fixed) + DUK_HBUFFER_GET_SIZE(h);
+}
+
+static duk_size_t duk__api_count_list(duk_heap *heap, duk_heaphdr *h, duk_api_heap_info *stats) {
+    duk_size_t n = 0;
+    DUK_UNREF(heap); /* used only with pointer compression */
+    for (; h != NULL; h = DUK_HEAPHDR_GET_NEXT(heap, h), n++) {
+        if (DUK_HEAPHDR_GET_TYPE(h) == DUK_HTYPE_OBJECT) {
+            duk_hobject *obj = (duk_hobject *) h;
+            duk_api_heap_item *c = &stats->classes[DUK_HOBJECT_GET_CLASS_NUMBER(obj) % DUK_API_CLASS_COUNT];
+            c->count++;
+            c->bytes += duk__api_object_size(obj);
+        } else if (DUK_HEAPHDR_GET_TYPE(h) == DUK_HTYPE_BUFFER) {
+            stats->buffers.count++;
+            stats->buffers.bytes += duk__api_buffer_size((duk_hbuffer *) h);
+        }
+    }
+    return n;
+}
+
+void duk_api_heap_stats(duk_context *ctx, duk_api_heap_info *stats) {
+    duk_heap *heap = ((duk_hthread *) ctx)->heap;
+    duk_uint32_t i;
+    duk_hstring *h;
+
+    memset(stats, 0, sizeof(*stats));
+    duk__api_count_list(heap, heap->heap_allocated, stats);
+#if defined(DUK_USE_REFERENCE_COUNTING)
+    stats->refzero_pending = duk__api_count_list(heap, heap->refzero_list, stats);
+#endif
+#if defined(DUK_USE_FINALIZER_SUPPORT)
+    stats->finalize_pending = duk__api_count_list(heap, heap->finalize_list, stats);
+#endif
+
+#if defined(DUK_USE_STRTAB_PTRCOMP)
+    if (heap->strtable16 != NULL) {
+#else
+    if (heap->strtable != NULL) {
+#endif
+        for (i = 0; i < heap->st_size; i++) {
+#if defined(DUK_USE_STRTAB_PTRCOMP)
+            h = DUK_USE_HEAPPTR_DEC16(heap->heap_udata, heap->strtable16[i]);
+#else
+            h = heap->strtable[i];
+#endif
+            for (; h != NULL; h = h->hdr.h_next) {
+                stats->strings.count++;
+                stats->strings.bytes += sizeof(duk_hstring) + DUK_HSTRING_GET_BYTELEN(h) + 1;
+            }
+        }
+    }
+
+#if defined(DUK_USE_VOLUNTARY_GC)
+    stats->gc_countdown = heap->ms_trigger_counter;
+#else
+    stats->gc_countdown = -1;
+#endif
+}
+
+/* Heap walk. References are followed like in mark-and-sweep marking; the hash part of property
+ * tables is not a reference. Nothing is allocated or modified, so the heap may be paused.
+ */
+
+static void duk__api_edge(const duk_api_heap_visitor *v, void *from, duk_heaphdr *to, duk_uint_t type,
+                          const duk_uint8_t *name, duk_size_t name_len, duk_size_t index) {
+    if (to != NULL) {
+        v->edge(v->udata, from, to, type, (const char *) name, name_len, index);
+    }
+}
+
+static void duk__api_edge_tval(const duk_api_heap_visitor *v, void *from, duk_tval *tv, duk_uint_t type,
+                               const duk_uint8_t *name, duk_size_t name_len, duk_size_t index) {
+    if (DUK_TVAL_IS_HEAP_ALLOCATED(tv)) {
+        duk__api_edge(v, from, DUK_TVAL_GET_HEAPHDR(tv), type, name, name_len, index);
+    }
+}
+
+static void duk__api_edge_label(const duk_api_heap_visitor *v, void *from, duk_heaphdr *to, const char *label) {
+    duk__api_edge(v, from, to, DUK_API_EDGE_INTERNAL, (const duk_uint8_t *) label, strlen(label), 0);
+}
+
+static duk_hstring *duk__api_function_name(duk_heap *heap, duk_hobject *h) {
+    duk_uint_fast32_t i;
+    if (!DUK_HOBJECT_IS_CALLABLE(h)) {
+        return NULL;
+    }
+    for (i = 0; i < (duk_uint_fast32_t) DUK_HOBJECT_GET_ENEXT(h); i++) {
+        duk_tval *tv;
+        if (DUK_HOBJECT_E_GET_KEY(heap, h, i) != DUK_HEAP_STRING_NAME(heap) || DUK_HOBJECT_E_SLOT_IS_ACCESSOR(heap, h, i)) {
+            continue;
+        }
+        tv = &DUK_HOBJECT_E_GET_VALUE_PTR(heap, h, i)->v;
+        return DUK_TVAL_IS_STRING(tv) ? DUK_TVAL_GET_STRING(tv) : NULL;
+    }
+    return NULL;
+}
+
+static void duk__api_walk_object(duk_heap *heap, duk_hobject *h, const duk_api_heap_visitor *v) {
+    duk_uint_fast32_t i;
+    duk_hstring *name = duk__api_function_name(heap, h);
+
+    v->node(v->udata, h, DUK_API_NODE_OBJECT, DUK_HOBJECT_GET_CLASS_NUMBER(h), duk__api_object_size(h),
+            name ? (const char *) DUK_HSTRING_GET_DATA(name) : NULL, name ? DUK_HSTRING_GET_BYTELEN(name) : 0);
+
+    for (i = 0; i < (duk_uint_fast32_t) DUK_HOBJECT_GET_ENEXT(h); i++) {
+        duk_hstring *key = DUK_HOBJECT_E_GET_KEY(heap, h, i);
+        const duk_uint8_t *data;
+        duk_size_t len;
+        if (key == NULL) {
+            continue;
+        }
+        data = DUK_HSTRING_GET_DATA(key);
+        len = DUK_HSTRING_GET_BYTELEN(key);
+        if (DUK_HOBJECT_E_SLOT_IS_ACCESSOR(heap, h, i)) {
+            duk_propvalue *pv = DUK_HOBJECT_E_GET_VALUE_PTR(heap, h, i);
+            duk__api_edge(v, h, (duk_heaphdr *) pv->a.get, DUK_API_EDGE_GETTER, data, len, 0);
+            duk__api_edge(v, h, (duk_heaphdr *) pv->a.set, DUK_API_EDGE_SETTER, data, len, 0);
+        } else {
+            duk__api_edge_tval(v, h, &DUK_HOBJECT_E_GET_VALUE_PTR(heap, h, i)->v, DUK_API_EDGE_PROPERTY, data, len, 0);
+        }
+    }
+    for (i = 0; i < (duk_uint_fast32_t) DUK_HOBJECT_GET_ASIZE(h); i++) {
+        duk__api_edge_tval(v, h, DUK_HOBJECT_A_GET_VALUE_PTR(heap, h, i), DUK_API_EDGE_ELEMENT, NULL, 0, i);
+    }
+    duk__api_edge_label(v, h, (duk_heaphdr *) DUK_HOBJECT_GET_PROTOTYPE(heap, h), "__proto__");
+
+    if (DUK_HOBJECT_HAS_FASTREFS(h)) {
+        return;
+    }
+    if (DUK_HOBJECT_IS_COMPFUNC(h)) {
+        duk_hcompfunc *f = (duk_hcompfunc *) h;
+        duk__api_edge_label(v, h, (duk_heaphdr *) DUK_HCOMPFUNC_GET_DATA(heap, f), "bytecode");
+        duk__api_edge_label(v, h, (duk_heaphdr *) DUK_HCOMPFUNC_GET_LEXENV(heap, f), "lexical scope");
+        duk__api_edge_label(v, h, (duk_heaphdr *) DUK_HCOMPFUNC_GET_VARENV(heap, f), "variable scope");
+        if (DUK_HCOMPFUNC_GET_DATA(heap, f) != NULL) {
+            duk_tval *tv = DUK_HCOMPFUNC_GET_CONSTS_BASE(heap, f);
+            duk_tval *tv_end = DUK_HCOMPFUNC_GET_CONSTS_END(heap, f);
+            duk_hobject **fn = DUK_HCOMPFUNC_GET_FUNCS_BASE(heap, f);
+            duk_hobject **fn_end = DUK_HCOMPFUNC_GET_FUNCS_END(heap, f);
+            for (i = 0; tv < tv_end; tv++, i++) {
+                duk__api_edge_tval(v, h, tv, DUK_API_EDGE_HIDDEN, NULL, 0, i);
+            }
+            for (; fn < fn_end; fn++) {
+                duk__api_edge_label(v, h, (duk_heaphdr *) *fn, "inner function");
+            }
+        }
+    } else if (DUK_HOBJECT_IS_DECENV(h)) {
+        duk_hdecenv *e = (duk_hdecenv *) h;
+        duk__api_edge_label(v, h, (duk_heaphdr *) e->thread, "thread");
+        duk__api_edge_label(v, h, (duk_heaphdr *) e->varmap, "variables");
+    } else if (DUK_HOBJECT_IS_OBJENV(h)) {
+        duk__api_edge_label(v, h, (duk_heaphdr *) ((duk_hobjenv *) h)->target, "target");
+#if defined(DUK_USE_BUFFEROBJECT_SUPPORT)
+    } else if (DUK_HOBJECT_IS_BUFOBJ(h)) {
+        duk_hbufobj *b = (duk_hbufobj *) h;
+        duk__api_edge_label(v, h, (duk_heaphdr *) b->buf, "buffer");
+        duk__api_edge_label(v, h, (duk_heaphdr *) b->buf_prop, "buffer properties");
+#endif
+    } else if (DUK_HOBJECT_IS_BOUNDFUNC(h)) {
+        duk_hboundfunc *f = (duk_hboundfunc *) (void *) h;
+        static const char target[] = "bound target", this_binding[] = "bound this";
+        duk__api_edge_tval(v, h, &f->target, DUK_API_EDGE_INTERNAL, (const duk_uint8_t *) target, sizeof(target) - 1, 0);
+        duk__api_edge_tval(v, h, &f->this_binding, DUK_API_EDGE_INTERNAL, (const duk_uint8_t *) this_binding,
+                           sizeof(this_binding) - 1, 0);
+        for (i = 0; i < (duk_uint_fast32_t) f->nargs; i++) {
+            duk__api_edge_tval(v, h, &f->args[i], DUK_API_EDGE_HIDDEN, NULL, 0, i);
+        }
+#if defined(DUK_USE_ES6_PROXY)
+    } else if (DUK_HOBJECT_IS_PROXY(h)) {
+        duk_hproxy *p = (duk_hproxy *) h;
+        duk__api_edge_label(v, h, (duk_heaphdr *) p->target, "target");
+        duk__api_edge_label(v, h, (duk_heaphdr *) p->handler, "handler");
+#endif
+    } else if (DUK_HOBJECT_IS_THREAD(h)) {
+        duk_hthread *t = (duk_hthread *) h;
+        duk_activation *act;
+        duk_tval *tv;
+        for (i = 0, tv = t->valstack; tv < t->valstack_top; tv++, i++) {
+            duk__api_edge_tval(v, h, tv, DUK_API_EDGE_HIDDEN, NULL, 0, i);
+        }
+        for (act = t->callstack_curr; act != NULL; act = act->parent) {
+            duk__api_edge_label(v, h, (duk_heaphdr *) DUK_ACT_GET_FUNC(act), "function");
+            duk__api_edge_label(v, h, (duk_heaphdr *) act->var_env, "variable scope");
+            duk__api_edge_label(v, h, (duk_heaphdr *) act->lex_env, "lexical scope");
+        }
+        duk__api_edge_label(v, h, (duk_heaphdr *) t->resumer, "resumer");
+        for (i = 0; i < DUK_NUM_BUILTINS; i++) {
+            duk__api_edge_label(v, h, (duk_heaphdr *) t->builtins[i], "builtin");
+        }
+    }
+}
+
+static void duk__api_walk_list(duk_heap *heap, duk_heaphdr *h, const duk_api_heap_visitor *v) {
+    for (; h != NULL; h = DUK_HEAPHDR_GET_NEXT(heap, h)) {
+        if (DUK_HEAPHDR_GET_TYPE(h) == DUK_HTYPE_OBJECT) {
+            duk__api_walk_object(heap, (duk_hobject *) h, v);
+        } else if (DUK_HEAPHDR_GET_TYPE(h) == DUK_HTYPE_BUFFER) {
+            v->node(v->udata, h, DUK_API_NODE_BUFFER, 0, duk__api_buffer_size((duk_hbuffer *) h), NULL, 0);
+        }
+    }
+}
+
+void duk_api_heap_walk(duk_context *ctx, const duk_api_heap_visitor *v) {
+    duk_heap *heap = ((duk_hthread *) ctx)->heap;
+    duk_uint32_t i;
+    duk_hstring *h;
+
+    duk__api_edge_label(v, NULL, (duk_heaphdr *) heap->heap_thread, "heap thread");
+    duk__api_edge_label(v, NULL, (duk_heaphdr *) heap->heap_object, "heap object");
+    duk__api_edge_label(v, NULL, (duk_heaphdr *) heap->curr_thread, "current thread");
+    duk__api_edge_tval(v, NULL, &heap->lj.value1, DUK_API_EDGE_HIDDEN, NULL, 0, 0);
+    duk__api_edge_tval(v, NULL, &heap->lj.value2, DUK_API_EDGE_HIDDEN, NULL, 0, 1);
+
+    duk__api_walk_list(heap, heap->heap_allocated, v);
+#if defined(DUK_USE_REFERENCE_COUNTING)
+    duk__api_walk_list(heap, heap->refzero_list, v);
+#endif
+#if defined(DUK_USE_FINALIZER_SUPPORT)
+    duk__api_walk_list(heap, heap->finalize_list, v);
+#endif
+
+#if defined(DUK_USE_STRTAB_PTRCOMP)
+    if (heap->strtable16 != NULL) {
+#else
+    if (heap->strtable != NULL) {
+#endif
+        for (i = 0; i < heap->st_size; i++) {
+#if defined(DUK_USE_STRTAB_PTRCOMP)
+            h = DUK_USE_HEAPPTR_DEC16(heap->heap_udata, heap->strtable16[i]);
+#else
+            h = heap->strtable[i];
+#endif
+            for (; h != NULL; h = h->hdr.h_next) {
+                v->node(v->udata, h, DUK_API_NODE_STRING, 0, sizeof(duk_hstring) + DUK_HSTRING_GET_BYTELEN(h) + 1,
+                        (const char *) DUK_HSTRING_GET_DATA(h), DUK_HSTRING_GET_BYTELEN(h));
+            }
+        }
+    }
+}
+
+
+/* Execution interrupt. Duktape calls the hook every DUK_HTHREAD_INTCTR_DEFAULT bytecode
+ * instructions; a non-zero result throws a RangeError.
+ */
+
+static duk_api_interrupt_function duk__api_interrupt_func = NULL;
+
+void duk_api_set_interrupt_handler(duk_api_interrupt_function func) {
+    duk__api_interrupt_func = func;
+}
+
+duk_bool_t duk_api_exec_interrupt(void *udata) {
+    return duk__api_interrupt_func != NULL && duk__api_interrupt_func(udata);
+}
+
+void duk_api_request_interrupt(duk_context *ctx) {
+    duk_hthread *thr = ((duk_hthread *) ctx)->heap->curr_thread;
+
+    /* without a running thread, the next entry calls the hook before its first instruction */
+    if (thr != NULL) {
+        thr->interrupt_init -= thr->interrupt_counter;
+        thr->interrupt_counter = 0;
+    }
+}
+
+static duk_hstring *duk__api_string_prop(duk_heap *heap, duk_hobject *h, duk_small_uint_t stridx) {
+    duk_tval *tv = duk_hobject_find_entry_tval_ptr_stridx(heap, h, stridx);
+    return tv != NULL && DUK_TVAL_IS_STRING(tv) ? DUK_TVAL_GET_STRING(tv) : NULL;
+}
+
+duk_size_t duk_api_sample_callstack(duk_context *ctx, duk_api_frame *frames, duk_size_t max) {
+    duk_heap *heap = ((duk_hthread *) ctx)->heap;
+    duk_hthread *thr = heap->curr_thread;
+    duk_activation *act;
+    duk_size_t n = 0;
+
+    for (act = thr != NULL ? thr->callstack_curr : NULL; act != NULL && n < max; act = act->parent, n++) {
+        duk_hobject *func = DUK_ACT_GET_FUNC(act);
+        duk_api_frame *frame = &frames[n];
+        duk_hstring *name = NULL;
+        duk_hstring *filename = NULL;
+
+        frame->line = 0;
+        if (func != NULL) {
+            name = duk__api_string_prop(heap, func, DUK_STRIDX_NAME);
+            if (DUK_HOBJECT_IS_COMPFUNC(func)) {
+                filename = duk__api_string_prop(heap, func, DUK_STRIDX_FILE_NAME);
+#if defined(DUK_USE_PC2LINE)
+                {
+                    duk_tval *tv = duk_hobject_find_entry_tval_ptr_stridx(heap, func, DUK_STRIDX_INT_PC2LINE);
+                    if (tv != NULL && DUK_TVAL_IS_BUFFER(tv)) {
+                        frame->line = (duk_uint32_t) duk__hobject_pc2line_query_raw(
+                            thr, (duk_hbuffer_fixed *) (void *) DUK_TVAL_GET_BUFFER(tv), duk_hthread_get_act_prev_pc(thr, act));
+                    }
+                }
+#endif
+            }
+        }
+        frame->name = name ? (const char *) DUK_HSTRING_GET_DATA(name) : NULL;
+        frame->name_len = name ? DUK_HSTRING_GET_BYTELEN(name) : 0;
+        frame->filename = filename ? (const char *) DUK_HSTRING_GET_DATA(filename) : NULL;
+        frame->filename_len = filename ? DUK_HSTRING_GET_BYTELEN(filename) : 0;
+    }
+    return n;
+}
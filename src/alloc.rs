@@ -1,6 +1,10 @@
 use std::alloc::Layout;
 use std::mem::size_of;
 
+/// Allocates `size` bytes, storing the allocation size in a header word before the returned pointer.
+///
+/// # Safety
+/// The returned pointer must be released with [`free`] or resized with [`realloc`] from this module.
 pub unsafe fn alloc(size: usize) -> *mut u8 {
     let size = size + size_of::<usize>();
     let layout = Layout::from_size_align_unchecked(size, size_of::<usize>());
@@ -9,15 +13,19 @@ pub unsafe fn alloc(size: usize) -> *mut u8 {
         std::alloc::handle_alloc_error(layout);
     }
     *(ptr as *mut usize) = size;
-    ptr.offset(size_of::<usize>() as isize)
+    ptr.add(size_of::<usize>())
 }
 
+/// Resizes an allocation made by [`alloc`].
+///
+/// # Safety
+/// `ptr` must be null or a pointer previously returned by [`alloc`] or [`realloc`] and not yet freed.
 pub unsafe fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
     if ptr.is_null() {
         alloc(size)
     } else {
         let size = size + size_of::<usize>();
-        let ptr = ptr.offset(-(size_of::<usize>() as isize));
+        let ptr = ptr.sub(size_of::<usize>());
         let old_size = *(ptr as *mut usize);
         let layout = Layout::from_size_align_unchecked(old_size, size_of::<usize>());
         let ptr = std::alloc::realloc(ptr, layout, size);
@@ -25,15 +33,27 @@ pub unsafe fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
             std::alloc::handle_alloc_error(layout);
         }
         *(ptr as *mut usize) = size;
-        ptr.offset(size_of::<usize>() as isize)
+        ptr.add(size_of::<usize>())
     }
 }
 
+/// Returns the size requested for an allocation made by [`alloc`] or [`realloc`].
+///
+/// # Safety
+/// `ptr` must be a pointer previously returned by [`alloc`] or [`realloc`] and not yet freed.
+pub unsafe fn size(ptr: *mut u8) -> usize {
+    *(ptr.sub(size_of::<usize>()) as *mut usize) - size_of::<usize>()
+}
+
+/// Releases an allocation made by [`alloc`].
+///
+/// # Safety
+/// `ptr` must be null or a pointer previously returned by [`alloc`] or [`realloc`] and not yet freed.
 pub unsafe fn free(ptr: *mut u8) {
     if ptr.is_null() {
         return;
     }
-    let ptr = ptr.offset(-(size_of::<usize>() as isize));
+    let ptr = ptr.sub(size_of::<usize>());
     let size = *(ptr as *mut usize);
     let layout = Layout::from_size_align_unchecked(size, size_of::<usize>());
     std::alloc::dealloc(ptr, layout);
@@ -0,0 +1,176 @@
+//! `kg-js` command line tool, see [`kg_js::repl`], [`kg_js::runner`] and [`kg_js::DukContext::check`].
+
+use std::time::Duration;
+use kg_js::repl::{Repl, StdioInterop};
+use kg_js::runner::{EnvFilter, Runner, EXIT_FATAL, EXIT_IO_ERROR, EXIT_SUCCESS, EXIT_USAGE};
+use kg_js::{diagnostics_to_json, CheckOptions, JsEngine};
+
+const USAGE: &str = "\
+usage: kg-js                 start the interactive REPL
+       kg-js run [options] <script> [--] [args...]
+                             run a script, passing args in process.argv
+       kg-js check [options] <file>...
+                             compile files without running them and report problems;
+                             only the first syntax error of a file is reported, by line
+
+run options:
+  --env <name>               expose only the named environment variables in process.env,
+                             a trailing * matches a prefix; may be repeated
+  --no-env                   expose no environment variables
+  --source-map <file>        report stack traces with positions from the source map <file>
+  --profile <file>           sample the call stack, write folded stacks for flamegraph tools
+                             to <file> and print the top functions
+
+check options:
+  --json                     print diagnostics as JSON
+  --strict                   compile as strict mode code
+  --globals                  report references to undeclared globals
+  --allow <name>             allow the global <name>, implies --globals; may be repeated";
+
+/// Sampling interval and number of reported functions of `kg-js run --profile`.
+const PROFILE_INTERVAL: Duration = Duration::from_millis(1);
+const PROFILE_TOP: usize = 20;
+
+/// Exit code of `kg-js check` when problems were found.
+const EXIT_CHECK_FAILED: i32 = 1;
+
+fn engine() -> JsEngine {
+    match JsEngine::with_interop(StdioInterop) {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("kg-js: {err}");
+            std::process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("kg-js: {msg}\n{USAGE}");
+    std::process::exit(EXIT_USAGE);
+}
+
+fn run(args: &[String]) -> i32 {
+    let mut env = EnvFilter::All;
+    let mut source_map = None;
+    let mut profile = None;
+    let mut args = args.iter();
+    let script = loop {
+        match args.next().map(String::as_str) {
+            Some("--env") => match (args.next(), &mut env) {
+                (Some(name), EnvFilter::Only(names)) => names.push(name.clone()),
+                (Some(name), _) => env = EnvFilter::Only(vec![name.clone()]),
+                (None, _) => usage_error("--env requires a variable name"),
+            },
+            Some("--no-env") => env = EnvFilter::None,
+            Some("--source-map") => match args.next() {
+                Some(path) => source_map = Some(path),
+                None => usage_error("--source-map requires a file"),
+            },
+            Some("--profile") => match args.next() {
+                Some(path) => profile = Some(path),
+                None => usage_error("--profile requires a file"),
+            },
+            Some(opt) if opt.starts_with("--") => usage_error(&format!("unknown option {opt}")),
+            Some(script) => break script,
+            None => usage_error("missing script"),
+        }
+    };
+    let mut script_args = args.as_slice();
+    if script_args.first().map(String::as_str) == Some("--") {
+        script_args = &script_args[1..];
+    }
+    let mut runner = Runner::new()
+        .args(script_args.iter().cloned())
+        .env_filter(env);
+    let engine = engine();
+    if let Some(path) = source_map {
+        let map = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) => {
+                eprintln!("kg-js: cannot read {path}: {err}");
+                return EXIT_IO_ERROR;
+            }
+        };
+        match engine.parse_source_map(&map) {
+            Ok(map) => runner = runner.source_map(map),
+            Err(err) => {
+                eprintln!("kg-js: {path}: {err}");
+                return EXIT_USAGE;
+            }
+        }
+    }
+    engine.init_console();
+    if profile.is_some() {
+        engine.start_profiler(PROFILE_INTERVAL);
+    }
+    let exit_code = runner.run_file(&engine, script);
+    if let (Some(path), Some(profile)) = (profile, engine.stop_profiler()) {
+        if let Err(err) = std::fs::write(path, profile.folded()) {
+            eprintln!("kg-js: cannot write {path}: {err}");
+            return EXIT_IO_ERROR;
+        }
+        let _ = profile.write_report(std::io::stderr(), PROFILE_TOP);
+    }
+    exit_code
+}
+
+fn check(args: &[String]) -> i32 {
+    let mut options = CheckOptions::new();
+    let mut json = false;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--strict" => options = options.strict(true),
+            "--globals" => options = options.allowed_globals(std::iter::empty::<String>()),
+            "--allow" => match args.next() {
+                Some(name) => options = options.allowed_globals([name.as_str()]),
+                None => usage_error("--allow requires a global name"),
+            },
+            opt if opt.starts_with("--") => usage_error(&format!("unknown option {opt}")),
+            file => files.push(file),
+        }
+    }
+    if files.is_empty() {
+        usage_error("missing file");
+    }
+    let engine = engine();
+    engine.init_console();
+    let diagnostics = match engine.check_files(&files, &options) {
+        Ok(diagnostics) => diagnostics,
+        Err(err) => {
+            eprintln!("kg-js: {err}");
+            return EXIT_FATAL;
+        }
+    };
+    if json {
+        println!("{}", diagnostics_to_json(&diagnostics));
+    } else {
+        for diagnostic in diagnostics.iter() {
+            println!("{diagnostic}");
+        }
+    }
+    if diagnostics.is_empty() { EXIT_SUCCESS } else { EXIT_CHECK_FAILED }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let status = match args.first().map(String::as_str) {
+        None => match Repl::new(engine()).run() {
+            Ok(()) => EXIT_SUCCESS,
+            Err(err) => {
+                eprintln!("kg-js: {err}");
+                EXIT_FATAL
+            }
+        },
+        Some("run") => run(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            EXIT_SUCCESS
+        }
+        Some(cmd) => usage_error(&format!("unknown command {cmd}")),
+    };
+    std::process::exit(status);
+}
@@ -1,8 +1,14 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::null_mut;
+use std::sync::Once;
 use bitflags::bitflags;
 use crate::ctx::DukContext;
 use super::*;
 
 bitflags! {
+    /// Flags for compiling code, see [`CompileOptions`](crate::CompileOptions).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct DukCompileFlags: u32 {
         const DUK_COMPILE_EVAL                  = (1 << 3);    /* compile eval code (instead of global code) */
         const DUK_COMPILE_FUNCTION              = (1 << 4);    /* compile function code (instead of global code) */
@@ -33,6 +39,8 @@ bitflags! {
 }
 
 bitflags! {
+    /// Flags selecting properties enumerated by [`DukContext::entries_with`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct DukEnumFlags: u32 {
         const DUK_ENUM_INCLUDE_NONENUMERABLE    = (1 << 0);    /* enumerate non-numerable properties in addition to enumerable */
         const DUK_ENUM_INCLUDE_HIDDEN           = (1 << 1);    /* enumerate hidden symbols too (in Duktape 1.x called internal properties) */
@@ -55,6 +63,8 @@ bitflags! {
 
 
 bitflags! {
+    /// Flags for [`DukContext::gc_with`](crate::DukContext::gc_with).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct DukGcFlags: u32 {
         const NONE                        = 0;           /* No flags */
         const DUK_GC_COMPACT              = (1 << 0);    /* compact heap objects */
@@ -86,7 +96,7 @@ pub enum DukType {
 impl From<i32> for DukType {
     fn from(e: i32) -> Self {
         if e >= DukType::DUK_TYPE_NONE as i32 && e <= DukType::DUK_TYPE_LIGHTFUNC as i32 {
-            unsafe { std::mem::transmute(e) }
+            unsafe { std::mem::transmute::<i32, DukType>(e) }
         } else {
             panic!("incorrect DukType value: {}", e); //FIXME (jc)
         }
@@ -110,7 +120,75 @@ pub type duk_free_function = extern "C" fn (udata: *mut c_void, ptr: *mut c_void
 pub type duk_c_function = extern "C" fn(ctx: *mut duk_context) -> i32;
 
 #[allow(non_camel_case_types)]
-pub type duk_console_function = extern "C" fn(udata: *mut c_void, fun: u32, msg: *const c_char, msg_len: usize);
+pub type duk_console_function = extern "C" fn(udata: *mut c_void, fun: u32, ctx: *mut duk_context, msg: *const c_char, msg_len: usize);
+
+#[allow(non_camel_case_types)]
+pub type duk_console_args_function = extern "C" fn(udata: *mut c_void, fun: u32, ctx: *mut duk_context, nargs: i32, indent: u32);
+
+#[allow(non_camel_case_types)]
+pub type duk_console_free_function = extern "C" fn(udata: *mut c_void);
+
+pub const DUK_API_CLASS_COUNT: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct duk_api_heap_item {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct duk_api_heap_info {
+    pub classes: [duk_api_heap_item; DUK_API_CLASS_COUNT],
+    pub strings: duk_api_heap_item,
+    pub buffers: duk_api_heap_item,
+    pub finalize_pending: usize,
+    pub refzero_pending: usize,
+    pub gc_countdown: i32,
+}
+
+pub const DUK_API_NODE_OBJECT: u32 = 0;
+pub const DUK_API_NODE_STRING: u32 = 1;
+
+pub const DUK_API_EDGE_PROPERTY: u32 = 0;
+pub const DUK_API_EDGE_GETTER: u32 = 1;
+pub const DUK_API_EDGE_SETTER: u32 = 2;
+pub const DUK_API_EDGE_ELEMENT: u32 = 3;
+pub const DUK_API_EDGE_INTERNAL: u32 = 4;
+pub const DUK_API_EDGE_HIDDEN: u32 = 5;
+
+#[allow(non_camel_case_types)]
+pub type duk_api_heap_node_function = extern "C" fn(udata: *mut c_void, ptr: *mut c_void, node_type: u32, class: u32,
+                                                    size: usize, name: *const c_char, name_len: usize);
+
+#[allow(non_camel_case_types)]
+pub type duk_api_heap_edge_function = extern "C" fn(udata: *mut c_void, from: *mut c_void, to: *mut c_void, edge_type: u32,
+                                                    name: *const c_char, name_len: usize, index: usize);
+
+#[allow(non_camel_case_types)]
+pub type duk_api_interrupt_function = extern "C" fn(udata: *mut c_void) -> u32;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct duk_api_frame {
+    pub name: *const c_char,
+    pub name_len: usize,
+    pub filename: *const c_char,
+    pub filename_len: usize,
+    pub line: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct duk_api_heap_visitor {
+    pub udata: *mut c_void,
+    pub node: duk_api_heap_node_function,
+    pub edge: duk_api_heap_edge_function,
+}
 
 #[allow(dead_code)]
 extern "C" {
@@ -119,7 +197,31 @@ extern "C" {
     pub fn duk_api_git_commit() -> *const c_char;
     pub fn duk_api_git_branch() -> *const c_char;
     pub fn duk_api_get_heap_udata(ctx: *mut duk_context) -> *mut c_void;
-    pub fn duk_api_console_init(ctx: *mut duk_context, console_func: Option<duk_console_function>);
+    pub fn duk_api_console_init(ctx: *mut duk_context,
+                                console_func: Option<duk_console_function>,
+                                console_args_func: Option<duk_console_args_function>,
+                                console_udata: *mut c_void,
+                                console_udata_free: Option<duk_console_free_function>);
+    pub fn duk_api_encode(ctx: *mut duk_context, index: i32, format: u32, indent: i32) -> i32;
+    pub fn duk_api_decode(ctx: *mut duk_context, format: u32) -> i32;
+    pub fn duk_api_cbor_encode(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_api_cbor_decode(ctx: *mut duk_context) -> i32;
+    pub fn duk_api_push_symbol(ctx: *mut duk_context) -> i32;
+    pub fn duk_api_get_error_stack(ctx: *mut duk_context) -> i32;
+    pub fn duk_api_prop_op(ctx: *mut duk_context, obj_index: i32, op: u32, result: *mut usize) -> i32;
+    pub fn duk_api_in_protected_call(ctx: *mut duk_context) -> u32;
+    pub fn duk_api_def_prop(ctx: *mut duk_context, obj_index: i32, flags: u32, nvalues: i32) -> i32;
+    pub fn duk_api_get_prop_desc(ctx: *mut duk_context, obj_index: i32) -> i32;
+    pub fn duk_api_enum(ctx: *mut duk_context, index: i32, flags: u32) -> i32;
+    pub fn duk_api_next(ctx: *mut duk_context, enum_index: i32) -> i32;
+    pub fn duk_api_push_entries(ctx: *mut duk_context, index: i32, count: *mut usize) -> i32;
+    pub fn duk_api_dump_function(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_api_load_function(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_api_heap_stats(ctx: *mut duk_context, stats: *mut duk_api_heap_info);
+    pub fn duk_api_heap_walk(ctx: *mut duk_context, visitor: *const duk_api_heap_visitor);
+    pub fn duk_api_request_interrupt(ctx: *mut duk_context);
+    pub fn duk_api_set_interrupt_handler(func: Option<duk_api_interrupt_function>);
+    pub fn duk_api_sample_callstack(ctx: *mut duk_context, frames: *mut duk_api_frame, max: usize) -> usize;
 
     pub fn duk_create_heap(alloc_func: Option<duk_alloc_function>,
                        realloc_func: Option<duk_realloc_function>,
@@ -139,6 +241,7 @@ extern "C" {
     pub fn duk_pcall(ctx: *mut duk_context, nargs: i32) -> i32;
     pub fn duk_pcall_method(ctx: *mut duk_context, nargs: i32) -> i32;
     pub fn duk_pcall_prop(ctx: *mut duk_context, obj_index: i32, nargs: i32) -> i32;
+    pub fn duk_pnew(ctx: *mut duk_context, nargs: i32) -> i32;
 
     pub fn duk_safe_to_lstring(ctx: *mut duk_context,
                            index: i32,
@@ -146,6 +249,7 @@ extern "C" {
                            -> *const c_char;
 
     pub fn duk_get_top(ctx: *mut duk_context) -> i32;
+    pub fn duk_set_top(ctx: *mut duk_context, index: i32);
     pub fn duk_normalize_index(ctx: *mut duk_context, index: i32) -> i32;
     pub fn duk_require_normalize_index(ctx: *mut duk_context, index: i32) -> i32;
     pub fn duk_check_stack(ctx: *mut duk_context, extra: i32) -> bool;
@@ -172,10 +276,15 @@ extern "C" {
     pub fn duk_push_object(ctx: *mut duk_context) -> i32;
     pub fn duk_push_pointer(ctx: *mut duk_context, p: *mut c_void);
     pub fn duk_push_buffer_raw(ctx: *mut duk_context, len: usize, dynamic: u32) -> *mut c_void;
+    pub fn duk_push_buffer_object(ctx: *mut duk_context, idx_buffer: i32, byte_offset: usize, byte_length: usize, flags: u32);
     pub fn duk_push_c_function(ctx: *mut duk_context, func: Option<duk_c_function>, nargs: i32) -> i32;
     pub fn duk_push_c_lightfunc(ctx: *mut duk_context, func: Option<duk_c_function>, nargs: i32, length: i32, magic: i32);
     pub fn duk_push_current_function(ctx: *mut duk_context);
+    pub fn duk_set_magic(ctx: *mut duk_context, index: i32, magic: i32);
+    pub fn duk_get_current_magic(ctx: *mut duk_context) -> i32;
+    pub fn duk_set_finalizer(ctx: *mut duk_context, index: i32);
     pub fn duk_push_this(ctx: *mut duk_context);
+    pub fn duk_push_proxy(ctx: *mut duk_context, proxy_flags: u32) -> i32;
     pub fn duk_push_thread_raw(ctx: *mut duk_context, flags: u32) -> i32;
 
     pub fn duk_config_buffer(ctx: *mut duk_context, index: i32, ptr: *mut c_void, len: usize);
@@ -192,6 +301,8 @@ extern "C" {
     pub fn duk_is_string(ctx: *mut duk_context, index: i32) -> i32;
     pub fn duk_is_function(ctx: *mut duk_context, index: i32) -> i32;
     pub fn duk_is_thread(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_is_buffer_data(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_is_symbol(ctx: *mut duk_context, index: i32) -> i32;
 
     pub fn duk_to_object(ctx: *mut duk_context, index: i32);
     pub fn duk_to_number(ctx: *mut duk_context, index: i32) -> f64;
@@ -201,7 +312,10 @@ extern "C" {
     pub fn duk_get_number(ctx: *mut duk_context, index: i32) -> f64;
     pub fn duk_get_lstring(ctx: *mut duk_context, index: i32, len: Option<&mut usize>) -> *const c_char;
     pub fn duk_get_buffer(ctx: *mut duk_context, index: i32, len: Option<&mut usize>) -> *mut c_void;
+    pub fn duk_get_buffer_data(ctx: *mut duk_context, index: i32, len: Option<&mut usize>) -> *mut c_void;
     pub fn duk_get_pointer(ctx: *mut duk_context, index: i32) -> *mut c_void;
+    pub fn duk_get_heapptr(ctx: *mut duk_context, index: i32) -> *mut c_void;
+    pub fn duk_get_int(ctx: *mut duk_context, index: i32) -> i32;
 
     pub fn duk_get_prop(ctx: *mut duk_context, obj_index: i32) -> i32;
     pub fn duk_put_prop(ctx: *mut duk_context, obj_index: i32) -> i32;
@@ -217,10 +331,25 @@ extern "C" {
                             key: *const c_char,
                             len: usize)
                             -> i32;
+    pub fn duk_del_prop(ctx: *mut duk_context, obj_index: i32) -> i32;
+    pub fn duk_has_prop(ctx: *mut duk_context, obj_index: i32) -> i32;
+    pub fn duk_del_prop_lstring(ctx: *mut duk_context,
+                            obj_index: i32,
+                            key: *const c_char,
+                            len: usize)
+                            -> i32;
+    pub fn duk_get_error_code(ctx: *mut duk_context, index: i32) -> i32;
+    pub fn duk_has_prop_lstring(ctx: *mut duk_context,
+                            obj_index: i32,
+                            key: *const c_char,
+                            len: usize)
+                            -> i32;
     pub fn duk_get_prop_index(ctx: *mut duk_context, obj_index: i32, index: u32) -> i32;
     pub fn duk_put_prop_index(ctx: *mut duk_context, obj_index: i32, index: u32) -> i32;
 
     pub fn duk_push_global_object(ctx: *mut duk_context);
+    pub fn duk_push_heap_stash(ctx: *mut duk_context);
+    pub fn duk_push_global_stash(ctx: *mut duk_context);
     pub fn duk_get_global_lstring(ctx: *mut duk_context, key: *const c_char, len: usize) -> i32;
     pub fn duk_put_global_lstring(ctx: *mut duk_context, key: *const c_char, len: usize) -> i32;
 
@@ -228,9 +357,11 @@ extern "C" {
     pub fn duk_next(ctx: *mut duk_context, enum_idx: i32, get_value: i32) -> i32;
 
     pub fn duk_throw_raw(ctx: *mut duk_context);
-    pub fn duk_fatal(ctx: *mut duk_context, err_code: i32, err_msg: *const c_char);
+    pub fn duk_fatal_raw(ctx: *mut duk_context, err_msg: *const c_char);
 
     pub fn duk_push_context_dump(ctx: *mut duk_context);
+    pub fn duk_inspect_value(ctx: *mut duk_context, index: i32);
+    pub fn duk_inspect_callstack_entry(ctx: *mut duk_context, level: i32);
     pub fn duk_set_global_object(ctx: *mut duk_context);
 
     pub fn duk_gc(ctx: *mut duk_context, flags: u32);
@@ -242,30 +373,162 @@ unsafe fn interop<'a>(udata: *mut c_void) -> &'a mut InteropRef {
     &mut (*(udata as *mut Userdata)).interop
 }
 
+#[inline(always)]
+unsafe fn memory<'a>(udata: *mut c_void) -> &'a mut MemoryUsage {
+    &mut (*(udata as *mut Userdata)).memory
+}
+
+/// Stores a panic caught in a callback, to be resumed when control returns to the Rust caller.
+/// Only the first panic is kept.
+unsafe fn record_panic(udata: *mut c_void, payload: Box<dyn Any + Send>) {
+    let panic = &mut (*(udata as *mut Userdata)).panic;
+    if panic.is_none() {
+        *panic = Some(payload);
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Records the panic and returns the message of the JS error thrown in its place.
+unsafe fn panic_error(udata: *mut c_void, payload: Box<dyn Any + Send>) -> String {
+    let msg = format!("Rust panic: {}", panic_message(&*payload));
+    record_panic(udata, payload);
+    msg
+}
+
+/// Throws `msg` as a JS error. No Rust values may be pending destruction in the caller.
+unsafe fn throw_message(ctx: *mut duk_context, msg: String) {
+    duk_push_lstring(ctx, msg.as_ptr() as *const c_char, msg.len());
+    drop(msg);
+    duk_throw_raw(ctx);
+}
+
 pub extern "C" fn alloc_func(udata: *mut c_void, size: usize) -> *mut c_void {
     unsafe {
-        interop(udata).alloc(size) as *mut c_void
+        match panic::catch_unwind(AssertUnwindSafe(|| interop(udata).alloc(size))) {
+            Ok(ptr) if !ptr.is_null() => {
+                memory(udata).alloc(size);
+                ptr as *mut c_void
+            }
+            Ok(_) => null_mut(),
+            Err(payload) => {
+                record_panic(udata, payload);
+                null_mut()
+            }
+        }
     }
 }
 
 pub extern "C" fn realloc_func(udata: *mut c_void, ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return alloc_func(udata, size);
+    }
     unsafe {
-        interop(udata).realloc(ptr as *mut u8, size) as *mut c_void
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let interop = interop(udata);
+            let old_size = interop.alloc_size(ptr as *mut u8);
+            (old_size, interop.realloc(ptr as *mut u8, size))
+        }));
+        match res {
+            Ok((old_size, new_ptr)) if !new_ptr.is_null() => {
+                memory(udata).realloc(old_size, size);
+                new_ptr as *mut c_void
+            }
+            // failed reallocation leaves the old block in place
+            Ok(_) => null_mut(),
+            Err(payload) => {
+                record_panic(udata, payload);
+                null_mut()
+            }
+        }
     }
 }
 
 pub extern "C" fn free_func(udata: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let interop = interop(udata);
+            let size = interop.alloc_size(ptr as *mut u8);
+            interop.free(ptr as *mut u8);
+            size
+        }));
+        match res {
+            Ok(size) => memory(udata).free(size),
+            Err(payload) => record_panic(udata, payload),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+pub extern "C" fn console_func(udata: *mut c_void, func: u32, ctx: *mut duk_context, msg: *const c_char, len: usize) {
+    use std::str;
+    use std::slice;
     unsafe {
-        interop(udata).free(ptr as *mut u8);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let msg = str::from_utf8_unchecked(slice::from_raw_parts(msg as *const u8, len));
+            #[cfg(feature = "tracing")]
+            crate::trace::console_event(&DukContext::from_raw(ctx), ConsoleFunc::from(func), || msg.to_string());
+            interop(udata).console(ConsoleFunc::from(func), msg);
+        }));
+        if let Err(payload) = res {
+            throw_message(ctx, panic_error(udata, payload));
+        }
     }
 }
 
-pub extern "C" fn console_func(udata: *mut c_void, func: u32, msg: *const c_char, len: usize) {
+#[allow(unused_variables)]
+pub extern "C" fn console_handler_func(udata: *mut c_void, func: u32, ctx: *mut duk_context, msg: *const c_char, len: usize) {
     use std::str;
     use std::slice;
     unsafe {
-        let msg = str::from_utf8_unchecked(slice::from_raw_parts(msg as *const u8, len));
-        interop(udata).console(ConsoleFunc::from(func), msg);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let msg = str::from_utf8_unchecked(slice::from_raw_parts(msg as *const u8, len));
+            #[cfg(feature = "tracing")]
+            crate::trace::console_event(&DukContext::from_raw(ctx), ConsoleFunc::from(func), || msg.to_string());
+            let handler = &mut *(udata as *mut Box<dyn ConsoleHandler>);
+            handler.console(ConsoleFunc::from(func), msg);
+        }));
+        if let Err(payload) = res {
+            // udata is the handler here, the panic is recorded in the heap userdata
+            throw_message(ctx, panic_error(duk_api_get_heap_udata(ctx), payload));
+        }
+    }
+}
+
+pub extern "C" fn console_handler_free(udata: *mut c_void) {
+    unsafe {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            drop(Box::from_raw(udata as *mut Box<dyn ConsoleHandler>));
+        }));
+        if let Err(payload) = res {
+            log::error!("console handler panicked on drop: {}", panic_message(&*payload));
+        }
+    }
+}
+
+pub extern "C" fn console_args_func(udata: *mut c_void, func: u32, ctx: *mut duk_context, nargs: i32, indent: u32) {
+    unsafe {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let ctx = DukContext::from_raw(ctx);
+            let args = ConsoleArgs::new(&ctx, nargs, indent);
+            #[cfg(feature = "tracing")]
+            crate::trace::console_event(&ctx, ConsoleFunc::from(func), || args.message());
+            interop(udata).console_args(ConsoleFunc::from(func), &args);
+        }));
+        if let Err(payload) = res {
+            throw_message(ctx, panic_error(udata, payload));
+        }
     }
 }
 
@@ -274,29 +537,122 @@ pub extern "C" fn func_dispatch(ctx: *mut duk_context) -> i32 {
     use std::slice;
     unsafe {
         duk_push_current_function(ctx);
-        duk_get_prop_lstring(ctx, -1, FUNC_NAME_PROP.as_ptr() as *const c_char, FUNC_NAME_PROP.len());
+        DukContext::from_raw(ctx).get_hidden_prop(-1, FUNC_ID_PROP);
         let mut len: usize = 0;
         let ptr = duk_get_lstring(ctx, -1, Some(&mut len)) as *const u8;
         let name = str::from_utf8_unchecked(slice::from_raw_parts(ptr, len));
         duk_pop_2(ctx);
         let udata = duk_api_get_heap_udata(ctx);
-        let mut duk_ctx = DukContext::from_raw(ctx);
-        let r = match interop(udata).call(&mut duk_ctx, name) {
-            Ok(r) => r,
-            Err(err) => {
-                let msg = format!("{err}");
-                duk_push_lstring(ctx, msg.as_ptr() as *const c_char, msg.len());
-                duk_throw_raw(ctx);
-                Return::Error
-            },
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut duk_ctx = DukContext::from_raw(ctx);
+            interop(udata).call(&mut duk_ctx, name)
+        }));
+        let msg = match res {
+            Ok(Ok(r)) => return r as i32,
+            Ok(Err(err)) => format!("{err}"),
+            Err(payload) => panic_error(udata, payload),
+        };
+        throw_message(ctx, msg);
+        Return::Error as i32
+    }
+}
+
+pub extern "C" fn closure_dispatch(ctx: *mut duk_context) -> i32 {
+    unsafe {
+        duk_push_current_function(ctx);
+        DukContext::from_raw(ctx).get_hidden_prop(-1, FUNC_DATA_PROP);
+        let data = duk_get_pointer(ctx, -1) as *const Box<dyn Any + Send>;
+        duk_pop_2(ctx);
+        let udata = duk_api_get_heap_udata(ctx);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            // the function is on the call stack, so the closure cannot be finalized during the call
+            match data.as_ref().and_then(|data| data.downcast_ref::<NativeClosure>()) {
+                Some(closure) => closure(&mut DukContext::from_raw(ctx)),
+                None => Err(JsError::from("closure was released".to_string())),
+            }
+        }));
+        let msg = match res {
+            Ok(Ok(r)) => return r as i32,
+            Ok(Err(err)) => format!("{err}"),
+            Err(payload) => panic_error(udata, payload),
+        };
+        throw_message(ctx, msg);
+        Return::Error as i32
+    }
+}
+
+pub extern "C" fn proxy_dispatch(ctx: *mut duk_context) -> i32 {
+    unsafe {
+        let trap = duk_get_current_magic(ctx);
+        duk_push_this(ctx);
+        DukContext::from_raw(ctx).get_hidden_prop(-1, FUNC_DATA_PROP);
+        let data = duk_get_pointer(ctx, -1) as *mut Box<dyn Any + Send>;
+        duk_pop_2(ctx);
+        let udata = duk_api_get_heap_udata(ctx);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            // the handler object is referenced by the proxy being accessed
+            match data.as_mut().and_then(|data| data.downcast_mut::<Box<dyn ProxyHandler>>()) {
+                Some(handler) => crate::proxy::call_trap(&mut **handler, &mut DukContext::from_raw(ctx), trap),
+                None => Err(JsError::from("proxy handler was released".to_string())),
+            }
+        }));
+        let msg = match res {
+            Ok(Ok(r)) => return r as i32,
+            Ok(Err(err)) => format!("{err}"),
+            Err(payload) => panic_error(udata, payload),
         };
-        r as i32
+        throw_message(ctx, msg);
+        Return::Error as i32
+    }
+}
+
+pub extern "C" fn func_data_finalizer(ctx: *mut duk_context) -> i32 {
+    unsafe {
+        let duk_ctx = DukContext::from_raw(ctx);
+        duk_ctx.get_hidden_prop(0, FUNC_DATA_PROP);
+        let data = duk_get_pointer(ctx, -1) as *mut Box<dyn Any + Send>;
+        duk_pop(ctx);
+        if !data.is_null() {
+            // cleared first, since the function may be rescued and finalized again
+            duk_push_pointer(ctx, null_mut());
+            duk_ctx.put_hidden_prop(0, FUNC_DATA_PROP);
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(data)))) {
+                log::error!("function data panicked on drop: {}", panic_message(&*payload));
+            }
+        }
+        0
     }
 }
 
 pub extern "C" fn fatal_handler(udata: *mut c_void, msg: *const c_char) {
     unsafe {
         let msg = CStr::from_ptr(msg).to_string_lossy();
-        interop(udata).fatal(&msg);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            interop(udata).fatal(&msg);
+        }));
+        // Duktape state cannot be recovered after a fatal error and the handler must neither
+        // return nor unwind; errors outside of protected calls poison the heap instead, so this
+        // is an internal error
+        if let Err(payload) = res {
+            log::error!("Duktape fatal error: {}; fatal handler panicked: {}", msg, panic_message(&*payload));
+            std::process::abort();
+        }
+    }
+}
+
+/// Installs [`interrupt_handler`] as the interrupt hook of all heaps.
+pub(crate) fn register_interrupt_handler() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| unsafe { duk_api_set_interrupt_handler(Some(interrupt_handler)) });
+}
+
+pub extern "C" fn interrupt_handler(udata: *mut c_void) -> u32 {
+    let data = unsafe { &mut *(udata as *mut Userdata) };
+    if data.terminating {
+        return 1;
+    }
+    if let Some(ref mut sampler) = data.profiler {
+        sampler.interrupt();
     }
+    0
 }
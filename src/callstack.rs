@@ -0,0 +1,127 @@
+use super::*;
+
+/// Activation on the Duktape call stack, see [`DukContext::callstack`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallFrame {
+    /// Name of the called function, `None` for anonymous functions.
+    pub function: Option<String>,
+    /// File name of the function, `None` for native functions.
+    pub filename: Option<String>,
+    /// Line of the program counter, `0` for native functions.
+    pub line: u32,
+    /// Bytecode offset of the instruction being executed, `0` for native functions.
+    pub pc: u32,
+    /// Original position of the line, if a source map is registered for the file, see
+    /// [`DukContext::register_source_map`].
+    pub original: Option<OriginalPosition>,
+}
+
+impl std::fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let function = self.function.as_deref().unwrap_or("[anon]");
+        match (&self.original, &self.filename) {
+            (Some(original), _) => write!(f, "{function} ({original})"),
+            (None, Some(filename)) => write!(f, "{function} ({filename}:{})", self.line),
+            (None, None) => write!(f, "{function} (native)"),
+        }
+    }
+}
+
+impl DukContext {
+    /// Returns the call stack of the current thread, innermost frame first.
+    ///
+    /// Called from a native function, e.g. in [`JsInterop::call`], the first frame is the
+    /// native function itself and the next one its caller.
+    pub fn callstack(&self) -> Vec<CallFrame> {
+        (1..).map_while(|depth| self.callstack_entry(-depth)).collect()
+    }
+
+    /// Returns the frame at callstack `level`, `-1` being the innermost frame, like
+    /// `Duktape.act(level)`.
+    pub fn callstack_entry(&self, level: i32) -> Option<CallFrame> {
+        unsafe { duk_inspect_callstack_entry(self.ctx, level) };
+        if !self.is_object(-1) {
+            self.pop();
+            return None;
+        }
+        self.get_prop_string(-1, "lineNumber");
+        let line = self.get_number(-1) as u32;
+        self.get_prop_string(-2, "pc");
+        let pc = self.get_number(-1) as u32;
+        self.pop_n(2);
+        self.get_prop_string(-1, "function");
+        let filename = self.string_prop(-1, "fileName");
+        let original = filename.as_deref()
+            .and_then(|filename| self.source_map(filename))
+            .and_then(|map| map.lookup(line, 0));
+        let frame = CallFrame {
+            function: self.string_prop(-1, "name").filter(|name| !name.is_empty()),
+            filename,
+            line,
+            pc,
+            original,
+        };
+        self.pop_n(2);
+        Some(frame)
+    }
+
+    fn string_prop(&self, obj_index: i32, key: &str) -> Option<String> {
+        let value = if self.get_prop_string(obj_index, key) && self.is_string(-1) {
+            Some(self.get_string(-1).to_string())
+        } else {
+            None
+        };
+        self.pop();
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    #[test]
+    fn test_callstack() {
+        let engine = JsEngine::new().unwrap();
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorded = frames.clone();
+        engine.push_closure(0, move |ctx| {
+            *recorded.lock().unwrap() = ctx.callstack();
+            Ok(Return::Undefined)
+        });
+        engine.put_global_string("audit");
+        engine.eval_file("user.js", "function transfer() {\n  audit();\n}\n[1].forEach(function () { transfer(); });").unwrap();
+        engine.pop();
+
+        let frames = frames.lock().unwrap();
+        let names: Vec<_> = frames.iter().map(|f| f.to_string()).collect();
+        assert_eq!(names[..4], ["[anon] (native)", "transfer (user.js:2)", "[anon] (user.js:4)", "forEach (native)"]);
+        assert_eq!(frames[0].line, 0);
+        assert!(frames[1].pc > 0);
+        assert_eq!(frames.last().unwrap().filename.as_deref(), Some("user.js"));
+        assert_eq!(engine.callstack(), []);
+        assert_eq!(engine.callstack_entry(-1), None);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_callstack_source_mapped() {
+        let engine = JsEngine::new().unwrap();
+        let map = engine.parse_source_map(r#"{"version": 3, "sources": ["user.ts"], "mappings": ";AAEE"}"#).unwrap();
+        let frame = Arc::new(Mutex::new(None));
+        let recorded = frame.clone();
+        engine.push_closure(0, move |ctx| {
+            *recorded.lock().unwrap() = ctx.callstack_entry(-2);
+            Ok(Return::Undefined)
+        });
+        engine.put_global_string("audit");
+        let options = CompileOptions::new().filename("user.js").source_map(map);
+        engine.eval_with("function transfer() {\n  audit();\n}\ntransfer();", &options).unwrap();
+        engine.pop();
+
+        let frame = frame.lock().unwrap().clone().unwrap();
+        assert_eq!((frame.filename.as_deref(), frame.line), (Some("user.js"), 2));
+        assert_eq!(frame.to_string(), "transfer (user.ts:3:3)");
+    }
+}
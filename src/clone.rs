@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use super::*;
+
+/// Maximum nesting depth of a cloned value.
+const MAX_CLONE_DEPTH: usize = 1000;
+
+/// Hidden property holding the primitive value of `Date`, `Boolean`, `Number` and `String` objects.
+const INT_VALUE_PROP: &[u8] = b"\x82Value";
+
+/// Buffer object class names by `DUK_BUFOBJ_*` type passed to `duk_push_buffer_object()`;
+/// Node.js buffers are not supported.
+const BUFOBJ_CLASSES: [&str; 12] = [
+    "ArrayBuffer", "", "DataView", "Int8Array", "Uint8Array", "Uint8ClampedArray", "Int16Array",
+    "Uint16Array", "Int32Array", "Uint32Array", "Float32Array", "Float64Array",
+];
+const DUK_BUFOBJ_ARRAYBUFFER: u32 = 0;
+
+const _: () = assert!(matches!(env!("DUK_VERSION").as_bytes(), b"20700"), "BUFOBJ_CLASSES is written against Duktape 2.7.0");
+
+/// Deep-copies the value at `index` in `from` and pushes the copy onto `to`.
+///
+/// Works between any two contexts, including contexts belonging to different [`JsEngine`] heaps.
+/// Shared references and cycles in the source are preserved in the copy. Supported values are:
+/// * primitives, plain buffers and pointers,
+/// * arrays and plain objects (own enumerable string-keyed properties, accessors are invoked),
+/// * `Date`, `RegExp` and `Boolean`/`Number`/`String` wrapper objects,
+/// * `ArrayBuffer`, `DataView` and typed arrays (views are created on the copy of their buffer).
+///
+/// Other objects, including user-land `Map`/`Set` implementations, are copied as plain objects;
+/// prototypes are not preserved. Functions, threads and symbols cannot be cloned and
+/// result in an error, in which case both stacks are left unchanged. Errors thrown while reading
+/// the source, e.g. by a getter, are returned as well.
+pub fn structured_clone(from: &DukContext, index: i32, to: &DukContext) -> Result<(), JsError> {
+    let index = from.normalize_index(index);
+    let from_top = from.get_top();
+    let to_top = to.get_top();
+
+    let mut cloner = Cloner {
+        from,
+        to,
+        memo: HashMap::new(),
+        memo_index: to.push_array(),
+        depth: 0,
+    };
+    let res = cloner.clone_value(index)
+        .and_then(|()| from.check_poisoned())
+        .and_then(|()| to.check_poisoned());
+    match res {
+        Ok(()) => {
+            to.remove(cloner.memo_index);
+            Ok(())
+        }
+        Err(err) => {
+            to.set_top(to_top);
+            from.set_top(from_top);
+            Err(err)
+        }
+    }
+}
+
+struct Cloner<'a> {
+    from: &'a DukContext,
+    to: &'a DukContext,
+    /// Maps source heap pointers to slots in the memo array on the target stack.
+    memo: HashMap<usize, u32>,
+    memo_index: i32,
+    depth: usize,
+}
+
+impl Cloner<'_> {
+    fn clone_value(&mut self, index: i32) -> Result<(), JsError> {
+        use DukType::*;
+
+        if self.depth > MAX_CLONE_DEPTH {
+            return Err(JsError::from(format!("cloned value nesting exceeds {} levels", MAX_CLONE_DEPTH)));
+        }
+        self.to.check_stack(4)?;
+        self.from.check_stack(4)?;
+
+        let (from, to) = (self.from, self.to);
+        match from.get_type(index) {
+            DUK_TYPE_NONE => return Err(JsError::from(format!("invalid stack index {}", index))),
+            DUK_TYPE_UNDEFINED => to.push_undefined(),
+            DUK_TYPE_NULL => to.push_null(),
+            DUK_TYPE_BOOLEAN => to.push_boolean(from.get_boolean(index)),
+            DUK_TYPE_NUMBER => to.push_number(from.get_number(index)),
+            DUK_TYPE_STRING => {
+                if from.is_symbol(index) {
+                    return Err(JsError::from("symbols cannot be cloned".to_string()));
+                }
+                to.push_string(from.get_string(index));
+            }
+            DUK_TYPE_POINTER => unsafe {
+                duk_push_pointer(to.ctx, duk_get_pointer(from.ctx, index));
+            }
+            DUK_TYPE_LIGHTFUNC => return Err(JsError::from("functions cannot be cloned".to_string())),
+            DUK_TYPE_BUFFER => {
+                if !self.push_memoized(index) {
+                    to.push_buffer(from.get_buffer_data(index));
+                    self.memoize(index);
+                }
+            }
+            DUK_TYPE_OBJECT => {
+                if !self.push_memoized(index) {
+                    self.depth += 1;
+                    let res = self.clone_object(index);
+                    self.depth -= 1;
+                    res?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clone_object(&mut self, index: i32) -> Result<(), JsError> {
+        let (from, to) = (self.from, self.to);
+
+        if from.is_function(index) {
+            return Err(JsError::from("functions cannot be cloned".to_string()));
+        }
+        if unsafe { duk_is_thread(from.ctx, index) } == 1 {
+            return Err(JsError::from("threads cannot be cloned".to_string()));
+        }
+
+        let class = class_name(object_class(from, index));
+        match class {
+            "Date" => {
+                let time = internal_value(from, index, |ctx| ctx.get_number(-1));
+                self.construct("Date", |to| to.push_number(time))?;
+            }
+            "RegExp" => {
+                from.get_prop_string(index, "source");
+                from.get_prop_string(index, "flags");
+                let source = from.get_string(-2).to_string();
+                let flags = from.get_string(-1).to_string();
+                from.pop_n(2);
+                self.construct("RegExp", |to| {
+                    to.push_string(&source);
+                    to.push_string(&flags);
+                })?;
+            }
+            "Boolean" | "Number" | "String" => {
+                let value = internal_value(from, index, |ctx| Primitive::read(ctx, -1));
+                value.push(to);
+                unsafe { duk_to_object(to.ctx, -1); }
+            }
+            "ArrayBuffer" => {
+                let data = from.get_buffer_data(index);
+                to.push_buffer(data);
+                unsafe {
+                    duk_push_buffer_object(to.ctx, -1, 0, data.len(), DUK_BUFOBJ_ARRAYBUFFER);
+                }
+                to.remove(-2);
+            }
+            _ if BUFOBJ_CLASSES.contains(&class) => {
+                let flags = BUFOBJ_CLASSES.iter().position(|c| *c == class).unwrap_or_default() as u32;
+                self.clone_view(index, flags)?;
+            }
+            _ => {
+                if from.is_array(index) {
+                    to.push_array();
+                } else {
+                    to.push_object();
+                }
+                self.memoize(index);
+                let target = to.get_top() - 1;
+                self.clone_properties(index, target)?;
+                if from.is_array(index) {
+                    // restores trailing holes, which are not enumerated
+                    to.push_number(from.get_length(index) as f64);
+                    to.put_prop_string(target, "length");
+                }
+                return Ok(());
+            }
+        }
+        self.memoize(index);
+        Ok(())
+    }
+
+    /// Pushes a copy of the `DataView` or typed array at `index`, viewing the copy of its buffer,
+    /// so that views sharing a buffer keep sharing it.
+    fn clone_view(&mut self, index: i32, flags: u32) -> Result<(), JsError> {
+        let from = self.from;
+        from.get_prop_string(index, "byteOffset");
+        from.get_prop_string(index, "byteLength");
+        let offset = from.get_number(-2) as usize;
+        let length = from.get_number(-1) as usize;
+        from.pop_n(2);
+        from.get_prop_string(index, "buffer");
+        if !from.is_object(-1) {
+            return Err(JsError::from("buffer of a view cannot be read".to_string()));
+        }
+        self.clone_value(from.get_top() - 1)?;
+        from.pop();
+        unsafe {
+            duk_push_buffer_object(self.to.ctx, -1, offset, length, flags);
+        }
+        self.to.remove(-2);
+        Ok(())
+    }
+
+    fn clone_properties(&mut self, index: i32, target: i32) -> Result<(), JsError> {
+        let from = self.from;
+        let res = from.enum_raw(index, DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY.bits());
+        from.propagate_js_error(res)?;
+        let enum_index = from.get_top() - 1;
+        loop {
+            match from.next_raw(enum_index) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => return from.propagate_js_error(Err(err)),
+            }
+            let key = from.get_string(-2).to_string();
+            self.clone_value(from.get_top() - 1)?;
+            self.to.put_prop_string(target, &key);
+            from.pop_n(2);
+        }
+        from.pop();
+        Ok(())
+    }
+
+    /// Constructs `new <global_name>(...)` on the target, with arguments pushed by `push_args`.
+    fn construct<F: FnOnce(&DukContext)>(&self, global_name: &str, push_args: F) -> Result<(), JsError> {
+        let to = self.to;
+        let top = to.get_top();
+        to.get_global_string(global_name);
+        push_args(to);
+        let res = to.pnew((to.get_top() - top - 1) as usize);
+        to.propagate_js_error(res)
+    }
+
+    fn push_memoized(&self, index: i32) -> bool {
+        let ptr = unsafe { duk_get_heapptr(self.from.ctx, index) } as usize;
+        match self.memo.get(&ptr) {
+            Some(slot) => {
+                self.to.get_prop_index(self.memo_index, *slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records the value on top of the target stack as the copy of the source value at `index`.
+    fn memoize(&mut self, index: i32) {
+        let ptr = unsafe { duk_get_heapptr(self.from.ctx, index) } as usize;
+        let slot = self.memo.len() as u32;
+        self.memo.insert(ptr, slot);
+        self.to.dup(-1);
+        self.to.put_prop_index(self.memo_index, slot);
+    }
+}
+
+/// Primitive value of a `Boolean`, `Number` or `String` object.
+enum Primitive {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Primitive {
+    fn read(ctx: &DukContext, index: i32) -> Self {
+        match ctx.get_type(index) {
+            DukType::DUK_TYPE_BOOLEAN => Primitive::Boolean(ctx.get_boolean(index)),
+            DukType::DUK_TYPE_STRING => Primitive::String(ctx.get_string(index).to_string()),
+            _ => Primitive::Number(ctx.get_number(index)),
+        }
+    }
+
+    fn push(&self, ctx: &DukContext) {
+        match self {
+            Primitive::Boolean(b) => ctx.push_boolean(*b),
+            Primitive::Number(n) => ctx.push_number(*n),
+            Primitive::String(s) => ctx.push_string(s),
+        }
+    }
+}
+
+/// Returns the internal class number of the object at `index`.
+fn object_class(ctx: &DukContext, index: i32) -> usize {
+    unsafe {
+        duk_inspect_value(ctx.ctx, index);
+        ctx.get_prop_string(-1, "class");
+        let class = duk_get_int(ctx.ctx, -1);
+        ctx.pop_n(2);
+        class as usize
+    }
+}
+
+/// Reads the hidden primitive value of the object at `index` without invoking `valueOf()`.
+fn internal_value<T, F: FnOnce(&DukContext) -> T>(ctx: &DukContext, index: i32, f: F) -> T {
+    let index = ctx.normalize_index(index);
+    ctx.push_bytes(INT_VALUE_PROP);
+    ctx.get_prop(index);
+    let res = f(ctx);
+    ctx.pop();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eval_bool;
+
+    fn clone_between(code: &str) -> (JsEngine, JsEngine) {
+        let from = JsEngine::new().unwrap();
+        let to = JsEngine::new().unwrap();
+        from.eval(code).unwrap();
+        structured_clone(&from, -1, &to).unwrap();
+        to.put_global_string("value");
+        assert_eq!(to.get_top(), 0);
+        (from, to)
+    }
+
+    #[test]
+    fn clone_primitives_and_objects() {
+        //language=js
+        let (_from, to) = clone_between(r#"({
+            n: 1.5, s: "text", b: true, nil: null, u: undefined,
+            arr: [1, , "x", [2]], obj: {nested: {deep: 1}}
+        })"#);
+        assert!(eval_bool(&to, r#"value.n === 1.5 && value.s === "text" && value.b === true && value.nil === null
+            && 'u' in value && value.u === undefined"#));
+        assert!(eval_bool(&to, r#"value.arr.length === 4 && !(1 in value.arr) && value.arr[3][0] === 2"#));
+        assert!(eval_bool(&to, r#"value.obj.nested.deep === 1 && Object.getPrototypeOf(value) === Object.prototype"#));
+    }
+
+    #[test]
+    fn clone_builtin_types() {
+        //language=js
+        let (_from, to) = clone_between(r#"({
+            date: new Date(1234567890123),
+            re: /a+b/gi,
+            f64: new Float64Array([1.5, -2]),
+            u8: new Uint8Array([1, 2, 3]).subarray(1),
+            ab: new Uint8Array([9, 8]).buffer,
+            dv: new DataView(new ArrayBuffer(4)),
+            num: new Number(7),
+            str: new String("s")
+        })"#);
+        assert!(eval_bool(&to, r#"value.date instanceof Date && value.date.getTime() === 1234567890123"#));
+        assert!(eval_bool(&to, r#"value.re instanceof RegExp && value.re.source === "a+b" && value.re.global
+            && value.re.ignoreCase && !value.re.multiline && value.re.test("xAABy")"#));
+        assert!(eval_bool(&to, r#"value.f64 instanceof Float64Array && value.f64.length === 2 && value.f64[1] === -2"#));
+        assert!(eval_bool(&to, r#"value.u8 instanceof Uint8Array && value.u8.length === 2 && value.u8[0] === 2"#));
+        assert!(eval_bool(&to, r#"value.ab instanceof ArrayBuffer && value.ab.byteLength === 2"#));
+        assert!(eval_bool(&to, r#"value.dv instanceof DataView && value.dv.byteLength === 4"#));
+        assert!(eval_bool(&to, r#"value.num instanceof Number && value.num.valueOf() === 7 && value.str.valueOf() === "s""#));
+    }
+
+    #[test]
+    fn clone_preserves_identity_and_cycles() {
+        //language=js
+        let (from, to) = clone_between(r#"
+            var shared = {v: 1};
+            var root = {a: shared, b: shared, list: [shared]};
+            root.self = root;
+            root"#);
+        assert!(eval_bool(&to, r#"value.a === value.b && value.list[0] === value.a && value.self === value"#));
+
+        // copy is independent of the source
+        to.eval("value.a.v = 2").unwrap();
+        assert!(eval_bool(&from, "shared.v === 1"));
+    }
+
+    #[test]
+    fn clone_shares_view_buffers() {
+        //language=js
+        let (_from, to) = clone_between(r#"
+            var buf = new ArrayBuffer(8);
+            ({buf: buf, bytes: new Uint8Array(buf, 2, 4), words: new Uint16Array(buf), dv: new DataView(buf, 4)})"#);
+        assert!(eval_bool(&to, r#"value.bytes.buffer === value.buf && value.words.buffer === value.buf
+            && value.dv.buffer === value.buf"#));
+        assert!(eval_bool(&to, r#"value.bytes.byteOffset === 2 && value.bytes.length === 4 && value.dv.byteOffset === 4"#));
+        to.eval("value.words[1] = 0x0102").unwrap();
+        assert!(eval_bool(&to, "value.bytes[0] + value.bytes[1] === 3 && value.dv.byteLength === 4"));
+    }
+
+    #[test]
+    fn clone_returns_getter_errors() {
+        let from = JsEngine::new().unwrap();
+        let to = JsEngine::new().unwrap();
+        //language=js
+        from.eval("({a: 1, get b() { throw new Error('boom'); }})").unwrap();
+
+        let err = structured_clone(&from, -1, &to).unwrap_err();
+        assert!(err.to_string().contains("boom"));
+        assert!(!from.is_poisoned());
+        assert_eq!(from.get_top(), 1);
+        assert_eq!(to.get_top(), 0);
+    }
+
+    #[test]
+    fn clone_rejects_functions() {
+        let from = JsEngine::new().unwrap();
+        let to = JsEngine::new().unwrap();
+        to.push_string("keep");
+        //language=js
+        from.eval("({a: [1, {f: function() {}}]})").unwrap();
+
+        let err = structured_clone(&from, -1, &to).unwrap_err();
+        assert!(err.to_string().contains("functions cannot be cloned"));
+        assert_eq!(from.get_top(), 1);
+        assert_eq!(to.get_top(), 1);
+        assert_eq!(to.get_string(-1), "keep");
+    }
+
+    #[test]
+    fn clone_within_same_context() {
+        let engine = JsEngine::new().unwrap();
+        //language=js
+        engine.eval("var orig = {a: {b: [1, 2]}, s: new String('x'), d: new Date(5)}; orig").unwrap();
+        structured_clone(&engine, -1, &engine).unwrap();
+        engine.put_global_string("copy");
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+        assert!(eval_bool(&engine, "copy !== orig && copy.a !== orig.a && copy.a.b[1] === 2"));
+        assert!(eval_bool(&engine, "copy.s !== orig.s && copy.s.valueOf() === 'x' && copy.d.getTime() === 5"));
+    }
+}
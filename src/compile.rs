@@ -0,0 +1,216 @@
+use std::sync::Arc;
+use super::*;
+
+/// Kind of code compiled, see [`CompileOptions::mode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CompileMode {
+    /// Program code.
+    ///
+    /// [`DukContext::eval_with`] compiles program code as eval code, like `duk_eval()`.
+    #[default]
+    Global,
+    /// Code as passed to `eval()`.
+    Eval,
+    /// A single function expression, e.g. `function (a, b) { return a + b; }`, compiling to that
+    /// function.
+    Function,
+}
+
+/// Options for [`DukContext::compile_with`] and [`DukContext::eval_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CompileOptions {
+    filename: Option<String>,
+    mode: CompileMode,
+    strict: bool,
+    shebang: bool,
+    source_map: Option<Arc<SourceMap>>,
+}
+
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the file name used in stack traces and error messages. Without a file name,
+    /// Duktape uses `input`, or `eval` for eval code.
+    pub fn filename<S: Into<String>>(mut self, filename: S) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    pub fn mode(mut self, mode: CompileMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Compiles as strict mode code, as if the code started with `"use strict"`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Allows a `#!` comment on the first line.
+    pub fn shebang(mut self, shebang: bool) -> Self {
+        self.shebang = shebang;
+        self
+    }
+
+    /// Sets the source map of the compiled code, registered for the file name with
+    /// [`DukContext::register_source_map`]. Ignored without a file name.
+    pub fn source_map<M: Into<Arc<SourceMap>>>(mut self, map: M) -> Self {
+        self.source_map = Some(map.into());
+        self
+    }
+
+    pub fn get_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn get_mode(&self) -> CompileMode {
+        self.mode
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn is_shebang(&self) -> bool {
+        self.shebang
+    }
+
+    pub fn get_source_map(&self) -> Option<&Arc<SourceMap>> {
+        self.source_map.as_ref()
+    }
+
+    /// Returns the flags selected by these options.
+    pub fn flags(&self) -> DukCompileFlags {
+        let mut flags = match self.mode {
+            CompileMode::Global => DukCompileFlags::empty(),
+            CompileMode::Eval => DukCompileFlags::DUK_COMPILE_EVAL,
+            CompileMode::Function => DukCompileFlags::DUK_COMPILE_FUNCTION,
+        };
+        flags.set(DukCompileFlags::DUK_COMPILE_STRICT, self.strict);
+        flags.set(DukCompileFlags::DUK_COMPILE_SHEBANG, self.shebang);
+        flags
+    }
+
+    /// Registers the source map for the file name, if both are set. Code of a file with a map
+    /// registered before gets the `stack` getter installed in the realm of `ctx`.
+    pub(crate) fn register_source_map(&self, ctx: &DukContext) -> Result<(), JsError> {
+        match (&self.filename, &self.source_map) {
+            (Some(filename), Some(map)) => ctx.register_source_map(filename, map.clone()),
+            (Some(filename), None) if ctx.source_map(filename).is_some() => ctx.install_stack_rewrite(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pushes the file name, if set, and returns the flags for `duk_compile_raw()` and
+    /// `duk_eval_raw()` with the argument count in the low bits.
+    pub(crate) fn push_args(&self, ctx: &DukContext) -> u32 {
+        // the source is passed by pointer; without NOSOURCE Duktape takes the value below the
+        // file name as source and removes it from the stack
+        let flags = self.flags() | DukCompileFlags::DUK_COMPILE_SAFE | DukCompileFlags::DUK_COMPILE_NOSOURCE;
+        match self.filename {
+            Some(ref filename) => {
+                ctx.push_string(filename);
+                1 | flags.bits()
+            }
+            None => (flags | DukCompileFlags::DUK_COMPILE_NOFILENAME).bits(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_string(engine: &JsEngine, code: &str, options: &CompileOptions) -> Result<String, JsError> {
+        engine.eval_with(code, options)?;
+        let s = engine.safe_to_lstring(-1);
+        engine.pop();
+        Ok(s)
+    }
+
+    #[test]
+    fn test_compile_function() {
+        let engine = JsEngine::new().unwrap();
+        let options = CompileOptions::new().filename("add.js").mode(CompileMode::Function);
+        engine.compile_with("function add(a, b) { return a + b; }", &options).unwrap();
+        assert!(engine.is_function(-1));
+        for (a, b) in [(1, 2), (20, 22), (-5, 5)] {
+            engine.dup(-1);
+            engine.push_i32(a);
+            engine.push_i32(b);
+            engine.pcall(2).unwrap();
+            assert_eq!(engine.get_number(-1), (a + b) as f64);
+            engine.pop();
+        }
+        engine.get_prop_string(-1, "fileName");
+        assert_eq!(engine.get_string(-1), "add.js");
+        engine.pop_n(2);
+
+        // evaluating function code calls it without arguments
+        let options = CompileOptions::new().mode(CompileMode::Function);
+        assert_eq!(eval_string(&engine, "function () { return typeof arguments[0]; }", &options).unwrap(), "undefined");
+        assert!(engine.compile_with("var x = 1;", &options).is_err());
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_compile_keeps_stack() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_string("below");
+        engine.compile("1 + 1").unwrap();
+        engine.compile_file("a.js", "2 + 2").unwrap();
+        assert_eq!(engine.get_top(), 3);
+        engine.pcall(0).unwrap();
+        assert_eq!(engine.get_number(-1), 4.);
+        engine.pop();
+        engine.pcall(0).unwrap();
+        assert_eq!(engine.get_number(-1), 2.);
+        assert_eq!(engine.get_string(0), "below");
+        engine.pop_n(2);
+    }
+
+    #[test]
+    fn test_compile_global() {
+        let engine = JsEngine::new().unwrap();
+        engine.compile_with("var counter = (typeof counter === 'number' ? counter : 0) + 1; counter", &CompileOptions::new()).unwrap();
+        for expected in 1..=3 {
+            engine.dup(-1);
+            engine.pcall(0).unwrap();
+            assert_eq!(engine.get_number(-1), expected as f64);
+            engine.pop();
+        }
+        engine.pop();
+
+        engine.compile_with("var declared = 2; declared * 21", &CompileOptions::new().mode(CompileMode::Eval)).unwrap();
+        engine.pcall(0).unwrap();
+        assert_eq!(engine.get_number(-1), 42.);
+        engine.pop();
+
+        // eval_with() evaluates program code as eval code, so declarations are deletable
+        assert_eq!(eval_string(&engine, "var deletable = 1; delete deletable", &CompileOptions::new()).unwrap(), "true");
+    }
+
+    #[test]
+    fn test_strict_and_shebang() {
+        let engine = JsEngine::new().unwrap();
+        let code = "undeclared = 1; typeof undeclared";
+        assert_eq!(eval_string(&engine, code, &CompileOptions::new()).unwrap(), "number");
+        let err = eval_string(&engine, "other = 1", &CompileOptions::new().strict(true)).unwrap_err();
+        assert!(err.to_string().contains("ReferenceError"), "{err}");
+
+        let code = "#!/usr/bin/env kg-js\n'ok'";
+        assert!(engine.eval(code).is_err());
+        assert_eq!(eval_string(&engine, code, &CompileOptions::new().shebang(true)).unwrap(), "ok");
+
+        let options = CompileOptions::new().filename("named.js");
+        let err = eval_string(&engine, "\nnull.x", &options).unwrap_err();
+        assert!(err.to_string().contains("TypeError"));
+        engine.eval_with("new Error('x').stack", &options).unwrap();
+        assert!(engine.get_string(-1).contains("named.js:1"));
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+    }
+}
@@ -1,4 +1,5 @@
 use log::Level;
+use crate::{DukContext, DukType, JsError, JsonFormat, ReadJs};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u32)]
@@ -12,6 +13,15 @@ pub enum ConsoleFunc {
     Error = 7,
     Exception = 8,
     Dir = 9,
+    Time = 10,
+    Count = 11,
+    Group = 12,
+    Table = 13,
+    TimeLog = 14,
+    TimeEnd = 15,
+    CountReset = 16,
+    GroupCollapsed = 17,
+    GroupEnd = 18,
 }
 
 impl ConsoleFunc {
@@ -26,6 +36,15 @@ impl ConsoleFunc {
             Self::Error => Level::Error,
             Self::Exception => Level::Error,
             Self::Dir => Level::Debug,
+            Self::Time => Level::Debug,
+            Self::Count => Level::Debug,
+            Self::Group => Level::Debug,
+            Self::Table => Level::Debug,
+            Self::TimeLog => Level::Debug,
+            Self::TimeEnd => Level::Debug,
+            Self::CountReset => Level::Debug,
+            Self::GroupCollapsed => Level::Debug,
+            Self::GroupEnd => Level::Debug,
         }
     }
 }
@@ -42,21 +61,117 @@ impl From<u32> for ConsoleFunc {
             7 => Self::Error,
             8 => Self::Exception,
             9 => Self::Dir,
+            10 => Self::Time,
+            11 => Self::Count,
+            12 => Self::Group,
+            13 => Self::Table,
+            14 => Self::TimeLog,
+            15 => Self::TimeEnd,
+            16 => Self::CountReset,
+            17 => Self::GroupCollapsed,
+            18 => Self::GroupEnd,
             _ => Self::Log,
         }
     }
 }
 
+/// Receives console output of a single global environment, see [`DukContext::init_console_with`].
+pub trait ConsoleHandler: Send + 'static {
+    fn console(&mut self, func: ConsoleFunc, msg: &str);
+}
+
+impl<F: FnMut(ConsoleFunc, &str) + Send + 'static> ConsoleHandler for F {
+    fn console(&mut self, func: ConsoleFunc, msg: &str) {
+        self(func, msg)
+    }
+}
+
+/// Original arguments of a console call, passed to [`crate::JsInterop::console_args`].
+///
+/// Arguments occupy value stack indices `0..len()` of [`ConsoleArgs::ctx`]. Messages produced by
+/// the console itself (e.g. by `console.count` or `console.timeEnd`) are passed as a single string.
+#[derive(Debug)]
+pub struct ConsoleArgs<'a> {
+    ctx: &'a DukContext,
+    len: i32,
+    group_level: u32,
+}
+
+impl<'a> ConsoleArgs<'a> {
+    pub(crate) fn new(ctx: &'a DukContext, len: i32, group_level: u32) -> Self {
+        ConsoleArgs { ctx, len, group_level }
+    }
+
+    pub fn ctx(&self) -> &DukContext {
+        self.ctx
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Nesting level of `console.group` at the time of the call.
+    pub fn group_level(&self) -> u32 {
+        self.group_level
+    }
+
+    /// Reads argument `index`.
+    pub fn get<T: ReadJs>(&self, index: usize) -> Result<T, JsError> {
+        if index >= self.len() {
+            return Err(JsError::from(format!("console argument {} out of range", index)));
+        }
+        self.ctx.read(index as i32)
+    }
+
+    /// Formats argument `index` like the default console does: strings as they are,
+    /// objects as JX and other values with `ToString()`.
+    pub fn format(&self, index: usize) -> String {
+        let idx = index as i32;
+        if index >= self.len() {
+            return String::new();
+        }
+        if self.ctx.get_type(idx) == DukType::DUK_TYPE_OBJECT {
+            if let Ok(s) = self.ctx.json_encode(idx, JsonFormat::Jx, None) {
+                return s;
+            }
+        }
+        self.ctx.dup(idx);
+        let s = self.ctx.safe_to_lstring(-1);
+        self.ctx.pop();
+        s
+    }
+
+    /// All arguments formatted and joined with spaces, without format string substitution.
+    pub fn message(&self) -> String {
+        (0..self.len()).map(|i| self.format(i)).collect::<Vec<_>>().join(" ")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::{Arc, Mutex};
-    use crate::{ConsoleFunc, DukContext, JsEngine, JsError, JsInterop, Return};
+    use crate::{ConsoleArgs, ConsoleFunc, DukContext, JsEngine, JsError, JsInterop, Realm, Return};
 
     #[derive(Clone, Debug)]
     struct ConsoleInterop {
         messages: Arc<Mutex<Vec<String>>>
     }
 
+    fn console_messages(code: &str) -> Vec<String> {
+        let interop = ConsoleInterop {
+            messages: Arc::new(Mutex::new(Vec::new()))
+        };
+        let engine = JsEngine::with_interop(interop.clone()).unwrap();
+        engine.init_console();
+        engine.eval(code).unwrap();
+        let messages = interop.messages.lock().unwrap();
+        messages.clone()
+    }
+
     impl JsInterop for ConsoleInterop {
         fn call(&mut self, _engine: &mut DukContext, _func_name: &str) -> Result<Return, JsError> {
             Ok(Return::Error)
@@ -99,4 +214,208 @@ mod tests {
         assert_eq!(messages.len(), 1);
         assert_eq!(messages[0], "DEBUG: test message");
     }
+
+    #[test]
+    fn test_console_printf() {
+        let messages = console_messages(r#"
+            console.log('%s has %d items (%i%%) %f', 'cart', '42.9', 7.5, '1.25', 'extra', {a: 1});
+            console.log('%j %o', {a: [1, 2]}, {b: 'x'});
+            console.log('%s %s', 'only one');
+        "#);
+        assert_eq!(messages, vec![
+            "DEBUG: cart has 42 items (7%) 1.25 extra {a:1}",
+            r#"DEBUG: {"a":[1,2]} {b:"x"}"#,
+            "DEBUG: only one %s",
+        ]);
+    }
+
+    #[test]
+    fn test_console_count() {
+        let messages = console_messages(r#"
+            console.count(); console.count('x'); console.count();
+            console.countReset('x'); console.count('x'); console.countReset('missing');
+        "#);
+        assert_eq!(messages, vec![
+            "DEBUG: default: 1",
+            "DEBUG: x: 1",
+            "DEBUG: default: 2",
+            "DEBUG: x: 1",
+            "WARN: Count for 'missing' does not exist",
+        ]);
+    }
+
+    #[test]
+    fn test_console_time() {
+        let messages = console_messages(r#"
+            console.time('t'); console.time('t');
+            console.timeLog('t', 'step', 1); console.timeEnd('t'); console.timeEnd('t');
+        "#);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0], "WARN: Timer 't' already exists");
+        assert!(messages[1].starts_with("DEBUG: t: ") && messages[1].ends_with("ms step 1"), "{}", messages[1]);
+        assert!(messages[2].starts_with("DEBUG: t: ") && messages[2].ends_with("ms"), "{}", messages[2]);
+        assert_eq!(messages[3], "WARN: Timer 't' does not exist");
+    }
+
+    #[test]
+    fn test_console_group() {
+        let messages = console_messages(r#"
+            console.group('outer');
+            console.log('a\nb');
+            console.group();
+            console.warn('c');
+            console.groupEnd(); console.groupEnd(); console.groupEnd();
+            console.log('d');
+        "#);
+        assert_eq!(messages, vec![
+            "DEBUG: outer",
+            "DEBUG:   a\n  b",
+            "WARN:     c",
+            "DEBUG: d",
+        ]);
+    }
+
+    #[test]
+    fn test_console_table() {
+        let messages = console_messages(r#"
+            console.table([{a: 1, b: 'x'}, {a: 22}, 5]);
+            console.table({r: {a: 1, b: 2}}, ['b']);
+            console.table('plain');
+        "#);
+        assert_eq!(messages, vec![
+            "DEBUG: | (index) | a  | b | Values |\n\
+             |---------|----|---|--------|\n\
+             | 0       | 1  | x |        |\n\
+             | 1       | 22 |   |        |\n\
+             | 2       |    |   | 5      |",
+            "DEBUG: | (index) | b |\n\
+             |---------|---|\n\
+             | r       | 2 |",
+            "DEBUG: plain",
+        ]);
+    }
+
+    type ConsoleCall = (ConsoleFunc, u32, Vec<String>);
+
+    #[derive(Clone, Debug, Default)]
+    struct StructuredInterop {
+        calls: Arc<Mutex<Vec<ConsoleCall>>>,
+        numbers: Arc<Mutex<Vec<f64>>>,
+    }
+
+    impl JsInterop for StructuredInterop {
+        fn call(&mut self, _engine: &mut DukContext, _func_name: &str) -> Result<Return, JsError> {
+            Ok(Return::Error)
+        }
+
+        fn console_args(&mut self, func: ConsoleFunc, args: &ConsoleArgs) {
+            if let Ok(n) = args.get::<f64>(args.len().saturating_sub(1)) {
+                self.numbers.lock().unwrap().push(n);
+            }
+            let values = (0..args.len()).map(|i| args.format(i)).collect();
+            self.calls.lock().unwrap().push((func, args.group_level(), values));
+        }
+    }
+
+    #[test]
+    fn test_console_structured() {
+        let interop = StructuredInterop::default();
+        let engine = JsEngine::with_interop(interop.clone()).unwrap();
+        engine.init_console_structured();
+        engine.eval(r#"
+            console.info('user %s', 'bob', {id: 7}, 12.5);
+            console.group('g');
+            console.count();
+            console.table([1]);
+        "#).unwrap();
+        assert_eq!(engine.get_top(), 1);
+
+        let calls = interop.calls.lock().unwrap();
+        assert_eq!(*calls, vec![
+            (ConsoleFunc::Info, 0, vec!["user %s".to_string(), "bob".to_string(), "{id:7}".to_string(), "12.5".to_string()]),
+            (ConsoleFunc::Group, 0, vec!["g".to_string()]),
+            (ConsoleFunc::Count, 1, vec!["default: 1".to_string()]),
+            (ConsoleFunc::Table, 1, vec!["[1]".to_string()]),
+        ]);
+        assert_eq!(*interop.numbers.lock().unwrap(), vec![12.5]);
+    }
+
+    #[test]
+    fn test_console_funcs() {
+        let interop = StructuredInterop::default();
+        let engine = JsEngine::with_interop(interop.clone()).unwrap();
+        engine.init_console_structured();
+        engine.eval(r#"
+            console.time('t'); console.timeLog('t'); console.timeEnd('t');
+            console.group('g'); console.groupCollapsed('c'); console.groupEnd();
+            console.count(); console.countReset(); console.countReset('missing');
+        "#).unwrap();
+
+        let funcs: Vec<_> = interop.calls.lock().unwrap().iter().map(|call| call.0).collect();
+        assert_eq!(funcs, vec![
+            ConsoleFunc::TimeLog,
+            ConsoleFunc::TimeEnd,
+            ConsoleFunc::Group,
+            ConsoleFunc::GroupCollapsed,
+            ConsoleFunc::Count,
+            ConsoleFunc::Warn,
+        ]);
+    }
+
+    #[test]
+    fn test_console_helpers_after_builtins_removed() {
+        let messages = console_messages(r#"
+            delete String; delete Object; delete Array; delete parseInt; delete JSON;
+            console.log('%d items %j', '42', {a: 1});
+        "#);
+        assert_eq!(messages, vec![r#"DEBUG: 42 items {"a":1}"#]);
+    }
+
+    #[test]
+    fn test_console_with_handler_per_realm() {
+        let interop = ConsoleInterop {
+            messages: Arc::new(Mutex::new(Vec::new()))
+        };
+        let engine = JsEngine::with_interop(interop.clone()).unwrap();
+        engine.init_console();
+
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+        let realm1 = Realm::new(&engine).unwrap();
+        let realm2 = Realm::new(&engine).unwrap();
+        let sink = first.clone();
+        realm1.init_console_with(move |func: ConsoleFunc, msg: &str| sink.lock().unwrap().push(format!("{:?} {}", func, msg)));
+        let sink = second.clone();
+        realm2.init_console_with(move |func: ConsoleFunc, msg: &str| sink.lock().unwrap().push(format!("{:?} {}", func, msg)));
+
+        realm1.eval("console.log('one'); console.group(); console.warn('%d', 1.5)").unwrap();
+        realm2.eval("console.info('two')").unwrap();
+        engine.eval("console.log('engine')").unwrap();
+
+        assert_eq!(*first.lock().unwrap(), vec!["Log one", "Warn   1"]);
+        assert_eq!(*second.lock().unwrap(), vec!["Info two"]);
+        assert_eq!(*interop.messages.lock().unwrap(), vec!["DEBUG: engine"]);
+    }
+
+    #[test]
+    fn test_console_handler_dropped() {
+        let engine = JsEngine::new().unwrap();
+        let sink = Arc::new(Mutex::new(Vec::<String>::new()));
+
+        let handler = sink.clone();
+        engine.init_console_with(move |_func: ConsoleFunc, msg: &str| handler.lock().unwrap().push(msg.to_string()));
+        assert_eq!(Arc::strong_count(&sink), 2);
+
+        // replacing the console releases the previous handler
+        let handler = sink.clone();
+        engine.init_console_with(move |_func: ConsoleFunc, msg: &str| handler.lock().unwrap().push(msg.to_string()));
+        engine.gc();
+        assert_eq!(Arc::strong_count(&sink), 2);
+
+        engine.eval("console.log('x')").unwrap();
+        assert_eq!(*sink.lock().unwrap(), vec!["x"]);
+
+        drop(engine);
+        assert_eq!(Arc::strong_count(&sink), 1);
+    }
 }
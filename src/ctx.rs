@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::ops::DerefMut;
 use super::*;
 
@@ -9,6 +10,9 @@ macro_rules! try_exec_success {
     }
 }
 
+/// Rust closure callable from JS, see [`DukContext::push_closure`].
+pub type NativeClosure = Box<dyn Fn(&mut DukContext) -> Result<Return, JsError> + Send>;
+
 /// Wrapper for Duktape context
 #[derive(Debug)]
 pub struct DukContext {
@@ -32,6 +36,11 @@ impl DukContext {
         unsafe { duk_get_top(self.ctx) }
     }
 
+    #[inline]
+    pub fn set_top(&self, index: i32) {
+        unsafe { duk_set_top(self.ctx, index) }
+    }
+
     #[inline]
     pub fn dup(&self, index: i32) {
         unsafe {
@@ -87,6 +96,19 @@ impl DukContext {
         unsafe { duk_push_global_object(self.ctx); }
     }
 
+    /// Pushes the heap stash, an object shared by all threads of the heap and unreachable from scripts.
+    #[inline]
+    pub fn push_heap_stash(&self) {
+        unsafe { duk_push_heap_stash(self.ctx); }
+    }
+
+    /// Pushes the global stash, an object shared by threads with the same global environment
+    /// and unreachable from scripts.
+    #[inline]
+    pub fn push_global_stash(&self) {
+        unsafe { duk_push_global_stash(self.ctx); }
+    }
+
     #[inline]
     pub fn push_boolean(&self, value: bool) {
         unsafe { duk_push_boolean(self.ctx, value as i32) }
@@ -137,28 +159,112 @@ impl DukContext {
         }
     }
 
+    /// Pushes a fixed buffer holding a copy of `data`.
+    #[inline]
+    pub fn push_buffer(&self, data: &[u8]) {
+        unsafe {
+            let ptr = duk_push_buffer_raw(self.ctx, data.len(), DukBufFlags::DUK_BUF_FLAG_NOZERO.bits());
+            if !data.is_empty() {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
+            }
+        }
+    }
+
     #[inline]
     pub fn push_array(&self) -> i32 {
         unsafe { duk_push_array(self.ctx) }
     }
 
+    /// Pushes a function dispatched to [`JsInterop::call`] with `func_name`.
+    ///
+    /// The name passed to the interop is stored in a hidden property, so it does not change
+    /// when scripts redefine the function's `name`.
     pub fn push_function(&self, func_name: &str, nargs: i32) {
         unsafe {
             duk_push_c_function(self.ctx, Some(func_dispatch), nargs);
-            duk_push_lstring(self.ctx, FUNC_NAME_PROP.as_ptr() as *const c_char, FUNC_NAME_PROP.len());
-            duk_push_lstring(self.ctx, func_name.as_ptr() as *const c_char, func_name.len());
-            duk_def_prop(self.ctx, -3, (DukDefpropFlags::DUK_DEFPROP_ENUMERABLE | DukDefpropFlags::DUK_DEFPROP_HAVE_VALUE).bits())
         }
+        // configurable like the `name` of script functions; defining it on the new function
+        // fails only on a poisoned heap
+        self.push_string(func_name);
+        let _ = self.define_property(-2, FUNC_NAME_PROP, PropertyDescriptor::stack_value().configurable(true));
+        self.push_string(func_name);
+        self.put_hidden_prop(-2, FUNC_ID_PROP);
     }
 
-    pub fn put_prop_function(&self, obj_index: i32, func_name: &str, nargs: i32) {
-        let obj_index = self.normalize_index(obj_index);
+    /// Pushes a function like [`DukContext::push_function`] carrying a `magic` value,
+    /// read in the handler with [`DukContext::current_magic`].
+    pub fn push_function_magic(&self, func_name: &str, nargs: i32, magic: i16) {
+        self.push_function(func_name, nargs);
+        unsafe {
+            duk_set_magic(self.ctx, -1, magic as i32);
+        }
+    }
+
+    /// Pushes a function like [`DukContext::push_function`] carrying `data`, accessed in the
+    /// handler with [`DukContext::current_function_data`]. The data is dropped when the function
+    /// is garbage collected.
+    pub fn push_function_data<T: Any + Send>(&self, func_name: &str, nargs: i32, data: T) {
         self.push_function(func_name, nargs);
+        let data: Box<Box<dyn Any + Send>> = Box::new(Box::new(data));
+        unsafe { duk_push_pointer(self.ctx, Box::into_raw(data) as *mut c_void) };
+        self.put_hidden_prop(-2, FUNC_DATA_PROP);
         unsafe {
-            duk_put_prop_lstring(self.ctx, obj_index, func_name.as_ptr() as *const c_char, func_name.len());
+            duk_push_c_function(self.ctx, Some(func_data_finalizer), 1);
+            duk_set_finalizer(self.ctx, -2);
         }
     }
 
+    /// Pushes a function calling the Rust closure `f`, which is dropped when the function is
+    /// garbage collected.
+    pub fn push_closure<F>(&self, nargs: i32, f: F)
+    where
+        F: Fn(&mut DukContext) -> Result<Return, JsError> + Send + 'static,
+    {
+        let data: Box<Box<dyn Any + Send>> = Box::new(Box::new(Box::new(f) as NativeClosure));
+        unsafe {
+            duk_push_c_function(self.ctx, Some(closure_dispatch), nargs);
+        }
+        unsafe { duk_push_pointer(self.ctx, Box::into_raw(data) as *mut c_void) };
+        self.put_hidden_prop(-2, FUNC_DATA_PROP);
+        unsafe {
+            duk_push_c_function(self.ctx, Some(func_data_finalizer), 1);
+            duk_set_finalizer(self.ctx, -2);
+        }
+    }
+
+    /// Returns the magic value of the currently running native function, `0` if none was set.
+    #[inline]
+    pub fn current_magic(&self) -> i32 {
+        unsafe { duk_get_current_magic(self.ctx) }
+    }
+
+    /// Calls `f` with data attached to the currently running native function with
+    /// [`DukContext::push_function_data`], and returns its result; `None` if there is no data of
+    /// type `T`.
+    ///
+    /// The data is only borrowed for the duration of `f`, as the function and its data may be
+    /// collected once the native call returns.
+    pub fn current_function_data<T: Any, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        unsafe {
+            duk_push_current_function(self.ctx);
+            if duk_is_object(self.ctx, -1) == 0 {
+                duk_pop(self.ctx);
+                return None;
+            }
+            self.get_hidden_prop(-1, FUNC_DATA_PROP);
+            let data = duk_get_pointer(self.ctx, -1) as *const Box<dyn Any + Send>;
+            duk_pop_2(self.ctx);
+            // the function is on the call stack while `f` runs, so its data is alive
+            data.as_ref().and_then(|data| data.downcast_ref::<T>()).map(f)
+        }
+    }
+
+    pub fn put_prop_function(&self, obj_index: i32, func_name: &str, nargs: i32) {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_function(func_name, nargs);
+        self.put_prop_string(obj_index, func_name);
+    }
+
     pub fn put_global_function(&self, func_name: &str, nargs: i32) {
         self.push_function(func_name, nargs);
         self.put_global_string(func_name);
@@ -189,6 +295,22 @@ impl DukContext {
         unsafe { duk_is_array(self.ctx, index) == 1 }
     }
 
+    #[inline]
+    pub fn is_function(&self, index: i32) -> bool {
+        unsafe { duk_is_function(self.ctx, index) == 1 }
+    }
+
+    /// Returns `true` for plain buffers and buffer objects (`ArrayBuffer`, typed arrays, `DataView`).
+    #[inline]
+    pub fn is_buffer_data(&self, index: i32) -> bool {
+        unsafe { duk_is_buffer_data(self.ctx, index) == 1 }
+    }
+
+    #[inline]
+    pub fn is_symbol(&self, index: i32) -> bool {
+        unsafe { duk_is_symbol(self.ctx, index) == 1 }
+    }
+
     #[inline]
     pub fn is_pure_object(&self, index: i32) -> bool {
         unsafe {
@@ -220,6 +342,20 @@ impl DukContext {
         }
     }
 
+    /// Returns the active byte range of a plain buffer or a buffer object.
+    #[inline]
+    pub fn get_buffer_data(&self, index: i32) -> &[u8] {
+        use std::slice;
+        unsafe {
+            let mut len: usize = 0;
+            let ptr = duk_get_buffer_data(self.ctx, index, Some(&mut len)) as *const u8;
+            if ptr.is_null() {
+                return &[];
+            }
+            slice::from_raw_parts(ptr, len)
+        }
+    }
+
     #[inline]
     pub fn get_number(&self, index: i32) -> f64 {
         unsafe { duk_get_number(self.ctx, index) }
@@ -230,7 +366,7 @@ impl DukContext {
         unsafe { duk_get_boolean(self.ctx, index) != 0 }
     }
 
-    pub fn get_context(&self, index: i32) -> Result<DukContextGuard, JsError> {
+    pub fn get_context(&self, index: i32) -> Result<DukContextGuard<'_>, JsError> {
         let new_ctx = unsafe { duk_get_context(self.ctx, index) };
         if new_ctx.is_null() {
             return Err(JsError::from(format!("could not get context from index {}", index)));
@@ -240,115 +376,223 @@ impl DukContext {
 
     #[inline]
     pub fn get_prop(&self, obj_index: i32) -> bool {
-        unsafe { duk_get_prop(self.ctx, obj_index) == 1 }
+        self.prop_op(obj_index, DUK_API_PROP_GET)
     }
 
     #[inline]
     pub fn put_prop(&self, obj_index: i32) {
-        unsafe { duk_put_prop(self.ctx, obj_index); }
+        self.prop_op(obj_index, DUK_API_PROP_PUT);
     }
 
     #[inline]
     pub fn get_prop_string(&self, obj_index: i32, key: &str) -> bool {
-        unsafe {
-            duk_get_prop_lstring(self.ctx, obj_index, key.as_ptr() as *const c_char, key.len()) == 1
-        }
+        let obj_index = self.normalize_index(obj_index);
+        self.push_string(key);
+        self.prop_op(obj_index, DUK_API_PROP_GET)
     }
 
     #[inline]
     pub fn put_prop_string(&self, obj_index: i32, key: &str) {
-        unsafe {
-            duk_put_prop_lstring(self.ctx,
-                                 obj_index,
-                                 key.as_ptr() as *const c_char,
-                                 key.len());
-        }
+        let obj_index = self.normalize_index(obj_index);
+        self.push_string(key);
+        self.swap(-1, -2);
+        self.prop_op(obj_index, DUK_API_PROP_PUT);
+    }
+
+    #[inline]
+    pub fn has_prop(&self, obj_index: i32) -> bool {
+        self.prop_op(obj_index, DUK_API_PROP_HAS)
+    }
+
+    #[inline]
+    pub fn del_prop(&self, obj_index: i32) -> bool {
+        self.prop_op(obj_index, DUK_API_PROP_DEL)
+    }
+
+    #[inline]
+    pub fn del_prop_string(&self, obj_index: i32, key: &str) -> bool {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_string(key);
+        self.prop_op(obj_index, DUK_API_PROP_DEL)
+    }
+
+    #[inline]
+    pub fn has_prop_string(&self, obj_index: i32, key: &str) -> bool {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_string(key);
+        self.prop_op(obj_index, DUK_API_PROP_HAS)
     }
 
     #[inline]
     pub fn get_prop_index(&self, obj_index: i32, index: u32) -> bool {
-        unsafe { duk_get_prop_index(self.ctx, obj_index, index) == 1 }
+        let obj_index = self.normalize_index(obj_index);
+        self.push_u32(index);
+        self.prop_op(obj_index, DUK_API_PROP_GET)
     }
 
     #[inline]
     pub fn put_prop_index(&self, obj_index: i32, index: u32) {
-        unsafe {
-            duk_put_prop_index(self.ctx, obj_index, index);
-        }
+        let obj_index = self.normalize_index(obj_index);
+        self.push_u32(index);
+        self.swap(-1, -2);
+        self.prop_op(obj_index, DUK_API_PROP_PUT);
     }
 
     #[inline]
     pub fn get_global_string(&self, key: &str) -> bool {
-        unsafe {
-            duk_get_global_lstring(self.ctx, key.as_ptr() as *const c_char, key.len()) == 1
-        }
+        self.push_global_object();
+        let found = self.get_prop_string(-1, key);
+        self.remove(-2);
+        found
     }
 
     #[inline]
     pub fn put_global_string(&self, key: &str) {
-        unsafe {
-            duk_put_global_lstring(self.ctx, key.as_ptr() as *const c_char, key.len());
+        self.push_global_object();
+        self.swap(-1, -2);
+        self.put_prop_string(-2, key);
+        self.pop();
+    }
+
+    /// Runs a property operation with the key, and the value for puts, on stack top.
+    ///
+    /// Errors thrown outside of a protected call, e.g. by a getter, cannot be returned, so they
+    /// poison the heap; on a poisoned heap the operation is skipped. Gets push `undefined` then.
+    fn prop_op(&self, obj_index: i32, op: u32) -> bool {
+        self.prop_op_result(obj_index, op) != 0
+    }
+
+    /// Like [`DukContext::prop_op`], returning the raw result, e.g. the length read by
+    /// `DUK_API_PROP_LENGTH`.
+    fn prop_op_result(&self, obj_index: i32, op: u32) -> usize {
+        if self.is_poisoned() {
+            self.pop_n(match op {
+                DUK_API_PROP_PUT => 2,
+                DUK_API_PROP_LENGTH => 0,
+                _ => 1,
+            });
+        } else {
+            let mut result = 0;
+            let res = unsafe { duk_api_prop_op(self.ctx, obj_index, op, &mut result) };
+            if res == DUK_EXEC_SUCCESS {
+                return result;
+            }
+            self.poison_top();
+            self.pop();
+            self.resume_panic();
+        }
+        if op == DUK_API_PROP_GET {
+            self.push_undefined();
         }
+        0
     }
 
+    /// Returns the length of the value at `obj_index`, reading `length` of objects.
+    ///
+    /// Errors thrown by the read, e.g. by a proxy trap, poison the heap outside of a protected
+    /// call; the length is 0 then.
     #[inline]
     pub fn get_length(&self, obj_index: i32) -> usize {
-        unsafe {
-            duk_get_length(self.ctx, obj_index)
-        }
+        self.prop_op_result(obj_index, DUK_API_PROP_LENGTH)
     }
 
     #[inline]
     pub fn enum_indices(&self, obj_index: i32) {
-        unsafe {
-            duk_enum(self.ctx, obj_index, DukEnumFlags::DUK_ENUM_ARRAY_INDICES_ONLY.bits());
-        }
+        self.enum_unprotected(obj_index, DukEnumFlags::DUK_ENUM_ARRAY_INDICES_ONLY.bits());
     }
 
     #[inline]
     pub fn enum_keys(&self, obj_index: i32) {
-        unsafe {
-            duk_enum(self.ctx, obj_index, DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY.bits());
+        self.enum_unprotected(obj_index, DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY.bits());
+    }
+
+    fn enum_unprotected(&self, obj_index: i32, flags: u32) {
+        match self.enum_raw(obj_index, flags) {
+            Ok(()) => {}
+            Err(DUK_API_POISONED) => self.push_undefined(),
+            Err(_) => self.throw_or_poison(),
         }
     }
 
     #[inline]
     pub fn next(&self, obj_index: i32) -> bool {
-        unsafe {
-            duk_next(self.ctx, obj_index, 1) == 1
+        match self.next_raw(obj_index) {
+            Ok(found) => found,
+            Err(DUK_API_POISONED) => false,
+            Err(_) => {
+                self.throw_or_poison();
+                self.pop();
+                false
+            }
         }
     }
 
     #[inline]
     pub fn call_prop(&self, obj_index: i32, nargs: usize) {
-        unsafe {
-            duk_call_prop(self.ctx, obj_index, nargs as i32);
+        match self.pcall_prop(obj_index, nargs) {
+            Ok(()) => {}
+            Err(DUK_API_POISONED) => {
+                self.pop_n(nargs as i32 + 1);
+                self.push_undefined();
+            }
+            Err(_) => self.throw_or_poison(),
         }
     }
 
     #[inline]
     pub fn pcall(&self, nargs: usize) -> Result<(), i32> {
+        #[cfg(feature = "tracing")]
+        let _span = crate::trace::call_span(self).entered();
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
         let res = unsafe {
             duk_pcall(self.ctx, nargs as i32)
         };
+        self.resume_panic();
         try_exec_success!(res);
         Ok(())
     }
 
     #[inline]
     pub fn pcall_method(&self, nargs: usize) -> Result<(), i32> {
+        #[cfg(feature = "tracing")]
+        let _span = crate::trace::call_span(self).entered();
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
         let res = unsafe {
             duk_pcall_method(self.ctx, nargs as i32)
         };
+        self.resume_panic();
+        try_exec_success!(res);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn pnew(&self, nargs: usize) -> Result<(), i32> {
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe {
+            duk_pnew(self.ctx, nargs as i32)
+        };
+        self.resume_panic();
         try_exec_success!(res);
         Ok(())
     }
 
     #[inline]
     pub fn pcall_prop(&self, obj_index: i32, nargs: usize) -> Result<(), i32> {
+        #[cfg(feature = "tracing")]
+        let _span = crate::trace::call_span(self).entered();
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
         let res = unsafe {
             duk_pcall_prop(self.ctx, obj_index, nargs as i32)
         };
+        self.resume_panic();
         try_exec_success!(res);
         Ok(())
     }
@@ -362,11 +606,15 @@ impl DukContext {
         }
     }
 
+    /// Throws the value on stack top to the active protected call. Outside of one, where the
+    /// error would be fatal, the heap is poisoned with it and the value is popped instead.
     #[inline]
     pub fn throw(&self) {
-        unsafe {
-            duk_throw_raw(self.ctx);
+        if unsafe { duk_api_in_protected_call(self.ctx) } != 0 {
+            unsafe { duk_throw_raw(self.ctx) };
         }
+        self.poison_top();
+        self.pop();
     }
 
     #[inline]
@@ -385,15 +633,94 @@ impl DukContext {
         }
     }
 
+    /// Re-raises a panic caught in a Rust callback since the last call to this method.
+    ///
+    /// Callbacks convert panics into JS errors, so that they never unwind through Duktape
+    /// frames; protected calls re-raise them once control is back in Rust.
+    pub(crate) fn resume_panic(&self) {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        if let Some(payload) = unsafe { (*udata).panic.take() } {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    /// Returns `true` once an error was thrown outside of a protected call, e.g. by a getter run
+    /// by [`DukContext::get_prop`] at the top level. A poisoned engine should be dropped: protected
+    /// calls fail with a [`JsError`] for which [`JsError::is_poisoned`] holds, and property accesses are skipped.
+    pub fn is_poisoned(&self) -> bool {
+        unsafe { (*(duk_api_get_heap_udata(self.ctx) as *mut Userdata)).poisoned.is_some() }
+    }
+
+    pub(crate) fn poisoned_error(&self) -> JsError {
+        let msg = unsafe { (*(duk_api_get_heap_udata(self.ctx) as *mut Userdata)).poisoned.clone() };
+        JsError::poisoned(msg.unwrap_or_default())
+    }
+
+    /// Terminates running JavaScript code of the heap: the next bytecode instruction throws a
+    /// `RangeError`, and so does every following one until control returns to Rust, so neither
+    /// `catch` nor `finally` blocks can continue execution. Code run afterwards fails the same
+    /// way until [`DukContext::cancel_terminate_execution`] is called.
+    pub fn terminate_execution(&self) {
+        register_interrupt_handler();
+        unsafe {
+            (*(duk_api_get_heap_udata(self.ctx) as *mut Userdata)).terminating = true;
+            duk_api_request_interrupt(self.ctx);
+        }
+    }
+
+    /// Allows running code again after [`DukContext::terminate_execution`].
+    pub fn cancel_terminate_execution(&self) {
+        unsafe { (*(duk_api_get_heap_udata(self.ctx) as *mut Userdata)).terminating = false };
+    }
+
+    pub fn is_terminating(&self) -> bool {
+        unsafe { (*(duk_api_get_heap_udata(self.ctx) as *mut Userdata)).terminating }
+    }
+
+    /// Poisons the heap with the error on stack top, replacing it with `undefined`.
+    fn poison_top(&self) {
+        let msg = self.safe_to_lstring(-1);
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        unsafe {
+            if (*udata).poisoned.is_none() {
+                log::error!("uncaught error outside of a protected call: {}", msg);
+                (*udata).poisoned = Some(msg);
+            }
+        }
+        self.pop();
+        self.push_undefined();
+    }
+
+    /// Rethrows the error on stack top to the active protected call, or poisons the heap when
+    /// there is none, replacing the error with `undefined`.
+    fn throw_or_poison(&self) {
+        self.throw();
+        self.push_undefined();
+    }
+
+    #[inline]
+    pub(crate) fn check_poisoned(&self) -> Result<(), JsError> {
+        if self.is_poisoned() {
+            return Err(self.poisoned_error());
+        }
+        Ok(())
+    }
+
     /// Propagate JS error to Rust, popping the error from the stack.
     /// js_res: Result<(), i32> - JS result returned by protected call functions.
     /// If it is an error, it will be converted to JsError.
     /// This method should be called immediately after a protected call to handle the error.
+    /// Errors thrown in code with a registered [`SourceMap`] carry their mapped stack trace.
     pub fn propagate_js_error<T>(&self, js_res: Result<T, i32>) -> Result<T, JsError> {
         unsafe {
             match js_res {
                 Ok(v) => Ok(v),
+                Err(DUK_API_POISONED) => Err(self.poisoned_error()),
                 Err(_err) => {
+                    if let Some(stack) = self.mapped_error_stack() {
+                        duk_pop(self.ctx);
+                        return Err(JsError::from(stack));
+                    }
                     let mut len: usize = 0;
                     let msg = duk_safe_to_lstring(self.ctx, -1, &mut len);
                     let s = String::from(std::str::from_utf8_unchecked(std::slice::from_raw_parts(msg as *const u8, len)));
@@ -406,70 +733,163 @@ impl DukContext {
 
     #[inline]
     pub fn eval(&self, code: &str) -> Result<(), JsError> {
-        unsafe {
-            if duk_eval_raw(self.ctx,
-                            code.as_ptr() as *const c_char,
-                            code.len(),
-                            0 | (DukCompileFlags::DUK_COMPILE_SAFE | DukCompileFlags::DUK_COMPILE_NOSOURCE | DukCompileFlags::DUK_COMPILE_NOFILENAME).bits()) != 0 {
-                let mut len: usize = 0;
-                let msg = duk_safe_to_lstring(self.ctx, -1, &mut len);
-                let s = String::from(std::str::from_utf8_unchecked(std::slice::from_raw_parts(msg as *const u8, len)));
-                duk_pop(self.ctx);
-                Err(JsError::from(s))
-            } else {
-                Ok(())
-            }
-        }
+        self.eval_with(code, &CompileOptions::new())
     }
 
     #[inline]
     pub fn eval_file(&self, filename: &str, code: &str) -> Result<(), JsError> {
-        unsafe {
-            duk_push_lstring(self.ctx, filename.as_ptr() as *const c_char, filename.len());
-            if duk_eval_raw(self.ctx,
-                            code.as_ptr() as *const c_char,
-                            code.len(),
-                            1 | (DukCompileFlags::DUK_COMPILE_SAFE | DukCompileFlags::DUK_COMPILE_NOSOURCE).bits()) != 0 {
-                let s = self.safe_to_lstring(-1);
-                duk_pop(self.ctx);
-                Err(JsError::from(s))
-            } else {
-                Ok(())
-            }
-        }
+        self.eval_with(code, &CompileOptions::new().filename(filename))
+    }
+
+    /// Compiles `code` with `options`, runs it and pushes the result.
+    ///
+    /// Function code is called without arguments.
+    pub fn eval_with(&self, code: &str, options: &CompileOptions) -> Result<(), JsError> {
+        #[cfg(feature = "tracing")]
+        let _span = crate::trace::eval_span(self, options.get_filename()).entered();
+        self.check_poisoned()?;
+        options.register_source_map(self)?;
+        let flags = options.push_args(self);
+        let res = unsafe { duk_eval_raw(self.ctx, code.as_ptr() as *const c_char, code.len(), flags) };
+        self.resume_panic();
+        self.compile_result(res)
     }
 
     #[inline]
     pub fn compile(&self, code: &str) -> Result<(), JsError> {
-        unsafe {
-            if duk_compile_raw(self.ctx,
-                               code.as_ptr() as *const c_char,
-                               code.len(),
-                               0 | (DukCompileFlags::DUK_COMPILE_NORESULT | DukCompileFlags::DUK_COMPILE_NOFILENAME).bits()) != 0 {
-                let s = self.safe_to_lstring(-1);
-                duk_pop(self.ctx);
-                Err(JsError::from(s))
-            } else {
-                Ok(())
-            }
-        }
+        self.compile_with(code, &CompileOptions::new())
     }
 
     #[inline]
     pub fn compile_file(&self, filename: &str, code: &str) -> Result<(), JsError> {
-        unsafe {
-            duk_push_lstring(self.ctx, filename.as_ptr() as *const c_char, filename.len());
-            if duk_compile_raw(self.ctx,
-                               code.as_ptr() as *const c_char,
-                               code.len(),
-                               1 | DukCompileFlags::DUK_COMPILE_NORESULT.bits()) != 0 {
-                let s = self.safe_to_lstring(-1);
-                duk_pop(self.ctx);
-                Err(JsError::from(s))
-            } else {
-                Ok(())
+        self.compile_with(code, &CompileOptions::new().filename(filename))
+    }
+
+    /// Compiles `code` with `options` and pushes the compiled function, which can be called
+    /// any number of times.
+    pub fn compile_with(&self, code: &str, options: &CompileOptions) -> Result<(), JsError> {
+        self.check_poisoned()?;
+        options.register_source_map(self)?;
+        let flags = options.push_args(self);
+        let res = unsafe { duk_compile_raw(self.ctx, code.as_ptr() as *const c_char, code.len(), flags) };
+        self.resume_panic();
+        self.compile_result(res)
+    }
+
+    fn compile_result(&self, res: i32) -> Result<(), JsError> {
+        self.propagate_js_error(if res == 0 { Ok(()) } else { Err(res) })
+    }
+
+    /// Returns the Duktape bytecode of the compiled function at `index`, leaving the stack unchanged.
+    ///
+    /// Native functions and bound functions cannot be dumped.
+    pub fn dump_function(&self, index: i32) -> Result<Vec<u8>, JsError> {
+        if self.is_poisoned() {
+            return Err(self.poisoned_error());
+        }
+        let res = unsafe { duk_api_dump_function(self.ctx, index) };
+        self.resume_panic();
+        let res = if res == DUK_EXEC_SUCCESS { Ok(()) } else { Err(res) };
+        self.propagate_js_error(res)?;
+        let bytecode = self.get_buffer(-1).to_vec();
+        self.pop();
+        Ok(bytecode)
+    }
+
+    /// Pushes the function loaded from `bytecode` created by [`DukContext::dump_function`].
+    ///
+    /// # Safety
+    /// Duktape does not validate bytecode; loading bytecode that was not produced by the same
+    /// Duktape build, or was modified, is undefined behavior.
+    pub unsafe fn load_function(&self, bytecode: &[u8]) -> Result<(), JsError> {
+        if self.is_poisoned() {
+            return Err(self.poisoned_error());
+        }
+        self.push_buffer(bytecode);
+        let res = duk_api_load_function(self.ctx, -1);
+        self.resume_panic();
+        if res == DUK_API_POISONED {
+            return Err(self.poisoned_error());
+        }
+        // the function or error replaces the buffer
+        self.remove(-2);
+        self.propagate_js_error(if res == DUK_EXEC_SUCCESS { Ok(()) } else { Err(res) })
+    }
+
+    /// Encodes with `format`, or as CBOR if `format` is `None`.
+    fn encode_raw(&self, obj_index: i32, format: Option<JsonFormat>, indent: i32) -> Result<(), i32> {
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe {
+            match format {
+                Some(format) => duk_api_encode(self.ctx, obj_index, format as u32, indent),
+                None => duk_api_cbor_encode(self.ctx, obj_index),
             }
+        };
+        self.resume_panic();
+        try_exec_success!(res);
+        Ok(())
+    }
+
+    /// Decodes with `format`, or as CBOR if `format` is `None`.
+    fn decode_raw(&self, format: Option<JsonFormat>) -> Result<(), i32> {
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
         }
+        let res = unsafe {
+            match format {
+                Some(format) => duk_api_decode(self.ctx, format as u32),
+                None => duk_api_cbor_decode(self.ctx),
+            }
+        };
+        self.resume_panic();
+        try_exec_success!(res);
+        Ok(())
+    }
+
+    /// Encodes the value at `obj_index` as JSON, JX or JC text, leaving the stack unchanged.
+    /// `indent` enables pretty printing with the given number of spaces, at most 10 like in
+    /// `JSON.stringify()`; larger values are rejected.
+    pub fn json_encode(&self, obj_index: i32, format: JsonFormat, indent: Option<u32>) -> Result<String, JsError> {
+        let indent = match indent.unwrap_or(0) {
+            indent @ 0..=10 => indent as i32,
+            indent => return Err(JsError::from(format!("indent {} is greater than 10", indent))),
+        };
+        let res = self.encode_raw(obj_index, Some(format), indent);
+        self.propagate_js_error(res)?;
+        if !self.is_string(-1) {
+            self.pop();
+            return Err(JsError::from(format!("value at index {} cannot be encoded", obj_index)));
+        }
+        let s = self.get_string(-1).to_string();
+        self.pop();
+        Ok(s)
+    }
+
+    /// Decodes JSON, JX or JC `text` and pushes the result onto the stack.
+    pub fn json_decode(&self, text: &str, format: JsonFormat) -> Result<(), JsError> {
+        self.check_poisoned()?;
+        self.push_string(text);
+        let res = self.decode_raw(Some(format));
+        self.propagate_js_error(res)
+    }
+
+    /// Encodes the value at `obj_index` as CBOR, leaving the stack unchanged.
+    pub fn cbor_encode(&self, obj_index: i32) -> Result<Vec<u8>, JsError> {
+        let res = self.encode_raw(obj_index, None, 0);
+        self.propagate_js_error(res)?;
+        let data = self.get_buffer_data(-1).to_vec();
+        self.pop();
+        Ok(data)
+    }
+
+    /// Decodes CBOR `data` and pushes the result onto the stack.
+    pub fn cbor_decode(&self, data: &[u8]) -> Result<(), JsError> {
+        self.check_poisoned()?;
+        self.push_ext_buffer(data);
+        let res = self.decode_raw(None);
+        self.propagate_js_error(res)
     }
 
     #[inline]
@@ -492,7 +912,32 @@ impl DukContext {
     #[inline]
     pub fn init_console(&self) {
         unsafe {
-            duk_api_console_init(self.ctx, Some(console_func));
+            duk_api_console_init(self.ctx, Some(console_func), None, std::ptr::null_mut(), None);
+        }
+    }
+
+    /// Initialize console functions passing original arguments to [`JsInterop::console_args`]
+    /// instead of a formatted message.
+    #[inline]
+    pub fn init_console_structured(&self) {
+        unsafe {
+            duk_api_console_init(self.ctx, Some(console_func), Some(console_args_func), std::ptr::null_mut(), None);
+        }
+    }
+
+    /// Initialize console functions writing to `handler` instead of [`JsInterop::console`].
+    ///
+    /// The handler is bound to the global environment of this context, so threads created with
+    /// [`DukContext::push_thread_new_globalenv`] (and realms) can each capture their own output.
+    /// It is dropped when the console object is garbage collected or the heap is destroyed.
+    pub fn init_console_with<H: ConsoleHandler>(&self, handler: H) {
+        let handler: Box<Box<dyn ConsoleHandler>> = Box::new(Box::new(handler));
+        unsafe {
+            duk_api_console_init(self.ctx,
+                                 Some(console_handler_func),
+                                 None,
+                                 Box::into_raw(handler) as *mut c_void,
+                                 Some(console_handler_free));
         }
     }
 
@@ -543,8 +988,14 @@ impl DukContext {
     }
 
     pub fn gc(&self) {
+        self.gc_with(DukGcFlags::NONE);
+    }
+
+    /// Runs a mark-and-sweep collection; with [`DukGcFlags::DUK_GC_COMPACT`], property tables
+    /// of live objects are also shrunk to fit.
+    pub fn gc_with(&self, flags: DukGcFlags) {
         unsafe {
-            duk_gc(self.ctx, DukGcFlags::NONE.bits());
+            duk_gc(self.ctx, flags.bits());
         }
     }
 }
@@ -584,6 +1035,7 @@ impl <'a> DukContextGuard<'a> {
 }
 
 #[cfg(test)]
+#[allow(clippy::drop_non_drop)]
 mod tests {
     use serde::Deserialize;
     use super::*;
@@ -760,6 +1212,86 @@ mod tests {
         engine.gc();
     }
 
+    #[test]
+    fn test_json_encode() {
+        let engine = JsEngine::new().unwrap();
+        //language=js
+        engine.eval("({a: 1, b: [true, null], c: undefined, d: NaN})").unwrap();
+
+        assert_eq!(engine.json_encode(-1, JsonFormat::Json, None).unwrap(), r#"{"a":1,"b":[true,null],"d":null}"#);
+        assert_eq!(engine.json_encode(-1, JsonFormat::Json, Some(1)).unwrap(), "{\n \"a\": 1,\n \"b\": [\n  true,\n  null\n ],\n \"d\": null\n}");
+        assert_eq!(engine.json_encode(-1, JsonFormat::Jx, None).unwrap(), "{a:1,b:[true,null],c:undefined,d:NaN}");
+        assert_eq!(engine.json_encode(-1, JsonFormat::Jc, None).unwrap(), r#"{"a":1,"b":[true,null],"c":{"_undef":true},"d":{"_nan":true}}"#);
+        assert_eq!(engine.get_top(), 1);
+    }
+
+    #[test]
+    fn test_json_encode_error() {
+        let engine = JsEngine::new().unwrap();
+        //language=js
+        engine.eval("var a = {}; a.self = a; a").unwrap();
+        assert!(engine.json_encode(-1, JsonFormat::Json, None).is_err());
+        assert!(engine.json_encode(-1, JsonFormat::Json, None).unwrap_err().to_string().contains("TypeError"));
+
+        engine.push_undefined();
+        assert!(engine.json_encode(-1, JsonFormat::Json, None).is_err());
+        assert!(engine.json_encode(-2, JsonFormat::Json, Some(11)).is_err());
+        assert_eq!(engine.get_top(), 2);
+    }
+
+    #[test]
+    fn test_json_decode() {
+        let engine = JsEngine::new().unwrap();
+        engine.json_decode(r#"{"a": [1, 2]}"#, JsonFormat::Json).unwrap();
+        engine.json_decode("{a:undefined,b:NaN,c:|0102|}", JsonFormat::Jx).unwrap();
+        engine.json_decode(r#"{"a":{"_inf":true}}"#, JsonFormat::Jc).unwrap();
+        engine.put_global_string("jc");
+        engine.put_global_string("jx");
+        engine.put_global_string("json");
+
+        //language=js
+        engine.eval("json.a[1] === 2 && jx.a === undefined && isNaN(jx.b) && jx.c.length === 2 && jc.a._inf === true").unwrap();
+        assert!(engine.get_boolean(-1));
+        engine.pop();
+
+        assert!(engine.json_decode("{invalid", JsonFormat::Json).is_err());
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_json_codec_ignores_script_bindings() {
+        let engine = JsEngine::new().unwrap();
+        //language=js
+        engine.eval("JSON.stringify = Duktape.enc = Duktape.dec = function() { return 'hijacked'; }; ({a: [1]})").unwrap();
+        assert_eq!(engine.json_encode(-1, JsonFormat::Json, Some(1)).unwrap(), "{\n \"a\": [\n  1\n ]\n}");
+        assert_eq!(engine.json_encode(-1, JsonFormat::Jx, None).unwrap(), "{a:[1]}");
+        engine.json_decode("{a:NaN}", JsonFormat::Jx).unwrap();
+        assert!(engine.get_prop_string(-1, "a"));
+        assert!(engine.get_number(-1).is_nan());
+        engine.pop_n(2);
+
+        assert!(engine.json_encode(5, JsonFormat::Json, None).is_err());
+        assert!(engine.cbor_encode(5).is_err());
+        assert_eq!(engine.get_top(), 1);
+    }
+
+    #[test]
+    fn test_cbor_roundtrip() {
+        let engine = JsEngine::new().unwrap();
+        //language=js
+        engine.eval("({name: 'state', counters: [1, 2.5, -3], flag: false})").unwrap();
+        let data = engine.cbor_encode(-1).unwrap();
+        assert_eq!(data[0], 0xa3);
+        engine.pop();
+
+        engine.cbor_decode(&data).unwrap();
+        assert_eq!(engine.json_encode(-1, JsonFormat::Json, None).unwrap(), r#"{"name":"state","counters":[1,2.5,-3],"flag":false}"#);
+        engine.pop();
+
+        assert!(engine.cbor_decode(&[0xff, 0x00]).is_err());
+        assert_eq!(engine.get_top(), 0);
+    }
+
     #[test]
     fn tes_propagate_js_error() {
         let engine = JsEngine::new().unwrap();
@@ -784,5 +1316,25 @@ mod tests {
         let err = res.unwrap_err();
         assert!(err.to_string().contains("test error"));
     }
-}
 
+    #[test]
+    fn test_dump_function() {
+        let engine = JsEngine::new().unwrap();
+        engine.compile_file("dump.js", "function twice(x) { return 2 * x; }\ntwice(21)").unwrap();
+        let bytecode = engine.dump_function(-1).unwrap();
+        assert_eq!(bytecode[0], 0xbf);
+        assert_eq!(engine.get_top(), 1);
+
+        engine.push_function("native", 0);
+        assert!(engine.dump_function(-1).is_err());
+        assert_eq!(engine.get_top(), 2);
+
+        let other = JsEngine::new().unwrap();
+        unsafe { other.load_function(&bytecode).unwrap(); }
+        other.pcall(0).unwrap();
+        assert_eq!(other.get_number(-1), 42.);
+        other.pop();
+        assert!(unsafe { other.load_function(&[0x00]) }.is_err());
+        assert_eq!(other.get_top(), 0);
+    }
+}
@@ -37,6 +37,7 @@ impl<'de, 'a> Deserializer<'de> for JsEngineDeserializer<'a> {
                     visitor.visit_f64(n)
                 }
             }
+            DUK_TYPE_STRING if self.ctx.is_symbol(self.index) => visitor.visit_str(&self.ctx.key_string(self.index)),
             DUK_TYPE_STRING => visitor.visit_str(self.ctx.get_string(self.index)),
             DUK_TYPE_BUFFER => visitor.visit_bytes(self.ctx.get_buffer(self.index)),
             DUK_TYPE_OBJECT => {
@@ -52,10 +53,10 @@ impl<'de, 'a> Deserializer<'de> for JsEngineDeserializer<'a> {
                     self.ctx.pop();
                     res
                 } else {
-                    return Err(JsError::from(format!("Unimplemented javascript object type"))); //FIXME (jc)
+                    Err(JsError::from("Unimplemented javascript object type".to_string())) //FIXME (jc)
                 }
             }
-            _ => return Err(JsError::from(format!("Unimplemented javascript object type"))) //FIXME (jc),
+            _ => Err(JsError::from("Unimplemented javascript object type".to_string())) //FIXME (jc),
         }
     }
 
@@ -274,6 +275,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default, clippy::approx_constant)]
     fn read_struct() {
         let mut p = TestStruct::default();
         p.char_field = 'B';
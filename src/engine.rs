@@ -1,13 +1,16 @@
+use std::any::Any;
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::ops::{Deref, DerefMut};
 use std::os::raw::c_void;
 use std::pin::Pin;
+use std::sync::{Arc, Weak};
 use once_cell::sync::Lazy;
 use smallbox::{SmallBox, smallbox};
 use smallbox::space::S8;
-use crate::bindings::{alloc_func, duk_api_git_branch, duk_api_git_commit, duk_api_git_describe, duk_api_version, duk_create_heap, duk_destroy_heap, fatal_handler, free_func, realloc_func};
+use crate::bindings::{alloc_func, duk_api_get_heap_udata, duk_api_git_branch, duk_api_git_commit, duk_api_git_describe, duk_api_version, duk_create_heap, duk_destroy_heap, fatal_handler, duk_context, free_func, realloc_func};
 use crate::ctx::{DukContext};
-use crate::{NoopInterop, JsInterop, JsError};
+use crate::{NoopInterop, JsInterop, JsError, Sampler, SourceMap};
 
 // using SmallBox with trait pointer to avoid generics in JsEngine definition
 pub (crate) type InteropRef = SmallBox<dyn JsInterop, S8>;
@@ -15,12 +18,71 @@ pub (crate) type InteropRef = SmallBox<dyn JsInterop, S8>;
 #[derive(Debug)]
 pub (crate) struct Userdata {
     pub (crate) interop: InteropRef,
+    pub (crate) memory: MemoryUsage,
+    /// Panic caught in a callback, resumed by [`DukContext::resume_panic`].
+    pub (crate) panic: Option<Box<dyn Any + Send>>,
+    /// Message of the error thrown outside of a protected call that poisoned the heap.
+    pub (crate) poisoned: Option<String>,
+    /// Source maps by file name of the generated code.
+    pub (crate) source_maps: HashMap<String, Arc<SourceMap>>,
+    /// Call stack sampler of a running profiler.
+    pub (crate) profiler: Option<Box<Sampler>>,
+    /// Set by [`DukContext::terminate_execution`], makes every interrupt throw.
+    pub (crate) terminating: bool,
+}
+
+/// Tracks the total size of live Duktape heap allocations.
+///
+/// Sizes of reallocated and freed blocks are queried with [`JsInterop::alloc_size`].
+#[derive(Debug, Default)]
+pub (crate) struct MemoryUsage {
+    total: usize,
+}
+
+impl MemoryUsage {
+    pub (crate) fn alloc(&mut self, size: usize) {
+        self.total += size;
+    }
+
+    pub (crate) fn realloc(&mut self, old_size: usize, size: usize) {
+        self.total = self.total - old_size + size;
+    }
+
+    pub (crate) fn free(&mut self, size: usize) {
+        self.total -= size;
+    }
 }
 
 #[derive(Debug)]
 pub struct JsEngine {
     ctx: DukContext,
     inner: Pin<Box<Userdata>>,
+    /// Dropped after the heap is destroyed, see [`HeapHandle`].
+    alive: Arc<()>,
+}
+
+/// Reference to the heap of a [`JsEngine`] that does not borrow the engine, used by values
+/// living in the heap stash such as [`Realm`](crate::Realm).
+#[derive(Debug, Clone)]
+pub (crate) struct HeapHandle {
+    ctx: *mut duk_context,
+    alive: Weak<()>,
+}
+
+impl HeapHandle {
+    /// Returns the context of the engine, `None` if the engine was dropped.
+    pub (crate) fn ctx(&self) -> Option<DukContext> {
+        if self.alive.strong_count() > 0 {
+            Some(unsafe { DukContext::from_raw(self.ctx) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if `ctx` belongs to this heap.
+    pub (crate) fn owns(&self, ctx: &DukContext) -> bool {
+        self.ctx().is_some_and(|heap| unsafe { duk_api_get_heap_udata(heap.ctx) == duk_api_get_heap_udata(ctx.ctx) })
+    }
 }
 
 
@@ -38,6 +100,12 @@ impl JsEngine {
     pub fn with_interop<I: JsInterop>(interop: I) -> Result<Self, JsError> {
         let userdata = Box::pin(Userdata {
             interop: smallbox!(interop),
+            memory: MemoryUsage::default(),
+            panic: None,
+            poisoned: None,
+            source_maps: HashMap::new(),
+            profiler: None,
+            terminating: false,
         });
         let udata = &(*userdata.as_ref()) as *const Userdata;
 
@@ -57,6 +125,7 @@ impl JsEngine {
         let e = JsEngine {
             ctx: unsafe { DukContext::from_raw(ctx) },
             inner: userdata,
+            alive: Arc::new(()),
         };
 
         Ok(e)
@@ -79,11 +148,16 @@ impl JsEngine {
                     &(CStr::from_ptr(duk_api_git_commit()).to_str().unwrap())[0..9])
             }
         });
-        &*DUK_VERSION_INFO
+        &DUK_VERSION_INFO
+    }
+
+    /// Returns the number of bytes currently allocated by the Duktape heap.
+    pub fn heap_size(&self) -> usize {
+        self.inner.memory.total
     }
 
     pub fn interop(&self) -> Pin<&dyn JsInterop> {
-        unsafe { self.inner.as_ref().map_unchecked(|r| &*((*r).interop)) }
+        unsafe { self.inner.as_ref().map_unchecked(|r| &*r.interop) }
     }
 
     pub fn interop_as<I: JsInterop>(&self) -> Pin<&I> {
@@ -91,7 +165,7 @@ impl JsEngine {
     }
 
     pub fn interop_mut(&mut self) -> Pin<&mut dyn JsInterop> {
-        unsafe { self.inner.as_mut().map_unchecked_mut(|r| &mut *((*r).interop)) }
+        unsafe { self.inner.as_mut().map_unchecked_mut(|r| &mut *r.interop) }
     }
 
     pub fn interop_as_mut<I: JsInterop>(&mut self) -> Pin<&mut I> {
@@ -101,6 +175,13 @@ impl JsEngine {
     pub fn ctx(&mut self) -> &mut DukContext {
         &mut self.ctx
     }
+
+    pub (crate) fn handle(&self) -> HeapHandle {
+        HeapHandle {
+            ctx: self.ctx.ctx,
+            alive: Arc::downgrade(&self.alive),
+        }
+    }
 }
 
 impl Drop for JsEngine {
@@ -128,7 +209,7 @@ impl DerefMut for JsEngine {
 
 #[cfg(test)]
 mod tests {
-    use crate::JsEngine;
+    use crate::{DukType, JsEngine, JsonFormat, Realm, Return};
 
     #[test]
     fn test_trait_bounds() {
@@ -148,6 +229,23 @@ mod tests {
         assert_eq!(version_info, "03d4d72-dirty (HEAD/03d4d728f)");
     }
 
+    #[test]
+    fn test_heap_size() {
+        let engine = JsEngine::new().unwrap();
+        let initial = engine.heap_size();
+        assert!(initial > 0);
+
+        //language=js
+        engine.eval("var big = []; for (var i = 0; i < 10000; i++) big.push({i: i});").unwrap();
+        engine.pop();
+        assert!(engine.heap_size() > initial + 100000);
+
+        engine.eval("big = null").unwrap();
+        engine.pop();
+        engine.gc();
+        assert!(engine.heap_size() < initial + 100000);
+    }
+
     #[test]
     fn test_move_to_other_thread() {
         let mut engine = JsEngine::new().unwrap();
@@ -166,4 +264,105 @@ mod tests {
         engine.pop_n(2);
         assert!(engine.get_stack_dump().contains("ctx: top=0"));
     }
-}
\ No newline at end of file
+
+    const THROWING_GETTER: &str = "var o = { get x() { throw new Error('boom'); } }";
+
+    #[test]
+    fn test_uncaught_error_poisons_engine() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval(THROWING_GETTER).unwrap();
+        engine.pop();
+        engine.get_global_string("o");
+        assert!(!engine.is_poisoned());
+
+        assert!(!engine.get_prop_string(-1, "x"));
+        assert!(engine.is_poisoned());
+        assert_eq!(engine.get_top(), 2);
+        assert_eq!(engine.get_type(-1), DukType::DUK_TYPE_UNDEFINED);
+
+        let err = engine.eval("1 + 1").unwrap_err();
+        assert!(err.is_poisoned());
+        assert_eq!(String::from(err), "Error: boom");
+        assert!(engine.compile("1 + 1").unwrap_err().is_poisoned());
+        assert_eq!(engine.pcall(0), Err(crate::DUK_API_POISONED));
+
+        // property accesses are skipped, keeping the stack balanced
+        engine.push_i32(1);
+        engine.put_prop_string(-3, "y");
+        assert!(!engine.get_prop_string(-2, "y"));
+        assert_eq!(engine.get_top(), 3);
+
+        // so are decodes, which fail before pushing their input
+        assert!(engine.json_decode("{}", JsonFormat::Json).unwrap_err().is_poisoned());
+        assert!(engine.cbor_decode(&[0xa0]).unwrap_err().is_poisoned());
+        assert_eq!(engine.get_top(), 3);
+    }
+
+    #[test]
+    fn test_uncaught_error_in_enum_poisons_engine() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("new Proxy({}, { ownKeys: function() { throw new Error('boom'); } })").unwrap();
+        engine.enum_keys(-1);
+        assert!(engine.is_poisoned());
+        assert!(!engine.next(-1));
+        assert_eq!(engine.get_top(), 2);
+    }
+
+    #[test]
+    fn test_uncaught_error_in_length_poisons_engine() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("new Proxy([1, 2], { get: function(t, k) { if (k === 'length') throw new Error('boom'); return t[k]; } })").unwrap();
+        assert_eq!(engine.get_length(-1), 0);
+        assert!(engine.is_poisoned());
+        assert_eq!(engine.get_top(), 1);
+        assert_eq!(engine.get_length(-1), 0);
+    }
+
+    #[test]
+    fn test_uncaught_throw_poisons_engine() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_string("boom");
+        engine.throw();
+        assert!(engine.is_poisoned());
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_error_in_callback_is_catchable() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval(THROWING_GETTER).unwrap();
+        engine.pop();
+        engine.push_closure(1, |ctx| {
+            ctx.get_prop_string(0, "x");
+            Ok(Return::Top)
+        });
+        engine.put_global_string("get");
+        engine.eval("try { get(o); } catch (e) { 'caught ' + e.message }").unwrap();
+        assert_eq!(engine.get_string(-1), "caught boom");
+        assert!(!engine.is_poisoned());
+    }
+
+    #[test]
+    fn test_poison_does_not_affect_other_engines() {
+        let first = JsEngine::new().unwrap();
+        let second = JsEngine::new().unwrap();
+        second.eval("var x = 1").unwrap();
+        first.eval(THROWING_GETTER).unwrap();
+        first.get_global_string("o");
+        first.get_prop_string(-1, "x");
+        assert!(first.is_poisoned());
+        let realm = Realm::new(&first);
+        assert!(realm.unwrap_err().is_poisoned());
+        second.eval("x + 1").unwrap();
+        assert_eq!(second.get_number(-1), 2.0);
+    }
+
+    #[test]
+    fn test_compile_error() {
+        let engine = JsEngine::new().unwrap();
+        assert!(engine.compile("var = ;").is_err());
+        assert!(engine.compile_file("broken.js", "function {").is_err());
+        assert_eq!(engine.get_top(), 0);
+        assert!(!engine.is_poisoned());
+    }
+}
@@ -1,11 +1,30 @@
-
 //FIXME embed javascript error type
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct JsError(String);
+pub struct JsError {
+    message: String,
+    poisoned: bool,
+}
+
+impl JsError {
+    pub(crate) fn poisoned(message: String) -> Self {
+        JsError { message, poisoned: true }
+    }
+
+    /// Returns `true` if an error was thrown outside of a protected call, see
+    /// [`DukContext::is_poisoned`](crate::DukContext::is_poisoned). The engine should be dropped;
+    /// every later protected call fails with this error.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
 
 impl std::fmt::Display for JsError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Error: {}", self.0)
+        if self.poisoned {
+            write!(f, "Engine poisoned by uncaught error: {}", self.message)
+        } else {
+            write!(f, "Error: {}", self.message)
+        }
     }
 }
 
@@ -13,12 +32,12 @@ impl std::error::Error for JsError {}
 
 impl From<String> for JsError {
     fn from(s: String) -> Self {
-        JsError(s)
+        JsError { message: s, poisoned: false }
     }
 }
 
-impl Into<String> for JsError {
-    fn into(self) -> String {
-        self.0
+impl From<JsError> for String {
+    fn from(e: JsError) -> Self {
+        e.message
     }
 }
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use super::*;
+
+/// Duktape object class names by class number (`DUK_HOBJECT_CLASS_*`, internal to Duktape).
+const CLASS_NAMES: [&str; 30] = [
+    "None", "Object", "Array", "Function", "Arguments", "Boolean", "Date", "Error", "JSON", "Math",
+    "Number", "RegExp", "String", "global", "Symbol", "ObjEnv", "DecEnv", "Pointer", "Thread",
+    "ArrayBuffer", "DataView", "Int8Array", "Uint8Array", "Uint8ClampedArray", "Int16Array",
+    "Uint16Array", "Int32Array", "Uint32Array", "Float32Array", "Float64Array",
+];
+
+// class numbers are private to Duktape, recheck them when updating it
+const _: () = assert!(matches!(env!("DUK_VERSION").as_bytes(), b"20700"), "CLASS_NAMES is written against Duktape 2.7.0");
+
+pub(crate) fn class_name(class: usize) -> &'static str {
+    CLASS_NAMES.get(class).copied().unwrap_or("Unknown")
+}
+
+/// Number and total size of heap allocated values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HeapCount {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl std::ops::AddAssign for HeapCount {
+    fn add_assign(&mut self, other: HeapCount) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Heap counters returned by [`JsEngine::heap_stats`].
+///
+/// Byte counts are computed like in [`DukContext::inspect_value`] and do not include allocator
+/// overhead; [`HeapStats::allocated_bytes`] is the total allocated by the heap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// All objects, including functions and threads.
+    pub objects: HeapCount,
+    /// Objects by Duktape class, e.g. `Array`, `Function` or `DecEnv` for scopes captured by
+    /// closures. Only classes with live objects are listed.
+    pub classes: BTreeMap<&'static str, HeapCount>,
+    pub strings: HeapCount,
+    /// Plain buffers, including the bytecode of compiled functions.
+    pub buffers: HeapCount,
+    /// Objects whose finalizer has not run yet.
+    pub finalize_pending: usize,
+    /// Objects whose refcount dropped to zero and are being freed.
+    pub refzero_pending: usize,
+    /// Allocations left until the next voluntary mark-and-sweep collection.
+    pub gc_countdown: Option<u32>,
+    pub allocated_bytes: usize,
+}
+
+/// Internal information about a value, see [`DukContext::inspect_value`].
+///
+/// Fields not applicable to the value type are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueInfo {
+    pub value_type: DukType,
+    /// Address of the heap allocated value, usable as an identity.
+    pub heap_ptr: Option<usize>,
+    pub refcount: Option<u32>,
+    /// Duktape class of an object.
+    pub class: Option<&'static str>,
+    /// Size of the heap header and of fixed buffer or string data.
+    pub heap_bytes: Option<u32>,
+    /// Size of the property table of an object.
+    pub property_bytes: Option<u32>,
+    /// Size of the entry part of the property table.
+    pub entry_size: Option<u32>,
+    /// Number of used entries, including deleted ones.
+    pub entry_next: Option<u32>,
+    /// Size of the array part of the property table.
+    pub array_size: Option<u32>,
+    /// Size of the hash part of the property table.
+    pub hash_size: Option<u32>,
+    /// Size of the bytecode of a compiled function.
+    pub bytecode_bytes: Option<u32>,
+    /// Size of the data of a dynamic buffer.
+    pub data_bytes: Option<u32>,
+}
+
+impl DukContext {
+    /// Returns internal information about the value at `index`, like `Duktape.info()`.
+    pub fn inspect_value(&self, index: i32) -> ValueInfo {
+        let heap_ptr = unsafe { duk_get_heapptr(self.ctx, index) };
+        unsafe { duk_inspect_value(self.ctx, index) };
+        let field = |key: &str| -> Option<u32> {
+            let value = if self.get_prop_string(-1, key) && self.is_number(-1) {
+                Some(self.get_number(-1) as u32)
+            } else {
+                None
+            };
+            self.pop();
+            value
+        };
+        let info = ValueInfo {
+            value_type: DukType::from(field("type").unwrap_or(0) as i32),
+            heap_ptr: (!heap_ptr.is_null()).then_some(heap_ptr as usize),
+            refcount: field("refc"),
+            class: field("class").map(|c| class_name(c as usize)),
+            heap_bytes: field("hbytes"),
+            property_bytes: field("pbytes"),
+            entry_size: field("esize"),
+            entry_next: field("enext"),
+            array_size: field("asize"),
+            hash_size: field("hsize"),
+            bytecode_bytes: field("bcbytes"),
+            data_bytes: field("dbytes"),
+        };
+        self.pop();
+        info
+    }
+}
+
+impl JsEngine {
+    /// Counts the values allocated in the heap, including garbage not collected yet.
+    pub fn heap_stats(&self) -> HeapStats {
+        let mut info = duk_api_heap_info::default();
+        unsafe { duk_api_heap_stats(self.ctx, &mut info) };
+        let mut stats = HeapStats {
+            strings: HeapCount { count: info.strings.count, bytes: info.strings.bytes },
+            buffers: HeapCount { count: info.buffers.count, bytes: info.buffers.bytes },
+            finalize_pending: info.finalize_pending,
+            refzero_pending: info.refzero_pending,
+            gc_countdown: u32::try_from(info.gc_countdown).ok(),
+            allocated_bytes: self.heap_size(),
+            ..HeapStats::default()
+        };
+        for (class, item) in info.classes.iter().enumerate().filter(|(_, item)| item.count > 0) {
+            let count = HeapCount { count: item.count, bytes: item.bytes };
+            stats.objects += count;
+            *stats.classes.entry(class_name(class)).or_default() += count;
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heap_stats() {
+        let engine = JsEngine::new().unwrap();
+        engine.gc();
+        let before = engine.heap_stats();
+        assert!(before.classes["global"].count >= 1);
+        assert!(before.objects.count > 10 && before.strings.count > 10);
+        assert!(before.allocated_bytes > before.objects.bytes);
+
+        engine.eval("var leak = []; for (var i = 0; i < 1000; i++) leak.push([i, 'item' + i, function () { return i; }]);").unwrap();
+        engine.pop();
+        let after = engine.heap_stats();
+        assert!(after.classes["Array"].count >= before.classes["Array"].count + 1000);
+        assert!(after.classes["Function"].count >= before.classes["Function"].count + 1000);
+        assert!(after.strings.count >= before.strings.count + 1000);
+        assert!(after.objects.bytes > before.objects.bytes);
+
+        engine.eval("leak = null;").unwrap();
+        engine.pop();
+        engine.gc();
+        assert!(engine.heap_stats().classes["Array"].count < before.classes["Array"].count + 10);
+    }
+
+    #[test]
+    fn test_inspect_value() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var o = {}; for (var i = 0; i < 100; i++) o['k' + i] = i; for (i = 0; i < 100; i++) delete o['k' + i]; o").unwrap();
+        let info = engine.inspect_value(-1);
+        assert_eq!(info.value_type, DukType::DUK_TYPE_OBJECT);
+        assert_eq!(info.class, Some("Object"));
+        assert!(info.heap_ptr.is_some());
+        assert!(info.refcount.unwrap() >= 1);
+        assert!(info.entry_next.unwrap() >= 100);
+        let property_bytes = info.property_bytes.unwrap();
+
+        engine.gc_with(DukGcFlags::DUK_GC_COMPACT);
+        let info = engine.inspect_value(-1);
+        assert!(info.property_bytes.unwrap() < property_bytes);
+        assert_eq!(info.entry_next, Some(0));
+        engine.pop();
+
+        engine.push_string("text");
+        let info = engine.inspect_value(-1);
+        assert_eq!(info.value_type, DukType::DUK_TYPE_STRING);
+        assert_eq!(info.class, None);
+        assert!(info.heap_bytes.unwrap() > 4);
+        engine.pop();
+
+        engine.compile("function f(a) { return a * 2; }").unwrap();
+        assert_eq!(engine.inspect_value(-1).class, Some("Function"));
+        engine.pop();
+
+        engine.push_i32(1);
+        let info = engine.inspect_value(-1);
+        assert_eq!(info.value_type, DukType::DUK_TYPE_NUMBER);
+        assert_eq!((info.heap_ptr, info.refcount), (None, None));
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+    }
+}
@@ -1,29 +1,77 @@
-use crate::{ConsoleFunc, DukContext, JsError, Return};
+use crate::{ConsoleArgs, ConsoleFunc, DukContext, JsError, Return};
 use log::log;
 use std::any::TypeId;
 
+/// Host side of a [`JsEngine`](crate::JsEngine), called back by the engine.
+///
+/// Methods are called reentrantly: the allocation methods run whenever the heap allocates, also
+/// while another method runs JavaScript, and [`JsInterop::call`] runs again when JavaScript
+/// it runs calls another native function. Implementations must not keep references into their
+/// own state across calls that run JavaScript or allocate on the heap.
 pub trait JsInterop: std::any::Any + std::fmt::Debug + 'static {
     fn call(&mut self, engine: &mut DukContext, func_name: &str) -> Result<Return, JsError>;
 
+    /// Allocates memory for the Duktape heap.
+    ///
+    /// # Safety
+    /// Implementations must return memory that stays valid until passed to [`JsInterop::free`].
     unsafe fn alloc(&mut self, size: usize) -> *mut u8 {
         super::alloc::alloc(size)
     }
 
+    /// Resizes memory previously allocated for the Duktape heap.
+    ///
+    /// # Safety
+    /// `ptr` must be null or a pointer returned by this interop's `alloc` or `realloc`.
     unsafe fn realloc(&mut self, ptr: *mut u8, size: usize) -> *mut u8 {
         super::alloc::realloc(ptr, size)
     }
 
+    /// Releases memory previously allocated for the Duktape heap.
+    ///
+    /// # Safety
+    /// `ptr` must be null or a pointer returned by this interop's `alloc` or `realloc`.
     unsafe fn free(&mut self, ptr: *mut u8) {
         super::alloc::free(ptr)
     }
 
+    /// Returns the size requested for memory allocated for the Duktape heap, used to track
+    /// [`JsEngine::heap_size`](crate::JsEngine::heap_size), since Duktape does not pass sizes
+    /// to `realloc` and `free`.
+    ///
+    /// # Safety
+    /// `ptr` must be a live pointer returned by this interop's `alloc` or `realloc`.
+    /// Implementations overriding the allocation methods with another allocator must override
+    /// this as well.
+    unsafe fn alloc_size(&mut self, ptr: *mut u8) -> usize {
+        super::alloc::size(ptr)
+    }
+
+    /// Handles an unrecoverable Duktape error, e.g. an internal assertion; must not return.
+    ///
+    /// Errors thrown outside of a protected call do not end up here, they poison the engine
+    /// instead, see [`DukContext::is_poisoned`](crate::DukContext::is_poisoned).
+    ///
+    /// A panic cannot unwind through Duktape, so when this panics, as the default does, the
+    /// error is logged and the process is aborted. Implementations that must keep the process
+    /// alive have to diverge otherwise, e.g. by parking the thread.
     fn fatal(&mut self, msg: &str) -> ! {
         panic!("Duktape fatal error: {}", msg);
     }
 
+    /// Receives formatted console messages.
+    ///
+    /// Logs the message through the `log` crate. With the `tracing` feature enabled, console
+    /// calls are also emitted as `tracing` events before this is called.
     fn console(&mut self, func: ConsoleFunc, msg: &str) {
         log!(func.level(), "JS: {}", msg);
     }
+
+    /// Receives console calls with their original arguments when the console was
+    /// initialized with [`DukContext::init_console_structured`].
+    fn console_args(&mut self, func: ConsoleFunc, args: &ConsoleArgs) {
+        self.console(func, &args.message());
+    }
 }
 
 impl dyn JsInterop {
@@ -55,10 +103,12 @@ impl JsInterop for NoopInterop {
 }
 
 #[cfg(test)]
+#[allow(clippy::drop_non_drop)]
 mod tests {
     use std::sync::Arc;
     use crate::JsEngine;
     use super::*;
+    use crate::test_util::DropFlag;
 
     #[derive(Debug)]
     struct Interop {
@@ -219,15 +269,16 @@ mod tests {
     fn test_eval_allocations() {
         let engine = init();
         let tracker = engine.interop_as::<Interop>().tracker.clone();
-        assert_eq!(tracker.lock().unwrap().total_bytes(), 102591);
+        assert_eq!(tracker.lock().unwrap().total_bytes(), 103374);
 
         //language=javascript
         engine.eval(r#"100 + 2"#).unwrap();
         assert_eq!(engine.get_number(-1), 102.);
-        assert_eq!(tracker.lock().unwrap().total_bytes(), 102591);
+        assert_eq!(tracker.lock().unwrap().total_bytes(), 103470);
 
         engine.gc();
-        assert_eq!(tracker.lock().unwrap().total_bytes(), 101686);
+        assert_eq!(tracker.lock().unwrap().total_bytes(), 103374);
+        assert_eq!(engine.heap_size(), 103374);
 
         drop(engine);
 
@@ -235,6 +286,192 @@ mod tests {
     }
 
 
+    #[derive(Debug, Default)]
+    struct PanicInterop {
+        panic_alloc: bool,
+    }
+
+    impl JsInterop for PanicInterop {
+        fn call(&mut self, _ctx: &mut DukContext, func_name: &str) -> Result<Return, JsError> {
+            match func_name {
+                "boom" => panic!("boom"),
+                "arm_alloc" => {
+                    self.panic_alloc = true;
+                    Ok(Return::Undefined)
+                }
+                _ => Ok(Return::Undefined),
+            }
+        }
+
+        unsafe fn alloc(&mut self, size: usize) -> *mut u8 {
+            if self.panic_alloc && size >= 1 << 20 {
+                self.panic_alloc = false;
+                panic!("alloc refused");
+            }
+            crate::alloc::alloc(size)
+        }
+
+        fn console(&mut self, _func: ConsoleFunc, msg: &str) {
+            if msg == "panic" {
+                panic!("console panic");
+            }
+        }
+    }
+
+    fn panic_engine() -> JsEngine {
+        let e = JsEngine::with_interop(PanicInterop::default()).unwrap();
+        e.init_console();
+        e.put_global_function("boom", 0);
+        e.put_global_function("arm_alloc", 0);
+        e
+    }
+
+    fn expect_panic<T: std::fmt::Debug, F: FnOnce() -> T>(f: F) -> String {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_err();
+        crate::bindings::panic_message(&*payload).to_string()
+    }
+
+    fn assert_consistent(e: &JsEngine) {
+        e.set_top(0);
+        e.gc();
+        e.eval("var o = {a: [1, 2, 3]}; o.a.map(function(x) { return x * 2; }).join()").unwrap();
+        assert_eq!(e.get_string(-1), "2,4,6");
+        e.pop();
+        assert_eq!(e.get_top(), 0);
+    }
+
+    #[test]
+    fn test_panic_in_call() {
+        let e = panic_engine();
+        let msg = expect_panic(|| e.eval("var caught; try { boom(); } catch (err) { caught = String(err); }"));
+        assert_eq!(msg, "boom");
+        assert_consistent(&e);
+
+        // script saw the panic as a thrown error
+        e.eval("caught").unwrap();
+        assert_eq!(e.get_string(-1), "Rust panic: boom");
+        e.pop();
+    }
+
+    #[test]
+    fn test_panic_in_pcall() {
+        let e = panic_engine();
+        e.eval("(function() { return boom(); })").unwrap();
+        let msg = expect_panic(|| e.pcall(0));
+        assert_eq!(msg, "boom");
+        assert_consistent(&e);
+
+        // panic is not re-raised twice
+        e.eval("1").unwrap();
+        e.pop();
+    }
+
+    #[test]
+    fn test_panic_in_console() {
+        let e = panic_engine();
+        let msg = expect_panic(|| e.eval_file("test.js", "console.log('panic')"));
+        assert_eq!(msg, "console panic");
+        assert_consistent(&e);
+    }
+
+    #[test]
+    fn test_panic_in_alloc() {
+        let e = panic_engine();
+        let msg = expect_panic(|| e.eval("arm_alloc(); var b = new Uint8Array(2 << 20); b.length"));
+        assert_eq!(msg, "alloc refused");
+        assert_consistent(&e);
+    }
+
+    #[derive(Debug, Default)]
+    struct DataInterop;
+
+    impl JsInterop for DataInterop {
+        fn call(&mut self, ctx: &mut DukContext, func_name: &str) -> Result<Return, JsError> {
+            match func_name {
+                "scale" => {
+                    let factor = ctx.current_function_data(|factor: &f64| *factor).unwrap();
+                    ctx.push_number(ctx.get_number(0) * factor);
+                    Ok(Return::Top)
+                }
+                "magic" => {
+                    ctx.push_i32(ctx.current_magic());
+                    Ok(Return::Top)
+                }
+                "plain" => {
+                    ctx.push_boolean(ctx.current_function_data(|_: &f64| ()).is_none());
+                    Ok(Return::Top)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn put_function_data(e: &JsEngine, obj: &str, factor: f64) {
+        e.push_global_object();
+        e.push_object();
+        e.push_function_data("scale", 1, factor);
+        e.put_prop_string(-2, "scale");
+        e.put_prop_string(-2, obj);
+        e.pop();
+    }
+
+    #[test]
+    fn test_function_renamed() {
+        let e = JsEngine::with_interop(DataInterop).unwrap();
+        e.put_global_function("plain", 0);
+        e.eval("Object.defineProperty(plain, 'name', { value: 'other' }); [plain.name, plain()]").unwrap();
+        e.get_prop_index(-1, 0);
+        assert_eq!(e.get_string(-1), "other");
+        e.get_prop_index(-2, 1);
+        assert!(e.get_boolean(-1));
+        e.pop_n(3);
+    }
+
+    #[test]
+    fn test_function_data() {
+        let e = JsEngine::with_interop(DataInterop).unwrap();
+        put_function_data(&e, "double", 2.);
+        put_function_data(&e, "triple", 3.);
+        e.eval("double.scale(5) + ',' + triple.scale(5)").unwrap();
+        assert_eq!(e.get_string(-1), "10,15");
+        e.pop();
+    }
+
+    #[test]
+    fn test_function_magic() {
+        let e = JsEngine::with_interop(DataInterop).unwrap();
+        e.push_global_object();
+        for (name, magic) in [("a", 1), ("b", -7)] {
+            e.push_function_magic("magic", 0, magic);
+            e.put_prop_string(-2, name);
+        }
+        e.pop();
+        e.eval("a() + ',' + b()").unwrap();
+        assert_eq!(e.get_string(-1), "1,-7");
+        e.pop();
+        assert_eq!(e.current_magic(), 0);
+        assert!(e.current_function_data(|_: &f64| ()).is_none());
+    }
+
+    #[test]
+    fn test_function_data_dropped() {
+        let collected = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let destroyed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        {
+            let e = JsEngine::with_interop(DataInterop).unwrap();
+            e.push_function_data("plain", 0, DropFlag(collected.clone()));
+            e.pop();
+            e.gc();
+            assert!(collected.load(std::sync::atomic::Ordering::SeqCst));
+
+            e.push_function_data("plain", 0, DropFlag(destroyed.clone()));
+            e.put_global_string("kept");
+            e.gc();
+            assert!(!destroyed.load(std::sync::atomic::Ordering::SeqCst));
+        }
+        assert!(destroyed.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
     pub mod alloc_tracker {
         use std::collections::HashMap;
 
@@ -271,6 +508,7 @@ mod tests {
                 self.allocs.values().sum()
             }
 
+            #[allow(dead_code)]
             pub fn alloc_count(&self) -> u64 {
                 self.alloc_count
             }
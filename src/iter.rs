@@ -0,0 +1,238 @@
+use std::marker::PhantomData;
+use super::*;
+
+impl DukContext {
+    /// Returns an iterator over elements of the array at `obj_index`, in index order.
+    ///
+    /// Holes are skipped. The enumerator occupies a value stack slot until the iterator is dropped.
+    pub fn array_iter<T: ReadJs>(&self, obj_index: i32) -> Result<ArrayIter<'_, T>, JsError> {
+        let flags = DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY
+            | DukEnumFlags::DUK_ENUM_ARRAY_INDICES_ONLY
+            | DukEnumFlags::DUK_ENUM_SORT_ARRAY_INDICES;
+        Ok(ArrayIter { inner: Enumerator::new(self, obj_index, flags)?, marker: PhantomData })
+    }
+
+    /// Returns an iterator over own enumerable string-keyed properties of the object at `obj_index`.
+    pub fn entries<T: ReadJs>(&self, obj_index: i32) -> Result<Entries<'_, T>, JsError> {
+        self.entries_with(obj_index, DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY)
+    }
+
+    /// Returns an iterator over properties of the object at `obj_index` selected by `flags`.
+    ///
+    /// Symbol keys are rendered as `Symbol(description)`, hidden symbols by their name.
+    pub fn entries_with<T: ReadJs>(&self, obj_index: i32, flags: DukEnumFlags) -> Result<Entries<'_, T>, JsError> {
+        Ok(Entries { inner: Enumerator::new(self, obj_index, flags)?, marker: PhantomData })
+    }
+
+    /// Returns keys of the properties of the object at `obj_index` selected by `flags`.
+    pub(crate) fn keys_with(&self, obj_index: i32, flags: DukEnumFlags) -> Result<Vec<String>, JsError> {
+        let mut enumerator = Enumerator::new(self, obj_index, flags)?;
+        let mut keys = Vec::new();
+        while let Some(res) = enumerator.next() {
+            res?;
+            keys.push(self.key_string(-2));
+            self.pop_n(2);
+        }
+        Ok(keys)
+    }
+
+    /// Returns the property key at `index` as a string, rendering symbols like `String(symbol)`.
+    pub(crate) fn key_string(&self, index: i32) -> String {
+        if !self.is_symbol(index) {
+            return self.get_string(index).to_string();
+        }
+        let desc = self.get_symbol_description(index).unwrap_or_default();
+        if self.is_hidden_symbol(index) {
+            desc
+        } else {
+            format!("Symbol({})", desc)
+        }
+    }
+}
+
+/// Duktape enumerator on the value stack, removed when dropped.
+struct Enumerator<'a> {
+    ctx: &'a DukContext,
+    index: i32,
+    done: bool,
+}
+
+impl<'a> Enumerator<'a> {
+    fn new(ctx: &'a DukContext, obj_index: i32, flags: DukEnumFlags) -> Result<Self, JsError> {
+        let res = ctx.enum_raw(obj_index, flags.bits());
+        ctx.propagate_js_error(res)?;
+        Ok(Enumerator { ctx, index: ctx.normalize_index(-1), done: false })
+    }
+
+    /// Pushes the next key and value, or returns `None` when exhausted.
+    fn next(&mut self) -> Option<Result<(), JsError>> {
+        if self.done {
+            return None;
+        }
+        match self.ctx.next_raw(self.index) {
+            Ok(true) => Some(Ok(())),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(self.ctx.propagate_js_error(Err(err)))
+            }
+        }
+    }
+}
+
+impl Drop for Enumerator<'_> {
+    fn drop(&mut self) {
+        if !self.ctx.is_poisoned() {
+            self.ctx.remove(self.index);
+        }
+    }
+}
+
+/// Iterator over array elements, see [`DukContext::array_iter`].
+pub struct ArrayIter<'a, T> {
+    inner: Enumerator<'a>,
+    marker: PhantomData<T>,
+}
+
+impl<T: ReadJs> Iterator for ArrayIter<'_, T> {
+    type Item = Result<T, JsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.inner.next()? {
+            return Some(Err(err));
+        }
+        let ctx = self.inner.ctx;
+        let value = T::read_js_top(ctx);
+        ctx.pop_n(2);
+        Some(value)
+    }
+}
+
+/// Iterator over object properties, see [`DukContext::entries`].
+pub struct Entries<'a, T> {
+    inner: Enumerator<'a>,
+    marker: PhantomData<T>,
+}
+
+impl<T: ReadJs> Iterator for Entries<'_, T> {
+    type Item = Result<(String, T), JsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.inner.next()? {
+            return Some(Err(err));
+        }
+        let ctx = self.inner.ctx;
+        let key = ctx.key_string(-2);
+        let value = T::read_js_top(ctx);
+        ctx.pop_n(2);
+        Some(value.map(|value| (key, value)))
+    }
+}
+
+impl DukContext {
+    pub(crate) fn enum_raw(&self, obj_index: i32, flags: u32) -> Result<(), i32> {
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe { duk_api_enum(self.ctx, obj_index, flags) };
+        self.resume_panic();
+        if res != DUK_EXEC_SUCCESS {
+            return Err(res);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next_raw(&self, enum_index: i32) -> Result<bool, i32> {
+        if self.is_poisoned() {
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe { duk_api_next(self.ctx, enum_index) };
+        self.resume_panic();
+        if res != DUK_EXEC_SUCCESS {
+            // error is followed by two undefined values
+            self.pop_n(2);
+            return Err(res);
+        }
+        let found = self.get_boolean(-1);
+        self.pop();
+        if !found {
+            self.pop_n(2);
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(engine: &JsEngine, code: &str) {
+        engine.eval(code).unwrap();
+    }
+
+    #[test]
+    fn test_array_iter() {
+        let engine = JsEngine::new().unwrap();
+        eval(&engine, "var a = [3, 1, 2]; a[5] = 9; a.extra = 7; a");
+        let values: Vec<f64> = engine.array_iter(-1).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(values, vec![3., 1., 2., 9.]);
+        assert_eq!(engine.get_top(), 1);
+
+        // stopping early removes the enumerator too
+        let first = engine.array_iter::<f64>(0).unwrap().next().unwrap().unwrap();
+        assert_eq!(first, 3.);
+        assert_eq!(engine.get_top(), 1);
+    }
+
+    #[test]
+    fn test_entries() {
+        let engine = JsEngine::new().unwrap();
+        eval(&engine, "var o = Object.create({ inherited: 1 }); o.b = 'x'; o.a = 'y'; \
+            Object.defineProperty(o, 'hidden', { value: 'z' }); o[Symbol('sym')] = 's'; o");
+        let entries: Vec<(String, String)> = engine.entries(-1).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, vec![("b".to_string(), "x".to_string()), ("a".to_string(), "y".to_string())]);
+
+        let flags = DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY
+            | DukEnumFlags::DUK_ENUM_INCLUDE_NONENUMERABLE
+            | DukEnumFlags::DUK_ENUM_INCLUDE_SYMBOLS;
+        let keys: Vec<String> = engine.entries_with::<String>(-1, flags).unwrap()
+            .map(|e| e.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["b", "a", "hidden", "Symbol(sym)"]);
+
+        let keys: Vec<String> = engine.entries_with::<f64>(-1, DukEnumFlags::empty()).unwrap()
+            .filter_map(|e| e.ok().map(|(k, _)| k))
+            .collect();
+        assert_eq!(keys, vec!["inherited"]);
+        assert_eq!(engine.get_top(), 1);
+    }
+
+    #[test]
+    fn test_entries_errors() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_undefined();
+        assert!(engine.entries::<f64>(-1).is_err());
+        engine.pop();
+
+        eval(&engine, "({ a: 1, get b() { throw new Error('getter failed'); }, c: 3 })");
+        let entries: Vec<_> = engine.entries::<f64>(-1).unwrap().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].as_ref().unwrap(), &("a".to_string(), 1.));
+        assert!(entries[1].as_ref().unwrap_err().to_string().contains("getter failed"));
+        assert_eq!(engine.get_top(), 1);
+    }
+
+    #[test]
+    fn test_proxy_modes() {
+        let engine = JsEngine::new().unwrap();
+        eval(&engine, "var target = { t: 1 }; new Proxy(target, { ownKeys: function() { return ['t', 'p']; } })");
+        let keys = |flags| -> Vec<String> {
+            engine.entries_with::<f64>(-1, flags).unwrap().map(|e| e.unwrap().0).collect()
+        };
+        assert_eq!(keys(DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY), vec!["t"]);
+        assert!(keys(DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY | DukEnumFlags::DUK_ENUM_NO_PROXY_BEHAVIOR).is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Conversion between [`serde_json::Value`] and JavaScript values.
+//!
+//! [`Value`] is written through its [`WriteJs`] implementation, which pushes it
+//! straight onto the Duktape value stack; this is several times faster than serializing it and
+//! running `JSON.parse`, see `benches/json.rs`. [`DukContext::get_json_value`] reads it back
+//! following the mapping below, with all properties of an object or elements of an array read
+//! in a single protected call. Reading a [`Value`] through its generic
+//! [`ReadJs`] implementation works as for other serde types instead.
+//!
+//! Mapping from JSON to JavaScript:
+//!
+//! | JSON                 | JavaScript                                                     |
+//! |----------------------|----------------------------------------------------------------|
+//! | `null`               | `null`                                                         |
+//! | boolean              | boolean                                                        |
+//! | number               | number, integers beyond ±2^53 are rounded to the nearest double |
+//! | string               | string                                                         |
+//! | array                | array                                                          |
+//! | object               | object, keys in map order                                      |
+//!
+//! Mapping from JavaScript to JSON:
+//!
+//! | JavaScript                                   | JSON                                    |
+//! |----------------------------------------------|-----------------------------------------|
+//! | `undefined`, `null`                          | `null`                                  |
+//! | boolean                                      | boolean                                 |
+//! | integral number within the `i64` range       | integer                                 |
+//! | `-0`                                         | integer `0`                             |
+//! | other finite number                          | float                                   |
+//! | `NaN`, `Infinity`, `-Infinity`               | `null`                                  |
+//! | string                                       | string                                  |
+//! | symbol                                       | string `Symbol(description)`            |
+//! | plain buffer, `ArrayBuffer`, typed array     | array of byte values                    |
+//! | array                                        | array (holes become `null`)             |
+//! | function, pointer                            | `null`                                  |
+//! | other object                                 | object with own enumerable string keys  |
+//!
+//! As with `JSON.stringify`, object properties whose value is `undefined` or a function are
+//! omitted. Object keys are visited in JavaScript enumeration order; whether that order survives
+//! depends on `serde_json`'s `preserve_order` feature. Cyclic structures are rejected once
+//! [`MAX_DEPTH`] nesting levels are exceeded.
+
+use serde_json::{Map, Number, Value};
+use super::*;
+
+/// Maximum nesting depth of values read by [`DukContext::get_json_value`].
+pub const MAX_DEPTH: usize = 1000;
+
+impl DukContext {
+    /// Converts the value at `index` to [`serde_json::Value`], leaving the stack unchanged.
+    ///
+    /// Errors thrown by getters or proxy traps are returned.
+    pub fn get_json_value(&self, index: i32) -> Result<Value, JsError> {
+        self.check_poisoned()?;
+        let index = self.normalize_index(index);
+        read_value(self, index, 0)
+    }
+}
+
+fn read_value(ctx: &DukContext, index: i32, depth: usize) -> Result<Value, JsError> {
+    use DukType::*;
+
+    let value = match ctx.get_type(index) {
+        DUK_TYPE_BOOLEAN => Value::Bool(ctx.get_boolean(index)),
+        DUK_TYPE_NUMBER => read_number(ctx.get_number(index)),
+        DUK_TYPE_STRING if ctx.is_symbol(index) => Value::String(ctx.key_string(index)),
+        DUK_TYPE_STRING => Value::String(ctx.get_string(index).to_string()),
+        DUK_TYPE_BUFFER => read_bytes(ctx.get_buffer_data(index)),
+        DUK_TYPE_OBJECT if ctx.is_function(index) => Value::Null,
+        DUK_TYPE_OBJECT if ctx.is_buffer_data(index) => read_bytes(ctx.get_buffer_data(index)),
+        DUK_TYPE_OBJECT => {
+            if depth >= MAX_DEPTH {
+                return Err(JsError::from(format!("JSON value nesting exceeds {} levels", MAX_DEPTH)));
+            }
+            let mut count = 0;
+            let res = unsafe { duk_api_push_entries(ctx.ctx, index, &mut count) };
+            ctx.propagate_js_error(if res == DUK_EXEC_SUCCESS { Ok(()) } else { Err(res) })?;
+            let base = ctx.get_top() - count as i32;
+            let value = if ctx.is_array(index) {
+                (base..ctx.get_top())
+                    .map(|i| read_value(ctx, i, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Value::Array)
+            } else {
+                read_entries(ctx, base, depth)
+            };
+            ctx.set_top(base);
+            value?
+        }
+        _ => Value::Null,
+    };
+    Ok(value)
+}
+
+/// Reads keys and values pushed from index `base` up to the stack top into an object.
+fn read_entries(ctx: &DukContext, base: i32, depth: usize) -> Result<Value, JsError> {
+    let mut map = Map::new();
+    for i in (base..ctx.get_top()).step_by(2) {
+        let value = i + 1;
+        if ctx.get_type(value) == DukType::DUK_TYPE_UNDEFINED || ctx.is_function(value) {
+            continue;
+        }
+        map.insert(ctx.get_string(i).to_string(), read_value(ctx, value, depth + 1)?);
+    }
+    Ok(Value::Object(map))
+}
+
+fn read_number(n: f64) -> Value {
+    if n.trunc() == n && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn read_bytes(data: &[u8]) -> Value {
+    Value::Array(data.iter().map(|b| Value::from(*b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::*;
+
+    fn eval_json(engine: &JsEngine, code: &str) -> Value {
+        engine.eval(code).unwrap();
+        let value = engine.get_json_value(-1).unwrap();
+        engine.pop();
+        value
+    }
+
+    #[test]
+    fn roundtrip() {
+        let engine = JsEngine::new().unwrap();
+        let value = json!({
+            "int": 1,
+            "neg": -17,
+            "big": 9007199254740991i64,
+            "float": 12.5,
+            "str": "zażółć",
+            "bool": true,
+            "null": null,
+            "arr": [1, [2, 3], {"a": "b"}],
+            "obj": {"nested": {"deep": []}},
+        });
+        engine.write(&value).unwrap();
+        let back = engine.get_json_value(-1).unwrap();
+        engine.pop();
+        assert_eq!(back, value);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn write_is_visible_to_scripts() {
+        let engine = JsEngine::new().unwrap();
+        engine.write(&json!({"a": [1, 2.5, "x"], "b": null})).unwrap();
+        engine.put_global_string("value");
+        engine.eval("JSON.stringify(value)").unwrap();
+        assert_eq!(engine.get_string(-1), r#"{"a":[1,2.5,"x"],"b":null}"#);
+        engine.pop();
+    }
+
+    #[test]
+    fn read_numbers() {
+        let engine = JsEngine::new().unwrap();
+        assert_eq!(eval_json(&engine, "[1, 1.0, 1.5, -0, 2e53, NaN, Infinity, -Infinity]"),
+                   json!([1, 1, 1.5, 0, 2e53, null, null, null]));
+        assert!(eval_json(&engine, "1.0").is_i64());
+        assert!(eval_json(&engine, "0.5").is_f64());
+        assert_eq!(eval_json(&engine, "Math.pow(2, 60)"), json!(1u64 << 60));
+        assert!(eval_json(&engine, "1e300").is_f64());
+    }
+
+    #[test]
+    fn read_non_json_values() {
+        let engine = JsEngine::new().unwrap();
+        let value = eval_json(&engine, r#"({
+            u: undefined,
+            f: function() {},
+            arr: [undefined, function() {}, , 1],
+            sym: Symbol('tag'),
+            buf: new Uint8Array([1, 2, 255]),
+            ab: new Uint8Array([7, 8]).buffer
+        })"#);
+        assert_eq!(value, json!({
+            "arr": [null, null, null, 1],
+            "sym": "Symbol(tag)",
+            "buf": [1, 2, 255],
+            "ab": [7, 8],
+        }));
+        assert_eq!(eval_json(&engine, "undefined"), Value::Null);
+    }
+
+    #[test]
+    fn read_preserves_stack() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("({a: {b: [1, 2, {c: 3}]}})").unwrap();
+        let top = engine.get_top();
+        engine.get_json_value(-1).unwrap();
+        assert_eq!(engine.get_top(), top);
+    }
+
+    #[test]
+    fn read_cycle_fails() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var a = {x: [1]}; a.x.push(a); a").unwrap();
+        let top = engine.get_top();
+        let err = engine.get_json_value(-1).unwrap_err();
+        assert!(err.to_string().contains("nesting"));
+        assert_eq!(engine.get_top(), top);
+    }
+
+    #[test]
+    fn read_errors() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("({a: 1, get b() { throw new Error('boom'); }})").unwrap();
+        let err = engine.get_json_value(-1).unwrap_err();
+        assert!(err.to_string().contains("boom"));
+        engine.eval("new Proxy([1], {get: function(t, k) { if (k === 'length') throw new Error('length'); return t[k]; }})").unwrap();
+        let err = engine.get_json_value(-1).unwrap_err();
+        assert!(err.to_string().contains("length"));
+        assert_eq!(engine.get_top(), 2);
+        assert!(!engine.is_poisoned());
+    }
+}
@@ -5,29 +5,83 @@ use std::os::raw::*;
 mod bindings;
 use self::bindings::*;
 
-pub use self::bindings::DukType;
+pub use self::bindings::{DukCompileFlags, DukEnumFlags, DukGcFlags, DukType};
+pub use callstack::*;
+pub use clone::*;
+pub use compile::*;
 pub use console::*;
 pub use ctx::*;
 pub use engine::*;
+pub use inspect::*;
 pub use interop::*;
+pub use iter::*;
+pub use lint::*;
 pub use error::*;
+pub use pool::*;
+pub use profiler::*;
+pub use property::*;
+pub use proxy::*;
+pub use realm::*;
+pub use script::*;
+pub use snapshot::*;
+pub use sourcemap::*;
+pub use symbol::*;
 
+mod callstack;
+mod clone;
+mod compile;
 mod console;
 mod ctx;
 mod engine;
 pub mod alloc;
+mod inspect;
 mod interop;
+mod iter;
+mod lint;
 mod error;
+mod pool;
+mod profiler;
+mod property;
+mod proxy;
+mod realm;
+mod script;
+mod snapshot;
+mod sourcemap;
+mod symbol;
+#[cfg(test)]
+mod test_util;
 
 #[cfg(feature = "serde")]
 pub mod ser;
 #[cfg(feature = "serde")]
 pub mod de;
+#[cfg(feature = "serde_json")]
+pub mod json;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "cli")]
+pub mod runner;
 
-const FUNC_NAME_PROP: &[u8] = b"name";
+const FUNC_NAME_PROP: &str = "name";
+/// Hidden property holding the registration name used for dispatch, unaffected by changes of
+/// the visible `name`.
+const FUNC_ID_PROP: &str = "func_id";
+/// Hidden property holding a pointer to data attached with [`DukContext::push_function_data`].
+const FUNC_DATA_PROP: &str = "func_data";
 
 const DUK_EXEC_SUCCESS: i32 = 0;
 
+/// Result code of protected calls on a poisoned heap, see [`DukContext::is_poisoned`].
+const DUK_API_POISONED: i32 = -1;
+
+const DUK_API_PROP_GET: u32 = 0;
+const DUK_API_PROP_PUT: u32 = 1;
+const DUK_API_PROP_DEL: u32 = 2;
+const DUK_API_PROP_HAS: u32 = 3;
+const DUK_API_PROP_LENGTH: u32 = 4;
+
 pub trait ReadJs {
     fn read_js(ctx: &DukContext, obj_index: i32) -> Result<Self, JsError>
     where
@@ -60,3 +114,17 @@ pub enum Return {
     UriError = -7,
 }
 
+/// Text encoding formats supported by Duktape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum JsonFormat {
+    /// Standard JSON, as produced by `JSON.stringify()`.
+    Json = 0,
+    /// Duktape JX, a readable extension of JSON that also round-trips `undefined`, `NaN`,
+    /// infinities, buffers and functions.
+    Jx = 1,
+    /// Duktape JC, a JSON compatible format that encodes non-JSON values as plain objects.
+    /// Decoding JC is the same as decoding standard JSON.
+    Jc = 2,
+}
+
@@ -0,0 +1,558 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use super::*;
+
+/// Options for [`DukContext::check`].
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    strict: bool,
+    globals: Option<HashSet<String>>,
+}
+
+impl CheckOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles code as strict mode code, rejecting e.g. `with` statements.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Reports references to undeclared variables, unless they are properties of the global
+    /// object or listed in `allowed`.
+    ///
+    /// Names are resolved statically, so names bound by `with` statements or declared by
+    /// `eval()` code are reported too. The check decodes the compiled bytecode, whose format
+    /// is private to Duktape, so it depends on the Duktape version (2.7.0) kg-js is built with.
+    pub fn allowed_globals<I: IntoIterator<Item = S>, S: Into<String>>(mut self, allowed: I) -> Self {
+        self.globals.get_or_insert_with(HashSet::new).extend(allowed.into_iter().map(Into::into));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    SyntaxError,
+    UnknownGlobal,
+    /// The file could not be read.
+    Io,
+}
+
+impl DiagnosticKind {
+    /// Returns the identifier used in JSON output, e.g. `syntax-error`.
+    pub fn code(self) -> &'static str {
+        match self {
+            DiagnosticKind::SyntaxError => "syntax-error",
+            DiagnosticKind::UnknownGlobal => "unknown-global",
+            DiagnosticKind::Io => "io-error",
+        }
+    }
+}
+
+/// Problem found by [`DukContext::check`].
+///
+/// Lines and columns start at 1. Duktape reports syntax errors by line only, so `column` is
+/// `None` for them; `line` is `0` when unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if self.line > 0 {
+            write!(f, ":{}", self.line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": error[{}]: {}", self.kind.code(), self.message)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Diagnostic {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Diagnostic", 5)?;
+        s.serialize_field("file", &self.file)?;
+        s.serialize_field("line", &(self.line > 0).then_some(self.line))?;
+        s.serialize_field("column", &self.column)?;
+        s.serialize_field("kind", self.kind.code())?;
+        s.serialize_field("message", &self.message)?;
+        s.end()
+    }
+}
+
+/// Formats `diagnostics` as an indented JSON array of objects with `file`, `line`, `column`,
+/// `kind` and `message` properties. Unknown lines and columns are `null`.
+#[cfg(feature = "serde_json")]
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> String {
+    serde_json::to_string_pretty(diagnostics).expect("diagnostics are serializable")
+}
+
+/// Formats `diagnostics` as an indented JSON array of objects with `file`, `line`, `column`,
+/// `kind` and `message` properties. Unknown lines and columns are `null`.
+#[cfg(not(feature = "serde_json"))]
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> String {
+    use std::fmt::Write;
+
+    fn string(out: &mut String, s: &str) {
+        out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                '\u{8}' => out.push_str("\\b"),
+                '\u{c}' => out.push_str("\\f"),
+                c if c < ' ' => { let _ = write!(out, "\\u{:04x}", c as u32); }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    fn number(out: &mut String, n: Option<u32>) {
+        match n {
+            Some(n) => { let _ = write!(out, "{}", n); }
+            None => out.push_str("null"),
+        }
+    }
+
+    if diagnostics.is_empty() {
+        return "[]".to_string();
+    }
+    let mut out = String::from("[");
+    for (i, d) in diagnostics.iter().enumerate() {
+        out.push_str(if i == 0 { "\n  {\n    \"file\": " } else { ",\n  {\n    \"file\": " });
+        string(&mut out, &d.file);
+        out.push_str(",\n    \"line\": ");
+        number(&mut out, (d.line > 0).then_some(d.line));
+        out.push_str(",\n    \"column\": ");
+        number(&mut out, d.column);
+        out.push_str(",\n    \"kind\": ");
+        string(&mut out, d.kind.code());
+        out.push_str(",\n    \"message\": ");
+        string(&mut out, &d.message);
+        out.push_str("\n  }");
+    }
+    out.push_str("\n]");
+    out
+}
+
+impl DukContext {
+    /// Compiles `code` without running it and returns the problems found.
+    ///
+    /// Compilation stops at the first syntax error, so at most one syntax error is reported,
+    /// without a column. Errors are returned only when the engine fails; the value stack is
+    /// left unchanged.
+    pub fn check(&self, filename: &str, code: &str, options: &CheckOptions) -> Result<Vec<Diagnostic>, JsError> {
+        let compile_options = CompileOptions::new().filename(filename).strict(options.strict);
+        let msg = match self.compile_with(code, &compile_options) {
+            Ok(()) => {
+                let bytecode = self.dump_function(-1);
+                self.pop();
+                let Some(ref allowed) = options.globals else {
+                    return Ok(Vec::new());
+                };
+                let func = Function::parse(&bytecode?)?;
+                let mut globals = self.global_names()?;
+                globals.extend(allowed.iter().cloned());
+                let lines: Vec<&str> = code.lines().collect();
+                let mut refs = Vec::new();
+                func.unknown_globals(&mut vec![globals], true, &mut refs);
+                let mut diagnostics: Vec<Diagnostic> = refs.into_iter()
+                    .map(|(name, line)| Diagnostic {
+                        file: filename.to_string(),
+                        line,
+                        column: lines.get((line as usize).wrapping_sub(1)).and_then(|l| find_identifier(l, &name)),
+                        kind: DiagnosticKind::UnknownGlobal,
+                        message: format!("'{}' is not defined", name),
+                    })
+                    .collect();
+                // report the first reference of each name
+                diagnostics.sort_by_key(|d| (d.line, d.column));
+                let mut reported = HashSet::new();
+                diagnostics.retain(|d| reported.insert(d.message.clone()));
+                return Ok(diagnostics);
+            }
+            Err(err) if err.is_poisoned() => return Err(err),
+            Err(err) => String::from(err),
+        };
+        let (message, line) = split_line_suffix(&msg);
+        Ok(vec![Diagnostic {
+            file: filename.to_string(),
+            line,
+            column: None,
+            kind: DiagnosticKind::SyntaxError,
+            message: message.strip_prefix("SyntaxError: ").unwrap_or(message).to_string(),
+        }])
+    }
+
+    /// Checks the files at `paths`, see [`DukContext::check`]. Unreadable files are reported
+    /// as [`DiagnosticKind::Io`].
+    pub fn check_files<P: AsRef<Path>>(&self, paths: &[P], options: &CheckOptions) -> Result<Vec<Diagnostic>, JsError> {
+        let mut diagnostics = Vec::new();
+        for path in paths {
+            let file = path.as_ref().display().to_string();
+            match std::fs::read_to_string(path) {
+                Ok(code) => diagnostics.extend(self.check(&file, &code, options)?),
+                Err(err) => diagnostics.push(Diagnostic {
+                    file,
+                    line: 0,
+                    column: None,
+                    kind: DiagnosticKind::Io,
+                    message: err.to_string(),
+                }),
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// Returns own property names of the global object, including non-enumerable ones.
+    fn global_names(&self) -> Result<HashSet<String>, JsError> {
+        self.push_global_object();
+        let flags = DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY | DukEnumFlags::DUK_ENUM_INCLUDE_NONENUMERABLE;
+        let names = self.keys_with(-1, flags);
+        self.pop();
+        Ok(names?.into_iter().collect())
+    }
+}
+
+/// Splits the ` (line N)` suffix Duktape appends to compile error messages.
+fn split_line_suffix(msg: &str) -> (&str, u32) {
+    if let Some(start) = msg.rfind(" (line ") {
+        let suffix = &msg[start + 7..];
+        if let Some(suffix) = suffix.strip_suffix(')') {
+            let digits = suffix.split(',').next().unwrap_or_default();
+            if let Ok(line) = digits.parse() {
+                return (&msg[..start], line);
+            }
+        }
+    }
+    (msg, 0)
+}
+
+/// Returns the 1-based column of the first reference to `name` in `line`.
+fn find_identifier(line: &str, name: &str) -> Option<u32> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    line.match_indices(name)
+        .find(|&(i, _)| {
+            let before = line[..i].chars().next_back();
+            let after = line[i + name.len()..].chars().next();
+            !before.is_some_and(|c| is_ident(c) || c == '.') && !after.is_some_and(is_ident)
+        })
+        .map(|(i, _)| line[..i].chars().count() as u32 + 1)
+}
+
+// Opcodes of the Duktape version in lib/duktape, see DUK_OP_* in duktape.c. The bytecode dump
+// and pc2line formats below are version specific as well.
+const _: () = assert!(matches!(env!("DUK_VERSION").as_bytes(), b"20700"), "the bytecode decoder is written against Duktape 2.7.0");
+const OP_LDCONST: u32 = 3;
+const OP_GETVAR: u32 = 11;
+const OP_DECLVAR: u32 = 144;
+const OP_TYPEOFID: u32 = 154;
+const OP_PUTVAR: u32 = 155;
+const OP_DELVAR: u32 = 156;
+const OP_TRYCATCH: u32 = 165;
+const OP_CSVAR: u32 = 172;
+const TRYCATCH_FLAG_CATCH_BINDING: u32 = 1 << 2;
+const PC2LINE_SKIP: usize = 64;
+const NO_FORMALS: u32 = 0xffff_ffff;
+
+/// Compiled function decoded from a bytecode dump, see `duk__dump_func()` in duktape.c.
+struct Function {
+    code: Vec<u32>,
+    consts: Vec<Option<String>>,
+    funcs: Vec<Function>,
+    name: String,
+    pc2line: Vec<u8>,
+    /// Register-bound variables and formal arguments.
+    locals: Vec<String>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], JsError> {
+        let bytes = self.data.get(self.pos..self.pos + n)
+            .ok_or_else(|| JsError::from("truncated bytecode".to_string()))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, JsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, JsError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, JsError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+impl Function {
+    fn parse(bytecode: &[u8]) -> Result<Function, JsError> {
+        if bytecode.first() != Some(&0xbf) {
+            return Err(JsError::from("invalid bytecode".to_string()));
+        }
+        Function::read(&mut Reader { data: bytecode, pos: 1 })
+    }
+
+    fn read(r: &mut Reader) -> Result<Function, JsError> {
+        let count_instr = r.u32()?;
+        let count_consts = r.u32()?;
+        let count_funcs = r.u32()?;
+        // nregs, nargs, start and end line, flags
+        r.bytes(2 + 2 + 4 + 4 + 4)?;
+        let code = (0..count_instr).map(|_| r.u32()).collect::<Result<_, _>>()?;
+        let consts = (0..count_consts).map(|_| match r.u8()? {
+            0 => r.string().map(Some),
+            1 => r.bytes(8).map(|_| None),
+            tag => Err(JsError::from(format!("invalid constant tag {tag}"))),
+        }).collect::<Result<_, _>>()?;
+        let funcs = (0..count_funcs).map(|_| Function::read(r)).collect::<Result<_, _>>()?;
+        // length
+        r.u32()?;
+        let name = r.string()?;
+        // fileName
+        r.string()?;
+        let len = r.u32()? as usize;
+        let pc2line = r.bytes(len)?.to_vec();
+        let mut locals = Vec::new();
+        loop {
+            let len = r.u32()? as usize;
+            if len == 0 {
+                break;
+            }
+            locals.push(String::from_utf8_lossy(r.bytes(len)?).into_owned());
+            // register
+            r.u32()?;
+        }
+        let formals = r.u32()?;
+        if formals != NO_FORMALS {
+            for _ in 0..formals {
+                locals.push(r.string()?);
+            }
+        }
+        Ok(Function { code, consts, funcs, name, pc2line, locals })
+    }
+
+    fn constant(&self, index: u32) -> Option<&str> {
+        self.consts.get(index as usize).and_then(|c| c.as_deref())
+    }
+
+    /// Returns identifier references by name of this function and the names it declares.
+    fn identifiers(&self) -> (Vec<(&str, usize)>, HashSet<&str>) {
+        let mut refs = Vec::new();
+        let mut declared: HashSet<&str> = self.locals.iter().map(String::as_str).collect();
+        declared.insert(&self.name);
+        // constants loaded into registers, for operands shuffled out of the constant range
+        let mut loaded: HashMap<u32, u32> = HashMap::new();
+        for (pc, &ins) in self.code.iter().enumerate() {
+            let (op, a, b, bc) = (ins & 0xff, (ins >> 8) & 0xff, (ins >> 16) & 0xff, ins >> 16);
+            let name_b = || if op & 1 == 1 { self.constant(b) } else { loaded.get(&b).and_then(|&c| self.constant(c)) };
+            match op {
+                OP_LDCONST => {
+                    loaded.insert(a, bc);
+                }
+                OP_GETVAR | OP_TYPEOFID | OP_PUTVAR | OP_DELVAR => refs.extend(self.constant(bc).map(|n| (n, pc))),
+                _ if (OP_DECLVAR..OP_DECLVAR + 4).contains(&op) => declared.extend(name_b()),
+                _ if (OP_CSVAR..OP_CSVAR + 4).contains(&op) => refs.extend(name_b().map(|n| (n, pc))),
+                OP_TRYCATCH if a & TRYCATCH_FLAG_CATCH_BINDING != 0 => {
+                    declared.extend(loaded.get(&bc).and_then(|&c| self.constant(c)));
+                }
+                _ => {}
+            }
+        }
+        (refs, declared)
+    }
+
+    /// Collects references to names not declared in this function, its enclosing functions
+    /// (`scopes`) or the global code.
+    fn unknown_globals(&self, scopes: &mut Vec<HashSet<String>>, global_code: bool, out: &mut Vec<(String, u32)>) {
+        let (refs, mut declared) = self.identifiers();
+        if !global_code {
+            declared.insert("arguments");
+        }
+        scopes.push(declared.into_iter().map(String::from).collect());
+        for (name, pc) in refs {
+            if !scopes.iter().any(|scope| scope.contains(name)) {
+                out.push((name.to_string(), self.pc_to_line(pc)));
+            }
+        }
+        for func in self.funcs.iter() {
+            func.unknown_globals(scopes, false, out);
+        }
+        scopes.pop();
+    }
+
+    /// Maps a bytecode offset to a source line, see `duk_hobject_pc2line_query()` in duktape.c.
+    fn pc_to_line(&self, pc: usize) -> u32 {
+        let hdr = |i: usize| self.pc2line.get(i * 4..i * 4 + 4).map(|b| u32::from_ne_bytes(b.try_into().unwrap()));
+        let index = pc / PC2LINE_SKIP;
+        let (Some(limit), Some(mut line), Some(offset)) = (hdr(0), hdr(1 + index * 2), hdr(2 + index * 2)) else {
+            return 0;
+        };
+        if pc >= limit as usize {
+            return 0;
+        }
+        let mut bits = BitReader { data: self.pc2line.get(offset as usize..).unwrap_or_default(), pos: 0, value: 0, count: 0 };
+        for _ in 0..pc % PC2LINE_SKIP {
+            if bits.read(1) == 1 {
+                if bits.read(1) == 1 {
+                    if bits.read(1) == 1 {
+                        line = (bits.read(16) << 16) | bits.read(16);
+                    } else {
+                        line = line.wrapping_add(bits.read(8)).wrapping_sub(0x80);
+                    }
+                } else {
+                    line = line.wrapping_add(bits.read(2) + 1);
+                }
+            }
+        }
+        line
+    }
+}
+
+/// Reads bits most significant first, shifting in zeroes past the end like `duk_bd_decode()`.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    value: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: u32) -> u32 {
+        while self.count < bits {
+            self.value = (self.value << 8) | *self.data.get(self.pos).unwrap_or(&0) as u32;
+            self.pos += 1;
+            self.count += 8;
+        }
+        self.count -= bits;
+        (self.value >> self.count) & ((1 << bits) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globals(code: &str, allowed: &[&str]) -> Vec<(String, u32, Option<u32>)> {
+        let engine = JsEngine::new().unwrap();
+        let options = CheckOptions::new().allowed_globals(allowed.iter().copied());
+        let diagnostics = engine.check("test.js", code, &options).unwrap();
+        assert_eq!(engine.get_top(), 0);
+        diagnostics.into_iter()
+            .inspect(|d| assert_eq!(d.kind, DiagnosticKind::UnknownGlobal))
+            .map(|d| (d.message, d.line, d.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let engine = JsEngine::new().unwrap();
+        let diagnostics = engine.check("a.js", "var a = 1;\nvar b = a +* 2;\n", &CheckOptions::new()).unwrap();
+        assert_eq!(diagnostics, vec![Diagnostic {
+            file: "a.js".to_string(),
+            line: 2,
+            column: None,
+            kind: DiagnosticKind::SyntaxError,
+            message: "parse error".to_string(),
+        }]);
+        assert_eq!(diagnostics[0].to_string(), "a.js:2: error[syntax-error]: parse error");
+
+        let diagnostics = engine.check("b.js", "function f() {\n", &CheckOptions::new()).unwrap();
+        assert_eq!(diagnostics[0].line, 2);
+
+        let code = "with (Math) { max(1, 2); }";
+        assert!(engine.check("c.js", code, &CheckOptions::new()).unwrap().is_empty());
+        let diagnostics = engine.check("c.js", code, &CheckOptions::new().strict(true)).unwrap();
+        assert_eq!(diagnostics[0].message, "with in strict mode");
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_unknown_globals() {
+        let code = "var a = 1;\n\
+            function f(x) {\n\
+            \x20 var y = x + a + arguments.length;\n\
+            \x20 return function g() { return Math.max(y, g, missing); };\n\
+            }\n\
+            try { f(); } catch (e) { e.x = other.y + console; }\n\
+            implicit = typeof maybe;\n\
+            missing();";
+        assert_eq!(globals(code, &["console"]), vec![
+            ("'missing' is not defined".to_string(), 4, Some(47)),
+            ("'other' is not defined".to_string(), 6, Some(32)),
+            ("'implicit' is not defined".to_string(), 7, Some(1)),
+            ("'maybe' is not defined".to_string(), 7, Some(19)),
+        ]);
+        assert!(globals("console.log(a); var a;", &["console"]).is_empty());
+
+        // known false positives
+        assert_eq!(globals("with (Math) { max(1, 2); }", &[]), vec![("'max' is not defined".to_string(), 1, Some(15))]);
+        assert_eq!(globals("eval('var late = 1');\nlate;", &[]), vec![("'late' is not defined".to_string(), 2, Some(1))]);
+    }
+
+    #[test]
+    fn test_many_lines() {
+        // line changes beyond the first pc2line header entry and large line jumps
+        let mut code: String = (0..150).map(|i| format!("var v{i} = {i};\n")).collect();
+        code.push_str(&"\n".repeat(300));
+        code.push_str("v1 + unknown;\n");
+        assert_eq!(globals(&code, &[]), vec![("'unknown' is not defined".to_string(), 451, Some(6))]);
+    }
+
+    #[test]
+    fn test_check_files() {
+        let dir = std::env::temp_dir().join(format!("kg-js-lint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.js");
+        std::fs::write(&good, "var x = 1;").unwrap();
+        let bad = dir.join("bad.js");
+        std::fs::write(&bad, "var = 1;").unwrap();
+        let missing = dir.join("missing.js");
+
+        let engine = JsEngine::new().unwrap();
+        let diagnostics = engine.check_files(&[&good, &bad, &missing], &CheckOptions::new()).unwrap();
+        let kinds: Vec<_> = diagnostics.iter().map(|d| (d.file.clone(), d.kind)).collect();
+        assert_eq!(kinds, vec![
+            (bad.display().to_string(), DiagnosticKind::SyntaxError),
+            (missing.display().to_string(), DiagnosticKind::Io),
+        ]);
+
+        let json = diagnostics_to_json(&diagnostics[..1]);
+        assert_eq!(json, format!("[\n  {{\n    \"file\": \"{}\",\n    \"line\": 1,\n    \"column\": null,\n    \
+            \"kind\": \"syntax-error\",\n    \"message\": \"{}\"\n  }}\n]", bad.display(), diagnostics[0].message));
+        assert_eq!(diagnostics_to_json(&[]), "[]");
+        let escaped = Diagnostic {
+            file: "a\"b\\c".to_string(),
+            line: 0,
+            column: None,
+            kind: DiagnosticKind::Io,
+            message: "x\n\u{1}".to_string(),
+        };
+        assert!(diagnostics_to_json(&[escaped]).contains("\"a\\\"b\\\\c\",\n    \"line\": null,\n    \"column\": null,\n    \"kind\": \"io-error\",\n    \"message\": \"x\\n\\u0001\""));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
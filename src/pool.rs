@@ -0,0 +1,425 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use crate::bindings::duk_get_context;
+use crate::{DukContext, JsEngine, JsError};
+
+type EngineFactory = Box<dyn Fn() -> Result<JsEngine, JsError> + Send + Sync>;
+type RealmInit = Box<dyn Fn(&DukContext) -> Result<(), JsError> + Send + Sync>;
+type ResetHook = Box<dyn Fn(&mut JsEngine) -> Result<(), JsError> + Send + Sync>;
+
+/// How engine state is reset between checkouts.
+#[derive(Default)]
+pub enum PoolReset {
+    /// Engines are reused as they are; globals set during one checkout are visible to the next.
+    #[default]
+    None,
+    /// Each checkout runs in a fresh global environment (see [`DukContext::push_thread_new_globalenv`]),
+    /// set up by the given closure, e.g. registering functions or calling `init_console`.
+    NewGlobalEnv(RealmInit),
+    /// The hook is called when an engine is returned to the pool. An error retires the engine.
+    Hook(ResetHook),
+}
+
+impl std::fmt::Debug for PoolReset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolReset::None => f.write_str("None"),
+            PoolReset::NewGlobalEnv(_) => f.write_str("NewGlobalEnv"),
+            PoolReset::Hook(_) => f.write_str("Hook"),
+        }
+    }
+}
+
+/// Configuration of a [`JsEnginePool`].
+#[derive(Debug)]
+pub struct PoolConfig {
+    /// Number of engines created up front by [`JsEnginePool::new`].
+    pub initial_size: usize,
+    /// Maximum number of idle engines kept for reuse; engines returned above this limit are dropped.
+    pub max_idle: usize,
+    /// Retires an engine after it has been checked out this many times.
+    pub max_uses: Option<u64>,
+    /// Retires an engine when its heap size (see [`JsEngine::heap_size`]) exceeds this many bytes.
+    pub max_memory: Option<usize>,
+    pub reset: PoolReset,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            initial_size: 0,
+            max_idle: 8,
+            max_uses: None,
+            max_memory: None,
+            reset: PoolReset::None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PooledEngine {
+    engine: JsEngine,
+    uses: u64,
+    /// Value stack top right after initialization, restored on every return.
+    base_top: i32,
+}
+
+/// Pool of pre-initialized [`JsEngine`]s shared between threads.
+///
+/// Engines are built by a user-supplied initializer and handed out by [`JsEnginePool::checkout`]
+/// as RAII guards, which return the engine to the pool when dropped.
+pub struct JsEnginePool {
+    factory: EngineFactory,
+    config: PoolConfig,
+    idle: Mutex<Vec<PooledEngine>>,
+}
+
+impl std::fmt::Debug for JsEnginePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsEnginePool")
+            .field("config", &self.config)
+            .field("idle", &self.idle_count())
+            .finish()
+    }
+}
+
+impl JsEnginePool {
+    /// Creates a pool building engines with `factory`, e.g. creating the engine, calling
+    /// `init_console`, registering functions and loading library code.
+    pub fn new<F>(config: PoolConfig, factory: F) -> Result<Self, JsError>
+    where
+        F: Fn() -> Result<JsEngine, JsError> + Send + Sync + 'static,
+    {
+        let pool = JsEnginePool {
+            factory: Box::new(factory),
+            config,
+            idle: Mutex::new(Vec::new()),
+        };
+        let mut engines = Vec::with_capacity(pool.config.initial_size);
+        for _ in 0..pool.config.initial_size {
+            engines.push(pool.create()?);
+        }
+        *pool.idle.lock().unwrap() = engines;
+        Ok(pool)
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    /// Returns the number of engines currently waiting in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Takes an idle engine from the pool, creating a new one if none is available.
+    pub fn checkout(&self) -> Result<PoolGuard<'_>, JsError> {
+        let pooled = self.idle.lock().unwrap().pop();
+        let mut pooled = match pooled {
+            Some(pooled) => pooled,
+            None => self.create()?,
+        };
+        pooled.uses += 1;
+
+        let realm = match self.config.reset {
+            PoolReset::NewGlobalEnv(ref init) => {
+                let engine: &DukContext = &pooled.engine;
+                let idx = engine.push_thread_new_globalenv();
+                let realm = unsafe { DukContext::from_raw(duk_get_context(engine.ctx, idx)) };
+                if let Err(err) = init(&realm) {
+                    engine.set_top(pooled.base_top);
+                    self.checkin(pooled);
+                    return Err(err);
+                }
+                Some(realm)
+            }
+            _ => None,
+        };
+
+        Ok(PoolGuard {
+            pool: self,
+            pooled: Some(pooled),
+            realm,
+        })
+    }
+
+    fn create(&self) -> Result<PooledEngine, JsError> {
+        let engine = (self.factory)()?;
+        let base_top = engine.get_top();
+        Ok(PooledEngine {
+            engine,
+            uses: 0,
+            base_top,
+        })
+    }
+
+    fn checkin(&self, mut pooled: PooledEngine) {
+        if pooled.engine.is_poisoned() {
+            return;
+        }
+        pooled.engine.set_top(pooled.base_top);
+        if let PoolReset::Hook(ref hook) = self.config.reset {
+            if hook(&mut pooled.engine).is_err() {
+                return;
+            }
+            pooled.engine.set_top(pooled.base_top);
+        }
+        if self.config.max_uses.is_some_and(|max| pooled.uses >= max) {
+            return;
+        }
+        if self.config.max_memory.is_some_and(|max| pooled.engine.heap_size() > max) {
+            // the limit applies to live data, so collect garbage before deciding
+            pooled.engine.gc();
+            if pooled.engine.heap_size() > self.config.max_memory.unwrap() {
+                return;
+            }
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.max_idle {
+            idle.push(pooled);
+        }
+    }
+}
+
+/// Engine checked out from a [`JsEnginePool`], returned to the pool on drop.
+///
+/// Dereferences to the context scripts should run in: the fresh global environment
+/// with [`PoolReset::NewGlobalEnv`], the engine's own context otherwise.
+pub struct PoolGuard<'a> {
+    pool: &'a JsEnginePool,
+    pooled: Option<PooledEngine>,
+    realm: Option<DukContext>,
+}
+
+impl std::fmt::Debug for PoolGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolGuard")
+            .field("uses", &self.uses())
+            .finish()
+    }
+}
+
+impl PoolGuard<'_> {
+    /// Returns the underlying engine, e.g. to access its interop.
+    pub fn engine(&self) -> &JsEngine {
+        &self.pooled.as_ref().unwrap().engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut JsEngine {
+        &mut self.pooled.as_mut().unwrap().engine
+    }
+
+    /// Returns how many times the engine has been checked out, including this checkout.
+    pub fn uses(&self) -> u64 {
+        self.pooled.as_ref().unwrap().uses
+    }
+
+    /// Drops the engine instead of returning it to the pool.
+    /// Poisoned engines are always retired.
+    pub fn retire(mut self) {
+        self.realm = None;
+        self.pooled = None;
+    }
+}
+
+impl Deref for PoolGuard<'_> {
+    type Target = DukContext;
+
+    fn deref(&self) -> &Self::Target {
+        match self.realm {
+            Some(ref realm) => realm,
+            None => self.engine(),
+        }
+    }
+}
+
+impl DerefMut for PoolGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self.realm {
+            Some(ref mut realm) => realm,
+            None => self.pooled.as_mut().unwrap().engine.deref_mut(),
+        }
+    }
+}
+
+impl Drop for PoolGuard<'_> {
+    fn drop(&mut self) {
+        self.realm = None;
+        if let Some(pooled) = self.pooled.take() {
+            self.pool.checkin(pooled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    fn counting_pool(config: PoolConfig) -> (JsEnginePool, Arc<AtomicUsize>) {
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = created.clone();
+        let pool = JsEnginePool::new(config, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let engine = JsEngine::new()?;
+            //language=js
+            engine.eval("function lib_add(a, b) { return a + b; }")?;
+            engine.pop();
+            Ok(engine)
+        }).unwrap();
+        (pool, created)
+    }
+
+    #[test]
+    fn test_pool_reuses_engines() {
+        let (pool, created) = counting_pool(PoolConfig {
+            initial_size: 2,
+            ..Default::default()
+        });
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.idle_count(), 2);
+
+        for _ in 0..5 {
+            let engine = pool.checkout().unwrap();
+            engine.eval("lib_add(1, 2)").unwrap();
+            assert_eq!(engine.get_number(-1), 3.0);
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        let third = pool.checkout().unwrap();
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        assert_eq!(third.uses(), 1);
+        // stack left by the previous checkouts was cleaned up
+        assert_eq!(first.get_top(), 0);
+        drop((first, second, third));
+        assert_eq!(pool.idle_count(), 3);
+    }
+
+    #[test]
+    fn test_pool_max_idle() {
+        let (pool, _) = counting_pool(PoolConfig {
+            max_idle: 1,
+            ..Default::default()
+        });
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        drop((first, second));
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn test_pool_max_uses() {
+        let (pool, created) = counting_pool(PoolConfig {
+            max_uses: Some(2),
+            ..Default::default()
+        });
+        for _ in 0..6 {
+            pool.checkout().unwrap();
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_pool_max_memory() {
+        let (pool, created) = counting_pool(PoolConfig {
+            max_memory: Some(1024 * 1024),
+            ..Default::default()
+        });
+        {
+            let engine = pool.checkout().unwrap();
+            //language=js
+            engine.eval("var garbage = []; for (var i = 0; i < 1000; i++) garbage.push({i: i}); garbage = null;").unwrap();
+        }
+        {
+            let engine = pool.checkout().unwrap();
+            //language=js
+            engine.eval("var leak = []; for (var i = 0; i < 100000; i++) leak.push({i: i});").unwrap();
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle_count(), 0);
+        pool.checkout().unwrap();
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pool_new_globalenv() {
+        let (pool, created) = counting_pool(PoolConfig {
+            reset: PoolReset::NewGlobalEnv(Box::new(|ctx| {
+                ctx.eval("var realm_value = 1")?;
+                ctx.pop();
+                Ok(())
+            })),
+            ..Default::default()
+        });
+        for _ in 0..3 {
+            let engine = pool.checkout().unwrap();
+            //language=js
+            engine.eval("typeof leaked === 'undefined' && typeof lib_add === 'undefined' && realm_value === 1").unwrap();
+            assert!(engine.get_boolean(-1));
+            engine.eval("var leaked = true").unwrap();
+            assert_eq!(engine.engine().get_top(), 1);
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_pool_reset_hook() {
+        let (pool, created) = counting_pool(PoolConfig {
+            reset: PoolReset::Hook(Box::new(|engine| {
+                engine.eval("var state = undefined; typeof fail === 'undefined'")?;
+                if engine.get_boolean(-1) {
+                    Ok(())
+                } else {
+                    Err(JsError::from("engine tainted".to_string()))
+                }
+            })),
+            ..Default::default()
+        });
+        {
+            let engine = pool.checkout().unwrap();
+            engine.eval("var state = 1").unwrap();
+        }
+        {
+            let engine = pool.checkout().unwrap();
+            engine.eval("typeof state").unwrap();
+            assert_eq!(engine.get_string(-1), "undefined");
+            engine.eval("var fail = true").unwrap();
+        }
+        assert_eq!(pool.idle_count(), 0);
+        pool.checkout().unwrap();
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pool_retire() {
+        let (pool, _) = counting_pool(PoolConfig::default());
+        pool.checkout().unwrap().retire();
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn test_pool_threads() {
+        let (pool, created) = counting_pool(PoolConfig {
+            max_idle: 4,
+            ..Default::default()
+        });
+        let pool = Arc::new(pool);
+        let handles: Vec<_> = (0..4).map(|i| {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                for j in 0..50 {
+                    let engine = pool.checkout().unwrap();
+                    engine.eval(&format!("lib_add({}, {})", i, j)).unwrap();
+                    assert_eq!(engine.get_number(-1), (i + j) as f64);
+                }
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(created.load(Ordering::SeqCst) <= 4);
+    }
+}
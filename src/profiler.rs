@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+use super::*;
+
+/// Deepest call stack recorded by a sample, outer frames are dropped.
+const MAX_DEPTH: usize = 256;
+
+/// Call stack samples aggregated by function and line, returned by [`JsEngine::stop_profiler`].
+///
+/// Frames are identified by function name, file name and line; their `pc` is always `0`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    frames: Vec<CallFrame>,
+    /// Sample counts by call stack of frame indexes, outermost first.
+    stacks: HashMap<Vec<usize>, usize>,
+    samples: usize,
+    duration: Duration,
+}
+
+/// Samples of a frame in a [`Profile`], see [`Profile::top`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProfileEntry {
+    pub frame: CallFrame,
+    /// Samples with the frame being executed.
+    pub self_samples: usize,
+    /// Samples with the frame on the call stack.
+    pub total_samples: usize,
+}
+
+impl Profile {
+    /// Returns the number of samples taken.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns the time the profiler was running.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Writes the samples in the folded stack format of `flamegraph.pl` and `inferno`, one
+    /// `outer;...;inner count` line per distinct call stack.
+    pub fn write_folded<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut lines: Vec<_> = self.stacks.iter()
+            .map(|(stack, count)| {
+                let frames: Vec<_> = stack.iter()
+                    .map(|&frame| self.frames[frame].to_string().replace(';', ",").replace('\n', " "))
+                    .collect();
+                (frames.join(";"), *count)
+            })
+            .collect();
+        lines.sort();
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the samples in the folded stack format, see [`Profile::write_folded`].
+    pub fn folded(&self) -> String {
+        let mut out = Vec::new();
+        self.write_folded(&mut out).expect("writing to a vector cannot fail");
+        String::from_utf8(out).expect("folded stacks are valid UTF-8")
+    }
+
+    /// Returns the `n` frames with the most self samples.
+    pub fn top(&self, n: usize) -> Vec<ProfileEntry> {
+        let mut self_samples = vec![0; self.frames.len()];
+        let mut total_samples = vec![0; self.frames.len()];
+        for (stack, &count) in &self.stacks {
+            if let Some(&frame) = stack.last() {
+                self_samples[frame] += count;
+            }
+            // recursive calls count once
+            for frame in stack.iter().collect::<HashSet<_>>() {
+                total_samples[*frame] += count;
+            }
+        }
+        let mut entries: Vec<_> = self.frames.iter().enumerate()
+            .map(|(i, frame)| ProfileEntry {
+                frame: frame.clone(),
+                self_samples: self_samples[i],
+                total_samples: total_samples[i],
+            })
+            .collect();
+        entries.sort_by(|a, b| b.self_samples.cmp(&a.self_samples)
+            .then(b.total_samples.cmp(&a.total_samples))
+            .then_with(|| a.frame.to_string().cmp(&b.frame.to_string())));
+        entries.truncate(n);
+        entries
+    }
+
+    /// Writes a table of the `n` frames with the most self samples.
+    pub fn write_report<W: Write>(&self, mut out: W, n: usize) -> io::Result<()> {
+        writeln!(out, "{} samples in {:.3}s", self.samples, self.duration.as_secs_f64())?;
+        writeln!(out, "  self%    self  total%   total  function")?;
+        let percent = |count: usize| 100. * count as f64 / self.samples.max(1) as f64;
+        for entry in self.top(n) {
+            writeln!(out, "{:>6.1}% {:>7} {:>6.1}% {:>7}  {}", percent(entry.self_samples), entry.self_samples,
+                     percent(entry.total_samples), entry.total_samples, entry.frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// State of a running profiler, called from the interrupt hook of the heap.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    ctx: *mut duk_context,
+    interval: Duration,
+    start: Instant,
+    last: Option<Instant>,
+    buffer: Vec<duk_api_frame>,
+    indexes: HashMap<CallFrame, usize>,
+    profile: Profile,
+}
+
+impl Sampler {
+    pub(crate) fn interrupt(&mut self) {
+        let now = Instant::now();
+        if self.last.is_some_and(|last| now.duration_since(last) < self.interval) {
+            return;
+        }
+        self.last = Some(now);
+        let n = unsafe { duk_api_sample_callstack(self.ctx, self.buffer.as_mut_ptr(), self.buffer.len()) };
+        let mut stack = Vec::with_capacity(n);
+        for frame in self.buffer[..n].iter().rev() {
+            let frame = CallFrame {
+                function: unsafe { string(frame.name, frame.name_len) }.filter(|name| !name.is_empty()),
+                filename: unsafe { string(frame.filename, frame.filename_len) },
+                line: frame.line,
+                pc: 0,
+                // profiles report generated positions
+                original: None,
+            };
+            let frames = &mut self.profile.frames;
+            stack.push(*self.indexes.entry(frame).or_insert_with_key(|frame| {
+                frames.push(frame.clone());
+                frames.len() - 1
+            }));
+        }
+        *self.profile.stacks.entry(stack).or_default() += 1;
+        self.profile.samples += 1;
+    }
+}
+
+unsafe fn string(ptr: *const c_char, len: usize) -> Option<String> {
+    (!ptr.is_null()).then(|| String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)).into_owned())
+}
+
+impl JsEngine {
+    /// Starts sampling the call stack of running JavaScript code, discarding the samples of a
+    /// running profiler.
+    ///
+    /// Duktape interrupts bytecode execution every 256k instructions; a sample is taken on
+    /// interrupts at least `interval` after the previous one, so the effective interval is also
+    /// bounded by the execution speed. Time spent in native functions is not sampled, except
+    /// as callers of JavaScript code. When no profiler is running, interrupts return
+    /// immediately; the instruction counter behind them runs in every engine, see
+    /// `lib/duktape/PATCHES` for its measured cost.
+    pub fn start_profiler(&self, interval: Duration) {
+        register_interrupt_handler();
+        let sampler = Sampler {
+            ctx: self.ctx,
+            interval,
+            start: Instant::now(),
+            last: None,
+            buffer: vec![duk_api_frame {
+                name: std::ptr::null(),
+                name_len: 0,
+                filename: std::ptr::null(),
+                filename_len: 0,
+                line: 0,
+            }; MAX_DEPTH],
+            indexes: HashMap::new(),
+            profile: Profile::default(),
+        };
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        unsafe { (*udata).profiler = Some(Box::new(sampler)) };
+    }
+
+    /// Stops the profiler and returns its samples, `None` if no profiler is running.
+    pub fn stop_profiler(&self) -> Option<Profile> {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        let sampler = unsafe { (*udata).profiler.take() }?;
+        let mut profile = sampler.profile;
+        profile.duration = sampler.start.elapsed();
+        Some(profile)
+    }
+
+    /// Returns `true` if a profiler is running, see [`JsEngine::start_profiler`].
+    pub fn is_profiling(&self) -> bool {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        unsafe { (*udata).profiler.is_some() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOT: &str = "\
+function hot(n) {
+  var s = 0;
+  for (var i = 0; i < n; i++) {
+    s += i % 7;
+  }
+  return s;
+}
+function main() {
+  var t = 0;
+  for (var k = 0; k < 20; k++) t += hot(100000);
+  return t;
+}
+main();";
+
+    #[test]
+    fn test_profiler() {
+        let engine = JsEngine::new().unwrap();
+        assert!(engine.stop_profiler().is_none());
+        engine.start_profiler(Duration::ZERO);
+        assert!(engine.is_profiling());
+        engine.eval_file("hot.js", HOT).unwrap();
+        engine.pop();
+        let profile = engine.stop_profiler().unwrap();
+        assert!(!engine.is_profiling());
+        assert!(profile.samples() >= 10, "{} samples", profile.samples());
+
+        let top = profile.top(1);
+        assert_eq!(top[0].frame.function.as_deref(), Some("hot"));
+        assert_eq!(top[0].frame.filename.as_deref(), Some("hot.js"));
+        assert!((3..=4).contains(&top[0].frame.line));
+        assert!(top[0].self_samples * 2 > profile.samples());
+
+        let folded = profile.folded();
+        let line = folded.lines().max_by_key(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap()).unwrap();
+        assert!(line.contains("(hot.js:13);main (hot.js:10);hot (hot.js:"), "{line}");
+        let total: usize = folded.lines().map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap()).sum();
+        assert_eq!(total, profile.samples());
+
+        let mut report = Vec::new();
+        profile.write_report(&mut report, 5).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.lines().nth(2).unwrap().ends_with(&top[0].frame.to_string()), "{report}");
+        assert!(report.lines().count() <= 7);
+    }
+
+    #[test]
+    fn test_profiler_interval() {
+        let engine = JsEngine::new().unwrap();
+        engine.start_profiler(Duration::from_secs(3600));
+        engine.eval_file("hot.js", HOT).unwrap();
+        engine.pop();
+        assert_eq!(engine.stop_profiler().unwrap().samples(), 1);
+
+        // not sampled while stopped
+        engine.eval_file("hot.js", HOT).unwrap();
+        engine.pop();
+        engine.start_profiler(Duration::ZERO);
+        assert_eq!(engine.stop_profiler().unwrap().samples(), 0);
+    }
+}
@@ -0,0 +1,416 @@
+use super::*;
+
+/// Getter or setter of an accessor property.
+///
+/// Both are called with the object as `this`; a setter receives the assigned value at index `0`.
+pub enum Accessor {
+    /// Function dispatched to [`JsInterop::call`] under this name.
+    Interop(String),
+    /// Rust closure, see [`DukContext::push_closure`].
+    Closure(NativeClosure),
+}
+
+impl Accessor {
+    pub fn interop(func_name: &str) -> Self {
+        Accessor::Interop(func_name.to_string())
+    }
+
+    pub fn closure<F>(f: F) -> Self
+    where
+        F: Fn(&mut DukContext) -> Result<Return, JsError> + Send + 'static,
+    {
+        Accessor::Closure(Box::new(f))
+    }
+}
+
+impl std::fmt::Debug for Accessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Accessor::Interop(name) => f.debug_tuple("Interop").field(name).finish(),
+            Accessor::Closure(_) => f.write_str("Closure"),
+        }
+    }
+}
+
+enum PropertyValue {
+    None,
+    Write(Box<dyn WriteJs>),
+    Stack,
+}
+
+/// Property definition used with [`DukContext::define_property`], following the semantics of
+/// `Object.defineProperty()`.
+///
+/// Attributes left unspecified default to `false` for new properties and are kept unchanged
+/// for existing ones.
+pub struct PropertyDescriptor {
+    value: PropertyValue,
+    getter: Option<Accessor>,
+    setter: Option<Accessor>,
+    writable: Option<bool>,
+    enumerable: Option<bool>,
+    configurable: Option<bool>,
+}
+
+impl PropertyDescriptor {
+    /// Describes a property changing only the attributes set on it.
+    pub fn new() -> Self {
+        PropertyDescriptor {
+            value: PropertyValue::None,
+            getter: None,
+            setter: None,
+            writable: None,
+            enumerable: None,
+            configurable: None,
+        }
+    }
+
+    /// Describes a data property holding `value`.
+    pub fn value<T: WriteJs + 'static>(value: T) -> Self {
+        PropertyDescriptor { value: PropertyValue::Write(Box::new(value)), ..Self::new() }
+    }
+
+    /// Describes a data property holding the value on the stack top, which is popped
+    /// by [`DukContext::define_property`].
+    pub fn stack_value() -> Self {
+        PropertyDescriptor { value: PropertyValue::Stack, ..Self::new() }
+    }
+
+    /// Describes an accessor property computed by `getter`.
+    pub fn getter(getter: Accessor) -> Self {
+        PropertyDescriptor { getter: Some(getter), ..Self::new() }
+    }
+
+    /// Describes an accessor property that can only be assigned.
+    pub fn setter(setter: Accessor) -> Self {
+        PropertyDescriptor { setter: Some(setter), ..Self::new() }
+    }
+
+    pub fn with_setter(mut self, setter: Accessor) -> Self {
+        self.setter = Some(setter);
+        self
+    }
+
+    pub fn writable(mut self, writable: bool) -> Self {
+        self.writable = Some(writable);
+        self
+    }
+
+    pub fn enumerable(mut self, enumerable: bool) -> Self {
+        self.enumerable = Some(enumerable);
+        self
+    }
+
+    pub fn configurable(mut self, configurable: bool) -> Self {
+        self.configurable = Some(configurable);
+        self
+    }
+
+    fn flags(&self) -> DukDefpropFlags {
+        let mut flags = DukDefpropFlags::empty();
+        let attrs = [
+            (self.writable, DukDefpropFlags::DUK_DEFPROP_HAVE_WRITABLE, DukDefpropFlags::DUK_DEFPROP_WRITABLE),
+            (self.enumerable, DukDefpropFlags::DUK_DEFPROP_HAVE_ENUMERABLE, DukDefpropFlags::DUK_DEFPROP_ENUMERABLE),
+            (self.configurable, DukDefpropFlags::DUK_DEFPROP_HAVE_CONFIGURABLE, DukDefpropFlags::DUK_DEFPROP_CONFIGURABLE),
+        ];
+        for (value, have, set) in attrs {
+            if let Some(value) = value {
+                flags |= have;
+                flags.set(set, value);
+            }
+        }
+        if !matches!(self.value, PropertyValue::None) {
+            flags |= DukDefpropFlags::DUK_DEFPROP_HAVE_VALUE;
+        }
+        flags.set(DukDefpropFlags::DUK_DEFPROP_HAVE_GETTER, self.getter.is_some());
+        flags.set(DukDefpropFlags::DUK_DEFPROP_HAVE_SETTER, self.setter.is_some());
+        flags
+    }
+}
+
+impl Default for PropertyDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for PropertyDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PropertyDescriptor")
+            .field("value", &!matches!(self.value, PropertyValue::None))
+            .field("getter", &self.getter)
+            .field("setter", &self.setter)
+            .field("writable", &self.writable)
+            .field("enumerable", &self.enumerable)
+            .field("configurable", &self.configurable)
+            .finish()
+    }
+}
+
+/// Attributes of an own property, as returned by [`DukContext::get_own_property_descriptor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PropertyAttributes {
+    /// Always `false` for accessor properties.
+    pub writable: bool,
+    pub enumerable: bool,
+    pub configurable: bool,
+    pub getter: bool,
+    pub setter: bool,
+}
+
+impl PropertyAttributes {
+    pub fn is_accessor(&self) -> bool {
+        self.getter || self.setter
+    }
+}
+
+impl DukContext {
+    fn push_accessor(&self, accessor: Accessor, nargs: i32) {
+        match accessor {
+            Accessor::Interop(name) => self.push_function(&name, nargs),
+            Accessor::Closure(f) => self.push_closure(nargs, f),
+        }
+    }
+
+    /// Defines or modifies the property `key` of the object at `obj_index`, like
+    /// `Object.defineProperty()`. Fails e.g. when the property is not configurable.
+    pub fn define_property(&self, obj_index: i32, key: &str, desc: PropertyDescriptor) -> Result<(), JsError> {
+        let obj_index = self.normalize_index(obj_index);
+        let flags = desc.flags();
+        let mut nvalues = 0;
+        self.push_string(key);
+        match desc.value {
+            PropertyValue::None => {}
+            PropertyValue::Write(value) => {
+                if let Err(err) = value.write_js(self) {
+                    self.pop();
+                    return Err(err);
+                }
+                nvalues += 1;
+            }
+            PropertyValue::Stack => {
+                // key goes below the value
+                self.swap(-1, -2);
+                nvalues += 1;
+            }
+        }
+        if let Some(getter) = desc.getter {
+            self.push_accessor(getter, 0);
+            nvalues += 1;
+        }
+        if let Some(setter) = desc.setter {
+            self.push_accessor(setter, 1);
+            nvalues += 1;
+        }
+        let res = self.def_prop_raw(obj_index, flags.bits(), nvalues);
+        self.propagate_js_error(res)?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Returns attributes of the own property `key` of the object at `obj_index`, `None` if
+    /// there is no such property.
+    ///
+    /// On success, pushes exactly one value: the descriptor object returned by
+    /// `Object.getOwnPropertyDescriptor()`, holding the `value` or the `get` and `set` functions,
+    /// or `undefined` if there is no such property.
+    pub fn get_own_property_descriptor(&self, obj_index: i32, key: &str) -> Result<Option<PropertyAttributes>, JsError> {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_string(key);
+        self.get_prop_desc(obj_index)
+    }
+
+    /// Like [`DukContext::get_own_property_descriptor`], with the key on the stack top,
+    /// e.g. a symbol. The key is popped.
+    pub fn get_prop_desc(&self, obj_index: i32) -> Result<Option<PropertyAttributes>, JsError> {
+        let obj_index = self.normalize_index(obj_index);
+        let res = self.get_prop_desc_raw(obj_index);
+        self.propagate_js_error(res)?;
+        if !self.is_object(-1) {
+            return Ok(None);
+        }
+        let flag = |name: &str| {
+            self.get_prop_string(-1, name);
+            let value = self.get_boolean(-1);
+            self.pop();
+            value
+        };
+        let is_function = |name: &str| {
+            self.get_prop_string(-1, name);
+            let value = self.is_function(-1);
+            self.pop();
+            value
+        };
+        let attrs = PropertyAttributes {
+            writable: flag("writable"),
+            enumerable: flag("enumerable"),
+            configurable: flag("configurable"),
+            getter: is_function("get"),
+            setter: is_function("set"),
+        };
+        Ok(Some(attrs))
+    }
+
+    fn def_prop_raw(&self, obj_index: i32, flags: u32, nvalues: i32) -> Result<(), i32> {
+        if self.is_poisoned() {
+            self.pop_n(nvalues + 1);
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe { duk_api_def_prop(self.ctx, obj_index, flags, nvalues) };
+        self.resume_panic();
+        if res != DUK_EXEC_SUCCESS {
+            return Err(res);
+        }
+        Ok(())
+    }
+
+    fn get_prop_desc_raw(&self, obj_index: i32) -> Result<(), i32> {
+        if self.is_poisoned() {
+            self.pop();
+            return Err(DUK_API_POISONED);
+        }
+        let res = unsafe { duk_api_get_prop_desc(self.ctx, obj_index) };
+        self.resume_panic();
+        if res != DUK_EXEC_SUCCESS {
+            return Err(res);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use super::*;
+    use crate::test_util::eval_string;
+
+    #[derive(Debug, Default)]
+    struct Interop {
+        version: u32,
+    }
+
+    impl JsInterop for Interop {
+        fn call(&mut self, ctx: &mut DukContext, func_name: &str) -> Result<Return, JsError> {
+            match func_name {
+                "get_version" => {
+                    ctx.push_u32(self.version);
+                    Ok(Return::Top)
+                }
+                "set_version" => {
+                    self.version = ctx.get_number(0) as u32;
+                    Ok(Return::Undefined)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_only_value() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_global_object();
+        engine.define_property(-1, "config", PropertyDescriptor::value("prod").enumerable(true)).unwrap();
+        engine.pop();
+        assert_eq!(eval_string(&engine, "config = 'dev'; config"), "prod");
+        assert_eq!(eval_string(&engine, "'use strict'; try { config = 'dev'; } catch (e) { e.name }"), "TypeError");
+        assert_eq!(eval_string(&engine, "Object.keys(this).indexOf('config') >= 0"), "true");
+
+        engine.push_global_object();
+        let attrs = engine.get_own_property_descriptor(-1, "config").unwrap();
+        assert_eq!(attrs, Some(PropertyAttributes { enumerable: true, ..Default::default() }));
+        engine.get_prop_string(-1, "value");
+        assert_eq!(engine.get_string(-1), "prod");
+        engine.pop_n(2);
+        let err = engine.define_property(-1, "config", PropertyDescriptor::value("dev")).unwrap_err();
+        assert!(err.to_string().contains("TypeError"), "{err}");
+        assert_eq!(engine.get_own_property_descriptor(-1, "missing").unwrap(), None);
+        assert_eq!(engine.get_type(-1), DukType::DUK_TYPE_UNDEFINED);
+        engine.pop_n(2);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_symbol_key_descriptor() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var tag = Symbol('tag'), obj = {}; Object.defineProperty(obj, tag, { get: function () { return 1; } }); obj").unwrap();
+        engine.get_global_string("tag");
+        let attrs = engine.get_prop_desc(-2).unwrap().unwrap();
+        assert_eq!(attrs, PropertyAttributes { getter: true, ..Default::default() });
+        engine.get_prop_string(-1, "get");
+        engine.get_prop_string(-2, "set");
+        assert!(engine.is_function(-2));
+        assert_eq!(engine.get_type(-1), DukType::DUK_TYPE_UNDEFINED);
+        engine.pop_n(3);
+
+        engine.push_hidden_symbol("missing");
+        assert_eq!(engine.get_prop_desc(-2).unwrap(), None);
+        engine.pop_n(2);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_stack_value() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_global_object();
+        engine.eval("({ debug: true })").unwrap();
+        engine.define_property(-2, "settings", PropertyDescriptor::stack_value().writable(true)).unwrap();
+        assert_eq!(engine.get_top(), 1);
+        let attrs = engine.get_own_property_descriptor(-1, "settings").unwrap().unwrap();
+        assert!(attrs.writable && !attrs.enumerable && !attrs.is_accessor());
+        engine.get_prop_string(-1, "value");
+        assert!(engine.is_object(-1));
+        engine.pop_n(3);
+        assert_eq!(eval_string(&engine, "settings.debug"), "true");
+    }
+
+    #[test]
+    fn test_interop_accessor() {
+        let engine = JsEngine::with_interop(Interop { version: 3 }).unwrap();
+        engine.push_global_object();
+        let desc = PropertyDescriptor::getter(Accessor::interop("get_version"))
+            .with_setter(Accessor::interop("set_version"))
+            .configurable(true);
+        engine.define_property(-1, "version", desc).unwrap();
+        let attrs = engine.get_own_property_descriptor(-1, "version").unwrap().unwrap();
+        assert_eq!(attrs, PropertyAttributes { configurable: true, getter: true, setter: true, ..Default::default() });
+        engine.get_prop_string(-1, "get");
+        engine.get_prop_string(-2, "set");
+        assert!(engine.is_function(-1) && engine.is_function(-2));
+        engine.pop_n(4);
+
+        assert_eq!(eval_string(&engine, "version = version + 1; version"), "4");
+        assert_eq!(engine.interop_as::<Interop>().version, 4);
+    }
+
+    #[test]
+    fn test_lazy_closure_accessor() {
+        let engine = JsEngine::new().unwrap();
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        engine.push_global_object();
+        let getter = Accessor::closure(move |ctx| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            ctx.push_this();
+            ctx.push_string("computed");
+            ctx.define_property(-2, "lazy", PropertyDescriptor::stack_value())?;
+            ctx.get_prop_string(-1, "lazy");
+            Ok(Return::Top)
+        });
+        engine.define_property(-1, "lazy", PropertyDescriptor::getter(getter).configurable(true)).unwrap();
+        engine.pop();
+
+        assert_eq!(eval_string(&engine, "lazy + ',' + lazy"), "computed,computed");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_closure_error() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_global_object();
+        let getter = Accessor::closure(|_| Err(JsError::from("no access".to_string())));
+        engine.define_property(-1, "secret", PropertyDescriptor::getter(getter)).unwrap();
+        engine.pop();
+        assert_eq!(eval_string(&engine, "try { secret } catch (e) { String(e) }"), "Error: no access");
+    }
+}
@@ -0,0 +1,417 @@
+use std::any::Any;
+use super::*;
+
+/// Trap names in magic order, with the number of arguments Duktape passes to each.
+const TRAPS: [(&str, i32); 8] = [
+    ("get", 3),
+    ("set", 4),
+    ("has", 2),
+    ("deleteProperty", 2),
+    ("ownKeys", 1),
+    ("apply", 3),
+    ("construct", 3),
+    ("getOwnPropertyDescriptor", 2),
+];
+
+const TRAP_GET: i32 = 0;
+const TRAP_SET: i32 = 1;
+const TRAP_HAS: i32 = 2;
+const TRAP_DELETE_PROPERTY: i32 = 3;
+const TRAP_OWN_KEYS: i32 = 4;
+const TRAP_APPLY: i32 = 5;
+const TRAP_CONSTRUCT: i32 = 6;
+const TRAP_GET_OWN_PROPERTY_DESCRIPTOR: i32 = 7;
+
+/// Implements the traps of a proxy created with [`DukContext::push_proxy`].
+///
+/// Property traps receive string keys; symbol keys go to the `*_symbol` traps, with the key on
+/// the value stack. Defaults describe an empty, read-only object. The proxy target stays empty.
+///
+/// Traps are called reentrantly when a trap runs JavaScript that accesses the same proxy, like
+/// [`JsInterop`](crate::JsInterop) methods; handlers must not keep references into their own
+/// state across such calls.
+pub trait ProxyHandler: Send + 'static {
+    /// Pushes the value of `key` and returns [`Return::Top`].
+    fn get(&mut self, _ctx: &mut DukContext, _key: &str) -> Result<Return, JsError> {
+        Ok(Return::Undefined)
+    }
+
+    /// Assigns the value at `value_index`; returning `false` rejects the assignment, which
+    /// throws in strict mode code.
+    fn set(&mut self, _ctx: &mut DukContext, _key: &str, _value_index: i32) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    /// Answers the `in` operator.
+    fn has(&mut self, _ctx: &mut DukContext, _key: &str) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    fn delete_property(&mut self, _ctx: &mut DukContext, _key: &str) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    /// Pushes the value of the symbol at `key_index` and returns [`Return::Top`].
+    fn get_symbol(&mut self, _ctx: &mut DukContext, _key_index: i32) -> Result<Return, JsError> {
+        Ok(Return::Undefined)
+    }
+
+    /// Assigns the value at `value_index` to the symbol at `key_index`, see [`ProxyHandler::set`].
+    fn set_symbol(&mut self, _ctx: &mut DukContext, _key_index: i32, _value_index: i32) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    fn has_symbol(&mut self, _ctx: &mut DukContext, _key_index: i32) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    fn delete_symbol(&mut self, _ctx: &mut DukContext, _key_index: i32) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    /// Lists keys for `Object.keys()`, `for-in` and similar.
+    fn own_keys(&mut self, _ctx: &mut DukContext) -> Result<Vec<String>, JsError> {
+        Ok(Vec::new())
+    }
+
+    /// Describes an own property, or returns `None` if there is no property `key`. Key
+    /// enumeration leaves out keys that are not enumerable.
+    ///
+    /// Answers `Object.getOwnPropertyDescriptor()` with a descriptor without `value`. The
+    /// default describes a writable, enumerable property for keys accepted by
+    /// [`ProxyHandler::has`]. Duktape only calls this trap through a patch, see
+    /// `lib/duktape/PATCHES`.
+    fn get_own_property_descriptor(&mut self, ctx: &mut DukContext, key: &str) -> Result<Option<PropertyAttributes>, JsError> {
+        let exists = self.has(ctx, key)?;
+        Ok(exists.then(|| PropertyAttributes { writable: true, enumerable: true, configurable: true, ..Default::default() }))
+    }
+
+    /// Called for proxies created with [`DukContext::push_function_proxy`], with `this` at
+    /// index `0` followed by `nargs` arguments.
+    fn apply(&mut self, _ctx: &mut DukContext, _nargs: i32) -> Result<Return, JsError> {
+        Err(JsError::from("proxy is not callable".to_string()))
+    }
+
+    /// Called with `new` for proxies created with [`DukContext::push_function_proxy`], with
+    /// `nargs` arguments from index `0`. Must push an object and return [`Return::Top`].
+    fn construct(&mut self, _ctx: &mut DukContext, _nargs: i32) -> Result<Return, JsError> {
+        Err(JsError::from("proxy is not a constructor".to_string()))
+    }
+}
+
+impl DukContext {
+    /// Pushes a `Proxy` of an empty object whose traps are implemented by `handler`.
+    ///
+    /// The handler is dropped when the proxy is garbage collected.
+    pub fn push_proxy<H: ProxyHandler>(&self, handler: H) {
+        self.push_object();
+        self.push_proxy_handler(Box::new(handler));
+    }
+
+    /// Pushes a callable `Proxy` of a function, also dispatching calls and `new` to `handler`.
+    pub fn push_function_proxy<H: ProxyHandler>(&self, handler: H) {
+        self.push_closure(0, |_| Ok(Return::Undefined));
+        self.push_proxy_handler(Box::new(handler));
+    }
+
+    /// Replaces the target on stack top with a proxy dispatching to `handler`.
+    fn push_proxy_handler(&self, handler: Box<dyn ProxyHandler>) {
+        self.push_object();
+        for (magic, (name, nargs)) in TRAPS.iter().enumerate() {
+            unsafe {
+                duk_push_c_function(self.ctx, Some(proxy_dispatch), *nargs);
+                duk_set_magic(self.ctx, -1, magic as i32);
+            }
+            self.put_prop_string(-2, name);
+        }
+        let data: Box<Box<dyn Any + Send>> = Box::new(Box::new(handler));
+        unsafe { duk_push_pointer(self.ctx, Box::into_raw(data) as *mut c_void) };
+        self.put_hidden_prop(-2, FUNC_DATA_PROP);
+        unsafe {
+            duk_push_c_function(self.ctx, Some(func_data_finalizer), 1);
+            duk_set_finalizer(self.ctx, -2);
+            duk_push_proxy(self.ctx, 0);
+        }
+    }
+}
+
+/// Runs trap `trap` of `handler`, with trap arguments at the bottom of the stack of `ctx`.
+pub(crate) fn call_trap(handler: &mut dyn ProxyHandler, ctx: &mut DukContext, trap: i32) -> Result<Return, JsError> {
+    if trap <= TRAP_DELETE_PROPERTY && ctx.is_symbol(1) {
+        return call_symbol_trap(handler, ctx, trap);
+    }
+    match trap {
+        TRAP_GET => {
+            let key = trap_key(ctx);
+            handler.get(ctx, &key)
+        }
+        TRAP_SET => {
+            let key = trap_key(ctx);
+            let res = handler.set(ctx, &key, 2)?;
+            ctx.push_boolean(res);
+            Ok(Return::Top)
+        }
+        TRAP_HAS => {
+            let key = trap_key(ctx);
+            let res = handler.has(ctx, &key)?;
+            ctx.push_boolean(res);
+            Ok(Return::Top)
+        }
+        TRAP_DELETE_PROPERTY => {
+            let key = trap_key(ctx);
+            let res = handler.delete_property(ctx, &key)?;
+            ctx.push_boolean(res);
+            Ok(Return::Top)
+        }
+        TRAP_OWN_KEYS => {
+            let keys = handler.own_keys(ctx)?;
+            ctx.push_array();
+            for (i, key) in keys.iter().enumerate() {
+                ctx.push_string(key);
+                ctx.put_prop_index(-2, i as u32);
+            }
+            Ok(Return::Top)
+        }
+        TRAP_GET_OWN_PROPERTY_DESCRIPTOR => {
+            // symbol keys are not supported
+            if ctx.is_symbol(1) {
+                return Ok(Return::Undefined);
+            }
+            let key = trap_key(ctx);
+            match handler.get_own_property_descriptor(ctx, &key)? {
+                Some(attrs) => {
+                    // configurable, since the key does not exist on the target
+                    ctx.push_object();
+                    for (name, value) in [("writable", attrs.writable), ("enumerable", attrs.enumerable), ("configurable", true)] {
+                        ctx.push_boolean(value);
+                        ctx.put_prop_string(-2, name);
+                    }
+                    Ok(Return::Top)
+                }
+                None => Ok(Return::Undefined),
+            }
+        }
+        TRAP_APPLY | TRAP_CONSTRUCT => {
+            // [ target this|args args|newTarget ] -> [ this? arg1 ... argN ]
+            let args_index = if trap == TRAP_APPLY { 2 } else { 1 };
+            let mut nargs = 0;
+            let res = unsafe { duk_api_push_entries(ctx.ctx, args_index, &mut nargs) };
+            ctx.propagate_js_error(if res == DUK_EXEC_SUCCESS { Ok(()) } else { Err(res) })?;
+            let nargs = nargs as i32;
+            if trap == TRAP_APPLY {
+                ctx.remove(2);
+                ctx.remove(0);
+                handler.apply(ctx, nargs)
+            } else {
+                ctx.remove(2);
+                ctx.remove(1);
+                ctx.remove(0);
+                handler.construct(ctx, nargs)
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the property key of a trap, which Duktape may pass e.g. as a number for array indices.
+fn trap_key(ctx: &DukContext) -> String {
+    unsafe {
+        duk_to_string(ctx.ctx, 1);
+    }
+    ctx.get_string(1).to_string()
+}
+
+/// Runs a property trap with the symbol key at index `1`.
+fn call_symbol_trap(handler: &mut dyn ProxyHandler, ctx: &mut DukContext, trap: i32) -> Result<Return, JsError> {
+    let res = match trap {
+        TRAP_GET => return handler.get_symbol(ctx, 1),
+        TRAP_SET => handler.set_symbol(ctx, 1, 2)?,
+        TRAP_HAS => handler.has_symbol(ctx, 1)?,
+        _ => handler.delete_symbol(ctx, 1)?,
+    };
+    ctx.push_boolean(res);
+    Ok(Return::Top)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use super::*;
+    use crate::test_util::{eval_string, DropFlag};
+
+    /// Rows `0..len` computed on access, with explicitly assigned overrides.
+    struct Table {
+        len: u32,
+        overrides: BTreeMap<String, f64>,
+        /// Values of symbol keys by description.
+        symbols: BTreeMap<String, String>,
+        reads: Arc<AtomicU32>,
+    }
+
+    impl Table {
+        fn row(&self, key: &str) -> Option<f64> {
+            if let Some(value) = self.overrides.get(key) {
+                return Some(*value);
+            }
+            key.parse::<u32>().ok().filter(|i| *i < self.len).map(|i| i as f64 * 10.)
+        }
+    }
+
+    impl ProxyHandler for Table {
+        fn get(&mut self, ctx: &mut DukContext, key: &str) -> Result<Return, JsError> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            if key == "length" {
+                ctx.push_u32(self.len);
+                return Ok(Return::Top);
+            }
+            match self.row(key) {
+                Some(value) => {
+                    ctx.push_number(value);
+                    Ok(Return::Top)
+                }
+                None => Ok(Return::Undefined),
+            }
+        }
+
+        fn set(&mut self, ctx: &mut DukContext, key: &str, value_index: i32) -> Result<bool, JsError> {
+            if !ctx.is_number(value_index) {
+                return Err(JsError::from(format!("row {key} must be a number")));
+            }
+            self.overrides.insert(key.to_string(), ctx.get_number(value_index));
+            Ok(true)
+        }
+
+        fn has(&mut self, _ctx: &mut DukContext, key: &str) -> Result<bool, JsError> {
+            Ok(self.row(key).is_some())
+        }
+
+        fn delete_property(&mut self, _ctx: &mut DukContext, key: &str) -> Result<bool, JsError> {
+            Ok(self.overrides.remove(key).is_some())
+        }
+
+        fn get_symbol(&mut self, ctx: &mut DukContext, key_index: i32) -> Result<Return, JsError> {
+            match self.symbols.get(&ctx.get_symbol_description(key_index).unwrap_or_default()) {
+                Some(value) => {
+                    ctx.push_string(value);
+                    Ok(Return::Top)
+                }
+                None => Ok(Return::Undefined),
+            }
+        }
+
+        fn set_symbol(&mut self, ctx: &mut DukContext, key_index: i32, value_index: i32) -> Result<bool, JsError> {
+            let desc = ctx.get_symbol_description(key_index).unwrap_or_default();
+            self.symbols.insert(desc, ctx.safe_to_lstring(value_index));
+            Ok(true)
+        }
+
+        fn has_symbol(&mut self, ctx: &mut DukContext, key_index: i32) -> Result<bool, JsError> {
+            Ok(self.symbols.contains_key(&ctx.get_symbol_description(key_index).unwrap_or_default()))
+        }
+
+        fn own_keys(&mut self, _ctx: &mut DukContext) -> Result<Vec<String>, JsError> {
+            Ok((0..self.len.min(3)).map(|i| i.to_string()).chain(["hidden".to_string()]).collect())
+        }
+
+        fn get_own_property_descriptor(&mut self, _ctx: &mut DukContext, key: &str) -> Result<Option<PropertyAttributes>, JsError> {
+            let exists = key == "hidden" || self.row(key).is_some();
+            Ok(exists.then(|| PropertyAttributes { enumerable: key != "hidden", writable: true, ..Default::default() }))
+        }
+    }
+
+    fn put_table(engine: &JsEngine, reads: Arc<AtomicU32>) {
+        engine.push_proxy(Table { len: 1_000_000, overrides: BTreeMap::new(), symbols: BTreeMap::new(), reads });
+        engine.put_global_string("table");
+    }
+
+    #[test]
+    fn test_lazy_table() {
+        let engine = JsEngine::new().unwrap();
+        let reads = Arc::new(AtomicU32::new(0));
+        put_table(&engine, reads.clone());
+
+        assert_eq!(eval_string(&engine, "table.length + ':' + table[999999] + ':' + table[1000000]"), "1000000:9999990:undefined");
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+        assert_eq!(eval_string(&engine, "[5 in table, -1 in table, 'x' in table].join()"), "true,false,false");
+        assert_eq!(eval_string(&engine, "table[5] = 1.5; table[5]"), "1.5");
+        assert_eq!(eval_string(&engine, "[delete table[5], table[5], delete table[5]].join()"), "true,50,false");
+        assert_eq!(eval_string(&engine, "try { table[1] = 'x'; } catch (e) { String(e) }"), "Error: row 1 must be a number");
+    }
+
+    #[test]
+    fn test_own_keys() {
+        let engine = JsEngine::new().unwrap();
+        put_table(&engine, Default::default());
+        assert_eq!(eval_string(&engine, "Object.keys(table).join()"), "0,1,2");
+        assert_eq!(eval_string(&engine, "var keys = []; for (var k in table) keys.push(k); keys.join()"), "0,1,2");
+        assert_eq!(eval_string(&engine, "Object.getOwnPropertyNames(table).join()"), "0,1,2,hidden");
+        assert_eq!(eval_string(&engine, "var d = Object.getOwnPropertyDescriptor(table, '0'); [d.enumerable, d.writable, d.configurable].join()"), "true,true,true");
+        assert_eq!(eval_string(&engine, "Object.getOwnPropertyDescriptor(table, 'hidden').enumerable + ',' + typeof Object.getOwnPropertyDescriptor(table, 'x')"), "false,undefined");
+    }
+
+    #[test]
+    fn test_descriptor_trap() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var p = new Proxy({}, { getOwnPropertyDescriptor: function(t, k) { return k === 'a' ? { value: 1, configurable: true } : k === 'b' ? 1 : undefined; } })").unwrap();
+        assert_eq!(eval_string(&engine, "Object.getOwnPropertyDescriptor(p, 'a').value + ',' + typeof Object.getOwnPropertyDescriptor(p, 'c')"), "1,undefined");
+        assert_eq!(eval_string(&engine, "try { Object.getOwnPropertyDescriptor(p, 'b'); } catch (e) { e.name }"), "TypeError");
+    }
+
+    #[test]
+    fn test_symbol_keys() {
+        let engine = JsEngine::new().unwrap();
+        put_table(&engine, Default::default());
+        assert_eq!(eval_string(&engine, "var s = Symbol('s'); table[s] = 1; [table[s], s in table, Symbol('t') in table].join()"), "1,true,false");
+        assert_eq!(eval_string(&engine, "[delete table[s], table[s]].join()"), "false,1");
+
+        engine.push_proxy(Adder);
+        engine.put_global_string("empty");
+        assert_eq!(eval_string(&engine, "'use strict'; try { empty[s] = 1; } catch (e) { e.name }"), "TypeError");
+        assert_eq!(eval_string(&engine, "typeof empty[s]"), "undefined");
+    }
+
+    struct Adder;
+
+    impl ProxyHandler for Adder {
+        fn apply(&mut self, ctx: &mut DukContext, nargs: i32) -> Result<Return, JsError> {
+            let sum: f64 = (1..=nargs).map(|i| ctx.get_number(i)).sum();
+            ctx.push_number(sum);
+            Ok(Return::Top)
+        }
+
+        fn construct(&mut self, ctx: &mut DukContext, nargs: i32) -> Result<Return, JsError> {
+            ctx.push_object();
+            ctx.push_i32(nargs);
+            ctx.put_prop_string(-2, "nargs");
+            Ok(Return::Top)
+        }
+    }
+
+    #[test]
+    fn test_function_proxy() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_function_proxy(Adder);
+        engine.put_global_string("add");
+        assert_eq!(eval_string(&engine, "add(1, 2, 3) + ',' + new add(1, 2).nargs + ',' + typeof add"), "6,2,function");
+
+        engine.push_proxy(Adder);
+        engine.put_global_string("plain");
+        assert_eq!(eval_string(&engine, "try { plain(); } catch (e) { e.name }"), "TypeError");
+    }
+
+    impl ProxyHandler for DropFlag {}
+
+    #[test]
+    fn test_handler_dropped() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let engine = JsEngine::new().unwrap();
+        engine.push_proxy(DropFlag(dropped.clone()));
+        engine.gc();
+        assert!(!dropped.load(Ordering::SeqCst));
+        engine.pop();
+        engine.gc();
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::{Deref, DerefMut};
+use super::*;
+
+/// Global properties that are not configurable and always remain in a realm.
+const FIXED_GLOBALS: [&str; 3] = ["NaN", "Infinity", "undefined"];
+
+/// Options used when creating a [`Realm`].
+#[derive(Debug, Clone, Default)]
+pub struct RealmOptions {
+    /// Global built-ins kept in the realm, e.g. `["Math", "JSON"]`; all others are removed
+    /// from the global object. `None` keeps every built-in.
+    /// `NaN`, `Infinity` and `undefined` cannot be removed, `console` is controlled by [`RealmOptions::console`].
+    pub builtins: Option<Vec<String>>,
+    /// Calls [`DukContext::init_console`] in the realm.
+    pub console: bool,
+}
+
+/// Isolated global environment within the heap of a [`JsEngine`].
+///
+/// A realm owns a Duktape thread created with a fresh set of built-ins. The thread is kept alive
+/// by a reference in the heap stash rather than by a value stack slot, so realms can be created,
+/// stored and dropped in any order. A realm does not borrow its engine, but must not be used
+/// after the engine is dropped; doing so panics. Dereferences to the context of the realm's thread.
+pub struct Realm {
+    heap: HeapHandle,
+    ctx: DukContext,
+    stash_key: String,
+}
+
+impl std::fmt::Debug for Realm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Realm")
+            .field("stash_key", &self.stash_key)
+            .finish()
+    }
+}
+
+impl Realm {
+    /// Creates a realm with all built-ins and without console.
+    pub fn new(engine: &JsEngine) -> Result<Self, JsError> {
+        Self::with_options(engine, RealmOptions::default())
+    }
+
+    pub fn with_options(engine: &JsEngine, options: RealmOptions) -> Result<Self, JsError> {
+        // the realm thread is kept alive by the stash entry, which a poisoned engine does not write
+        engine.check_poisoned()?;
+        engine.check_stack(2)?;
+        let idx = engine.push_thread_new_globalenv();
+        let ctx = unsafe { duk_get_context(engine.ctx, idx) };
+        let stash_key = format!("realm:{:p}", ctx);
+
+        engine.push_heap_stash();
+        engine.dup(idx);
+        engine.put_prop_string(-2, &stash_key);
+        engine.pop_n(2);
+
+        let realm = Realm {
+            heap: engine.handle(),
+            ctx: unsafe { DukContext::from_raw(ctx) },
+            stash_key,
+        };
+        // console captures built-ins on initialization, so it must precede removal of built-ins
+        if options.console {
+            realm.init_console();
+        }
+        if let Some(ref builtins) = options.builtins {
+            realm.retain_globals(builtins)?;
+        }
+        Ok(realm)
+    }
+
+    fn retain_globals(&self, keep: &[String]) -> Result<(), JsError> {
+        self.check_stack(4)?;
+        self.push_global_object();
+        let names = match self.keys_with(-1, DukEnumFlags::DUK_ENUM_OWN_PROPERTIES_ONLY | DukEnumFlags::DUK_ENUM_INCLUDE_NONENUMERABLE) {
+            Ok(names) => names,
+            Err(err) => {
+                self.pop();
+                return Err(err);
+            }
+        };
+        for name in names {
+            if !keep.contains(&name) && !FIXED_GLOBALS.contains(&name.as_str()) && name != "console" {
+                self.del_prop_string(-1, &name);
+            }
+        }
+        self.pop();
+        Ok(())
+    }
+
+    /// Sets global variable `name` of this realm to `value`.
+    pub fn set_global<T: WriteJs>(&self, name: &str, value: &T) -> Result<(), JsError> {
+        self.check_stack(1)?;
+        value.write_js(self)?;
+        self.put_global_string(name);
+        Ok(())
+    }
+
+    /// Reads global variable `name` of this realm.
+    pub fn get_global<T: ReadJs>(&self, name: &str) -> Result<T, JsError> {
+        self.check_stack(1)?;
+        self.get_global_string(name);
+        let res = self.read_top();
+        self.pop();
+        res
+    }
+
+    /// Makes global `name` of this realm available in `target` under `target_name`.
+    ///
+    /// The value is shared by reference, so objects mutated in one realm are seen changed in the other.
+    /// Fails if `target` belongs to another engine.
+    pub fn share_global(&self, name: &str, target: &DukContext, target_name: &str) -> Result<(), JsError> {
+        if !self.heap.owns(target) {
+            return Err(JsError::from("target belongs to another engine".to_string()));
+        }
+        self.check_stack(1)?;
+        target.check_stack(1)?;
+        if !self.get_global_string(name) {
+            self.pop();
+            return Err(JsError::from(format!("global '{}' is not defined", name)));
+        }
+        target.xcopy_top(self, 1);
+        target.put_global_string(target_name);
+        self.pop();
+        Ok(())
+    }
+}
+
+impl Deref for Realm {
+    type Target = DukContext;
+
+    fn deref(&self) -> &Self::Target {
+        assert!(self.heap.ctx().is_some(), "realm used after its engine was dropped");
+        &self.ctx
+    }
+}
+
+impl DerefMut for Realm {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        assert!(self.heap.ctx().is_some(), "realm used after its engine was dropped");
+        &mut self.ctx
+    }
+}
+
+impl Drop for Realm {
+    fn drop(&mut self) {
+        let Some(engine) = self.heap.ctx() else {
+            return;
+        };
+        if engine.is_poisoned() {
+            return;
+        }
+        // the thread must not release itself, so its stash entry is removed through the engine
+        engine.push_heap_stash();
+        engine.del_prop_string(-1, &self.stash_key);
+        engine.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eval_bool;
+
+    #[test]
+    fn test_realms_are_isolated() {
+        let engine = JsEngine::new().unwrap();
+        let first = Realm::new(&engine).unwrap();
+        let second = Realm::new(&engine).unwrap();
+
+        first.eval("var tenant = 'first'; Math.custom = 1;").unwrap();
+        first.pop();
+        second.set_global("tenant", &"second").unwrap();
+
+        assert_eq!(first.get_global::<String>("tenant").unwrap(), "first");
+        assert_eq!(second.get_global::<String>("tenant").unwrap(), "second");
+        assert!(eval_bool(&second, "typeof Math.custom === 'undefined'"));
+        assert!(eval_bool(&engine, "typeof tenant === 'undefined'"));
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_realm_drop_order() {
+        let engine = JsEngine::new().unwrap();
+        let first = Realm::new(&engine).unwrap();
+        let second = Realm::new(&engine).unwrap();
+        first.eval("var big = []; for (var i = 0; i < 10000; i++) big.push({i: i});").unwrap();
+        engine.gc();
+        let size = engine.heap_size();
+
+        drop(first);
+        engine.gc();
+        assert!(engine.heap_size() < size);
+
+        second.eval("1 + 1").unwrap();
+        assert_eq!(second.get_number(-1), 2.0);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_realm_builtins() {
+        let engine = JsEngine::new().unwrap();
+        let realm = Realm::with_options(&engine, RealmOptions {
+            builtins: Some(vec!["Math".to_string(), "JSON".to_string()]),
+            console: true,
+        }).unwrap();
+
+        //language=js
+        assert!(eval_bool(&realm, "typeof Math === 'object' && typeof JSON === 'object' && typeof console === 'object'"));
+        //language=js
+        assert!(eval_bool(&realm, "typeof Duktape === 'undefined' && typeof Object === 'undefined' && typeof parseInt === 'undefined'"));
+        assert!(eval_bool(&realm, "NaN !== NaN && Infinity > 0"));
+        assert!(eval_bool(&engine, "typeof Duktape === 'object' && typeof console === 'undefined'"));
+    }
+
+    #[test]
+    fn test_realm_share_global() {
+        let engine = JsEngine::new().unwrap();
+        let first = Realm::new(&engine).unwrap();
+        let second = Realm::new(&engine).unwrap();
+
+        first.eval("var config = {limit: 10}").unwrap();
+        first.pop();
+        first.share_global("config", &second, "shared").unwrap();
+        assert!(eval_bool(&second, "shared.limit === 10"));
+
+        second.eval("shared.limit = 20").unwrap();
+        second.pop();
+        assert!(eval_bool(&first, "config.limit === 20"));
+
+        assert!(first.share_global("missing", &second, "x").is_err());
+        assert_eq!(first.get_top(), 0);
+    }
+
+    #[test]
+    fn test_realm_share_global_other_engine() {
+        let engine = JsEngine::new().unwrap();
+        let other = JsEngine::new().unwrap();
+        let realm = Realm::new(&engine).unwrap();
+        realm.eval("var config = {}").unwrap();
+        realm.pop();
+        assert!(realm.share_global("config", &other, "config").is_err());
+        assert_eq!(realm.get_top(), 0);
+        assert_eq!(other.get_top(), 0);
+    }
+
+    #[test]
+    fn test_realm_does_not_borrow_engine() {
+        let mut engine = JsEngine::new().unwrap();
+        let realm = Realm::new(&engine).unwrap();
+        let _ = engine.interop_mut();
+        realm.eval("1").unwrap();
+        realm.pop();
+
+        drop(engine);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| realm.get_top())).is_err());
+        drop(realm);
+    }
+
+    #[test]
+    fn test_realm_outlives_stack_changes() {
+        let engine = JsEngine::new().unwrap();
+        let realm = Realm::new(&engine).unwrap();
+        engine.push_string("unrelated");
+        engine.set_top(0);
+        engine.gc();
+        realm.eval("'still alive'").unwrap();
+        assert_eq!(realm.get_string(-1), "still alive");
+    }
+}
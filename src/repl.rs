@@ -0,0 +1,413 @@
+//! Interactive read-eval-print loop, enabled with the `cli` feature.
+//!
+//! The `kg-js` binary runs a [`Repl`] over a [`JsEngine`] with [`StdioInterop`]. Custom REPLs
+//! preloaded with host bindings are built the same way, passing an engine with their own
+//! [`JsInterop`] and registering [`ReplPlugin`]s:
+//!
+//! ```no_run
+//! use kg_js::repl::Repl;
+//! use kg_js::JsEngine;
+//!
+//! let engine = JsEngine::new().unwrap();
+//! Repl::new(engine).run().unwrap();
+//! ```
+//!
+//! Input spanning several lines is collected until it parses. Lines starting with `.` are
+//! meta-commands, see `.help`.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use super::*;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// Fragment of Duktape syntax error messages for input that ended too early.
+const END_OF_INPUT: &str = "end of input";
+
+const HELP: [(&str, &str); 6] = [
+    (".help", "Show this help"),
+    (".exit", "Exit the REPL"),
+    (".load <file>", "Evaluate a script file"),
+    (".bytecode <file> [out]", "Compile a script file and print or save its bytecode"),
+    (".heap", "Show heap usage, before and after garbage collection"),
+    (".break", "Discard incomplete multiline input"),
+];
+
+/// Extends a [`Repl`] with setup code and meta-commands.
+pub trait ReplPlugin {
+    /// Called once before the first prompt, e.g. to register host functions.
+    fn init(&mut self, _engine: &mut JsEngine) -> Result<(), JsError> {
+        Ok(())
+    }
+
+    /// Handles the meta-command `.name args`, returning `false` for unknown commands.
+    fn command(&mut self, _engine: &mut JsEngine, _name: &str, _args: &str, _out: &mut dyn Write) -> Result<bool, JsError> {
+        Ok(false)
+    }
+
+    /// Returns `(usage, description)` lines listed by `.help`.
+    fn help(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// Interop writing console output to stdout, and warnings, errors and traces to stderr.
+#[derive(Debug, Default)]
+pub struct StdioInterop;
+
+impl JsInterop for StdioInterop {
+    fn call(&mut self, _ctx: &mut DukContext, func_name: &str) -> Result<Return, JsError> {
+        Err(JsError::from(format!("unknown host function '{func_name}'")))
+    }
+
+    fn console(&mut self, func: ConsoleFunc, msg: &str) {
+        match func {
+            ConsoleFunc::Assert | ConsoleFunc::Trace | ConsoleFunc::Warn | ConsoleFunc::Error | ConsoleFunc::Exception => eprintln!("{msg}"),
+            _ => println!("{msg}"),
+        }
+    }
+}
+
+/// Outcome of [`Repl::eval_input`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplStatus {
+    /// Input was handled, the next line starts new input.
+    Done,
+    /// Input does not parse yet; the next line is appended to it.
+    Incomplete,
+    /// `.exit` was entered.
+    Exit,
+}
+
+/// Read-eval-print loop over a [`JsEngine`].
+pub struct Repl {
+    engine: JsEngine,
+    plugins: Vec<Box<dyn ReplPlugin>>,
+    history_file: Option<PathBuf>,
+    buffer: String,
+}
+
+impl std::fmt::Debug for Repl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Repl")
+            .field("history_file", &self.history_file)
+            .field("buffer", &self.buffer)
+            .finish()
+    }
+}
+
+impl Repl {
+    /// Creates a REPL over `engine`, calling [`DukContext::init_console`]. History is kept in
+    /// `.kg_js_history` in the home directory.
+    pub fn new(engine: JsEngine) -> Self {
+        engine.init_console();
+        let history_file = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".kg_js_history"));
+        Repl {
+            engine,
+            plugins: Vec::new(),
+            history_file,
+            buffer: String::new(),
+        }
+    }
+
+    pub fn plugin<P: ReplPlugin + 'static>(mut self, plugin: P) -> Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Sets the file history is loaded from and saved to, `None` disables persistent history.
+    pub fn history_file(mut self, path: Option<PathBuf>) -> Self {
+        self.history_file = path;
+        self
+    }
+
+    pub fn engine(&mut self) -> &mut JsEngine {
+        &mut self.engine
+    }
+
+    /// Initializes plugins and reads input from the terminal until `.exit` or end of input.
+    pub fn run(mut self) -> io::Result<()> {
+        self.init_plugins()?;
+        let mut editor = DefaultEditor::new().map_err(io::Error::other)?;
+        if let Some(ref path) = self.history_file {
+            // missing history is not an error
+            let _ = editor.load_history(path);
+        }
+        println!("kg-js {} (Duktape {}), type .help for help", env!("CARGO_PKG_VERSION"), JsEngine::version_info());
+        loop {
+            let prompt = if self.buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    let mut stdout = io::stdout();
+                    if self.eval_input(&line, &mut stdout) == ReplStatus::Exit {
+                        break;
+                    }
+                    stdout.flush()?;
+                }
+                Err(ReadlineError::Interrupted) => {
+                    self.buffer.clear();
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+        if let Some(ref path) = self.history_file {
+            editor.save_history(path).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// Calls [`ReplPlugin::init`] of registered plugins.
+    pub fn init_plugins(&mut self) -> io::Result<()> {
+        for plugin in self.plugins.iter_mut() {
+            plugin.init(&mut self.engine).map_err(|err| io::Error::other(String::from(err)))?;
+        }
+        Ok(())
+    }
+
+    /// Handles one line of input, writing results and errors to `out`.
+    pub fn eval_input(&mut self, line: &str, out: &mut dyn Write) -> ReplStatus {
+        if self.buffer.is_empty() {
+            if let Some(command) = line.trim().strip_prefix('.') {
+                return self.meta_command(command, out);
+            }
+        } else if line.trim() == ".break" {
+            self.buffer.clear();
+            return ReplStatus::Done;
+        }
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        let res = self.engine.eval_file("repl", &self.buffer);
+        match res {
+            Err(ref err) if !err.is_poisoned() && String::from(err.clone()).contains(END_OF_INPUT) => {
+                return ReplStatus::Incomplete;
+            }
+            Ok(()) => {
+                let result = self.format_top();
+                self.engine.pop();
+                let _ = writeln!(out, "{result}");
+            }
+            Err(err) => {
+                let _ = writeln!(out, "Uncaught {}", String::from(err));
+            }
+        }
+        self.buffer.clear();
+        ReplStatus::Done
+    }
+
+    fn meta_command(&mut self, command: &str, out: &mut dyn Write) -> ReplStatus {
+        let (name, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let args = args.trim();
+        let res = match name {
+            "exit" => return ReplStatus::Exit,
+            "help" => {
+                self.write_help(out);
+                Ok(())
+            }
+            "break" => Ok(()),
+            "load" => self.load(args, out),
+            "bytecode" => self.bytecode(args, out),
+            "heap" => {
+                let before = self.engine.heap_size();
+                self.engine.gc();
+                let _ = writeln!(out, "heap: {} bytes, {} bytes after gc", before, self.engine.heap_size());
+                let stats = self.engine.heap_stats();
+                let _ = writeln!(out, "objects: {} ({} bytes), strings: {} ({} bytes), buffers: {} ({} bytes)",
+                    stats.objects.count, stats.objects.bytes, stats.strings.count, stats.strings.bytes,
+                    stats.buffers.count, stats.buffers.bytes);
+                Ok(())
+            }
+            _ => {
+                let mut handled = Ok(false);
+                for plugin in self.plugins.iter_mut() {
+                    handled = plugin.command(&mut self.engine, name, args, out);
+                    if !matches!(handled, Ok(false)) {
+                        break;
+                    }
+                }
+                match handled {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(JsError::from(format!("unknown command '.{name}', see .help"))),
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        if let Err(err) = res {
+            let _ = writeln!(out, "{}", String::from(err));
+        }
+        ReplStatus::Done
+    }
+
+    fn write_help(&self, out: &mut dyn Write) {
+        let mut lines: Vec<(String, String)> = HELP.iter().map(|(u, d)| (u.to_string(), d.to_string())).collect();
+        for plugin in self.plugins.iter() {
+            lines.extend(plugin.help());
+        }
+        let width = lines.iter().map(|(usage, _)| usage.len()).max().unwrap_or(0);
+        for (usage, description) in lines {
+            let _ = writeln!(out, "{usage:width$}  {description}");
+        }
+    }
+
+    fn load(&mut self, path: &str, out: &mut dyn Write) -> Result<(), JsError> {
+        let code = read_file(path)?;
+        match self.engine.eval_file(path, &code) {
+            Ok(()) => {
+                let result = self.format_top();
+                self.engine.pop();
+                let _ = writeln!(out, "{result}");
+                Ok(())
+            }
+            Err(err) => Err(JsError::from(format!("Uncaught {}", String::from(err)))),
+        }
+    }
+
+    fn bytecode(&mut self, args: &str, out: &mut dyn Write) -> Result<(), JsError> {
+        let mut args = args.split_whitespace();
+        let (Some(path), target) = (args.next(), args.next()) else {
+            return Err(JsError::from("usage: .bytecode <file> [out]".to_string()));
+        };
+        let code = read_file(path)?;
+        self.engine.compile_file(path, &code)?;
+        let bytecode = self.engine.dump_function(-1);
+        self.engine.pop();
+        let bytecode = bytecode?;
+        match target {
+            Some(target) => {
+                std::fs::write(target, &bytecode).map_err(|err| JsError::from(format!("cannot write {target}: {err}")))?;
+                let _ = writeln!(out, "{} bytes written to {}", bytecode.len(), target);
+            }
+            None => {
+                for (i, chunk) in bytecode.chunks(16).enumerate() {
+                    let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+                    let _ = writeln!(out, "{:08x}  {}", i * 16, hex.join(" "));
+                }
+                let _ = writeln!(out, "{} bytes", bytecode.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats the value on stack top with the JX encoder, as used by the console.
+    fn format_top(&self) -> String {
+        if self.engine.get_type(-1) == DukType::DUK_TYPE_UNDEFINED {
+            return "undefined".to_string();
+        }
+        if self.engine.is_symbol(-1) {
+            return format!("Symbol({})", self.engine.get_symbol_description(-1).unwrap_or_default());
+        }
+        match self.engine.json_encode(-1, JsonFormat::Jx, Some(2)) {
+            Ok(s) => s,
+            Err(_) => self.engine.safe_to_lstring(-1),
+        }
+    }
+}
+
+fn read_file(path: &str) -> Result<String, JsError> {
+    if path.is_empty() {
+        return Err(JsError::from("missing file name".to_string()));
+    }
+    std::fs::read_to_string(path).map_err(|err| JsError::from(format!("cannot read {path}: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl {
+        Repl::new(JsEngine::new().unwrap()).history_file(None)
+    }
+
+    fn input(repl: &mut Repl, line: &str) -> (ReplStatus, String) {
+        let mut out = Vec::new();
+        let status = repl.eval_input(line, &mut out);
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut repl = repl();
+        assert_eq!(input(&mut repl, "var a = 1"), (ReplStatus::Done, "undefined\n".to_string()));
+        assert_eq!(input(&mut repl, "({ a: a, b: [1, 'x'] })").1, "{\n  a: 1,\n  b: [\n    1,\n    \"x\"\n  ]\n}\n");
+        assert_eq!(input(&mut repl, "Symbol('s')").1, "Symbol(s)\n");
+        assert_eq!(input(&mut repl, "null.x").1, "Uncaught TypeError: cannot read property 'x' of null\n");
+        assert_eq!(repl.engine().get_top(), 0);
+    }
+
+    #[test]
+    fn test_multiline() {
+        let mut repl = repl();
+        assert_eq!(input(&mut repl, "function f(a) {"), (ReplStatus::Incomplete, String::new()));
+        assert_eq!(input(&mut repl, "  return a * 2;"), (ReplStatus::Incomplete, String::new()));
+        assert_eq!(input(&mut repl, "}"), (ReplStatus::Done, "undefined\n".to_string()));
+        assert_eq!(input(&mut repl, "f(").0, ReplStatus::Incomplete);
+        assert_eq!(input(&mut repl, ".break").0, ReplStatus::Done);
+        assert_eq!(input(&mut repl, "f(2)").1, "4\n");
+        assert!(input(&mut repl, "1 +* 2").1.starts_with("Uncaught SyntaxError"));
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let dir = std::env::temp_dir().join(format!("kg-js-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("lib.js");
+        std::fs::write(&script, "function twice(x) { return 2 * x; }\ntwice(21)").unwrap();
+        let script = script.to_str().unwrap();
+
+        let mut repl = repl();
+        assert_eq!(input(&mut repl, &format!(".load {script}")).1, "42\n");
+        assert_eq!(input(&mut repl, "twice(2)").1, "4\n");
+        assert!(input(&mut repl, ".bytecode").1.starts_with("usage:"));
+        let res = input(&mut repl, &format!(".bytecode {script}")).1;
+        assert!(res.starts_with("00000000  bf "));
+        let out = dir.join("lib.bin");
+        let res = input(&mut repl, &format!(".bytecode {} {}", script, out.display())).1;
+        assert!(res.ends_with(&format!("written to {}\n", out.display())));
+        assert_eq!(std::fs::read(&out).unwrap()[0], 0xbf);
+        let heap = input(&mut repl, ".heap").1;
+        assert!(heap.starts_with("heap: ") && heap.contains("\nobjects: "), "{heap}");
+        assert!(input(&mut repl, ".help").1.contains(".load <file>"));
+        assert_eq!(input(&mut repl, ".nope").1, "unknown command '.nope', see .help\n");
+        assert_eq!(input(&mut repl, ".exit").0, ReplStatus::Exit);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    struct Greeter;
+
+    impl ReplPlugin for Greeter {
+        fn init(&mut self, engine: &mut JsEngine) -> Result<(), JsError> {
+            engine.eval("var greeting = 'hello'")?;
+            engine.pop();
+            Ok(())
+        }
+
+        fn command(&mut self, engine: &mut JsEngine, name: &str, args: &str, out: &mut dyn Write) -> Result<bool, JsError> {
+            if name != "greet" {
+                return Ok(false);
+            }
+            engine.get_global_string("greeting");
+            let _ = writeln!(out, "{} {}", engine.get_string(-1), args);
+            engine.pop();
+            Ok(true)
+        }
+
+        fn help(&self) -> Vec<(String, String)> {
+            vec![(".greet <name>".to_string(), "Greet someone".to_string())]
+        }
+    }
+
+    #[test]
+    fn test_plugin() {
+        let mut repl = repl().plugin(Greeter);
+        repl.init_plugins().unwrap();
+        assert_eq!(input(&mut repl, ".greet world").1, "hello world\n");
+        assert!(input(&mut repl, ".help").1.contains(".greet <name>           Greet someone"));
+        assert_eq!(input(&mut repl, "greeting").1, "\"hello\"\n");
+    }
+}
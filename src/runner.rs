@@ -0,0 +1,404 @@
+//! Script runner behind `kg-js run`, enabled with the `cli` feature.
+//!
+//! [`Runner`] exposes a Node.js-like `process` global to the script:
+//!
+//! | Property                  | Description                                                  |
+//! |---------------------------|--------------------------------------------------------------|
+//! | `process.argv`            | `["kg-js", script, ...args]`                                 |
+//! | `process.env`             | Environment variables selected by the [`EnvFilter`]          |
+//! | `process.exit(code)`      | Stops the script with exit code `code`, `0` if omitted, without running `catch` or `finally` blocks |
+//! | `process.stdout.write(s)` | Writes `s` to standard output, likewise `process.stderr`     |
+//! | `process.stdin.read()`    | Reads the remaining input, `""` at end of input              |
+//! | `process.stdin.readLine()`| Reads the next line without line terminator, `null` at end   |
+//!
+//! Uncaught errors are printed to standard error with their stack trace, mapped with the
+//! [`Runner::source_map`] if set, and mapped to the `EXIT_*` codes of this module.
+
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+use super::*;
+
+pub const EXIT_SUCCESS: i32 = 0;
+/// An error was thrown and not caught.
+pub const EXIT_UNCAUGHT_ERROR: i32 = 1;
+/// Invalid command line arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// The script does not compile, or a `SyntaxError` was not caught.
+pub const EXIT_SYNTAX_ERROR: i32 = 3;
+/// The script could not be read.
+pub const EXIT_IO_ERROR: i32 = 4;
+/// The engine hit a fatal error, e.g. ran out of memory.
+pub const EXIT_FATAL: i32 = 70;
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+type SharedReader = Arc<Mutex<Box<dyn BufRead + Send>>>;
+
+/// Selects the environment variables visible in `process.env`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EnvFilter {
+    #[default]
+    All,
+    None,
+    /// Variable names, or name prefixes when ending with `*`.
+    Only(Vec<String>),
+}
+
+impl EnvFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            EnvFilter::All => true,
+            EnvFilter::None => false,
+            EnvFilter::Only(patterns) => patterns.iter().any(|p| match p.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == p,
+            }),
+        }
+    }
+}
+
+/// Runs scripts with a `process` global, see the [module documentation](self).
+pub struct Runner {
+    args: Vec<String>,
+    env: EnvFilter,
+    source_map: Option<Arc<SourceMap>>,
+    stdin: SharedReader,
+    stdout: SharedWriter,
+    stderr: SharedWriter,
+}
+
+impl std::fmt::Debug for Runner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Runner")
+            .field("args", &self.args)
+            .field("env", &self.env)
+            .finish()
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runner {
+    /// Creates a runner using the standard streams of the process.
+    pub fn new() -> Self {
+        Runner {
+            args: Vec::new(),
+            env: EnvFilter::All,
+            source_map: None,
+            stdin: Arc::new(Mutex::new(Box::new(io::BufReader::new(io::stdin())))),
+            stdout: Arc::new(Mutex::new(Box::new(io::stdout()))),
+            stderr: Arc::new(Mutex::new(Box::new(io::stderr()))),
+        }
+    }
+
+    /// Sets the arguments following the script name in `process.argv`.
+    pub fn args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn env_filter(mut self, filter: EnvFilter) -> Self {
+        self.env = filter;
+        self
+    }
+
+    /// Sets the source map of the script, e.g. when it was transpiled from TypeScript.
+    pub fn source_map<M: Into<Arc<SourceMap>>>(mut self, map: M) -> Self {
+        self.source_map = Some(map.into());
+        self
+    }
+
+    pub fn stdin<R: BufRead + Send + 'static>(mut self, stdin: R) -> Self {
+        self.stdin = Arc::new(Mutex::new(Box::new(stdin)));
+        self
+    }
+
+    pub fn stdout<W: Write + Send + 'static>(mut self, stdout: W) -> Self {
+        self.stdout = Arc::new(Mutex::new(Box::new(stdout)));
+        self
+    }
+
+    /// Sets the writer for `process.stderr` and uncaught errors.
+    pub fn stderr<W: Write + Send + 'static>(mut self, stderr: W) -> Self {
+        self.stderr = Arc::new(Mutex::new(Box::new(stderr)));
+        self
+    }
+
+    /// Reads and runs the script at `path`, returning the exit code.
+    pub fn run_file(&self, ctx: &DukContext, path: &str) -> i32 {
+        match std::fs::read_to_string(path) {
+            Ok(code) => self.run(ctx, path, &code),
+            Err(err) => {
+                self.report(&format!("kg-js: cannot read {path}: {err}"));
+                EXIT_IO_ERROR
+            }
+        }
+    }
+
+    /// Runs `code` as global code of the script `filename`, returning the exit code.
+    ///
+    /// A leading `#!` line is ignored.
+    pub fn run(&self, ctx: &DukContext, filename: &str, code: &str) -> i32 {
+        let exit_code = Arc::new(Mutex::new(None));
+        self.push_process(ctx, filename, exit_code.clone());
+        ctx.put_global_string("process");
+
+        let mut options = CompileOptions::new().filename(filename).shebang(true);
+        if let Some(ref map) = self.source_map {
+            options = options.source_map(map.clone());
+        }
+        let status = match ctx.compile_with(code, &options) {
+            Err(err) if err.is_poisoned() => {
+                self.report(&format!("kg-js: {err}"));
+                EXIT_FATAL
+            }
+            Err(err) => {
+                self.report(&format!("Uncaught {}", String::from(err)));
+                EXIT_SYNTAX_ERROR
+            }
+            Ok(()) => {
+                let res = ctx.pcall(0);
+                ctx.cancel_terminate_execution();
+                match res {
+                    Ok(()) => {
+                        ctx.pop();
+                        EXIT_SUCCESS
+                    }
+                    Err(DUK_API_POISONED) => {
+                        self.report(&format!("kg-js: {}", ctx.poisoned_error()));
+                        EXIT_FATAL
+                    }
+                    Err(_) => {
+                        let status = match *exit_code.lock().unwrap() {
+                            Some(_) => EXIT_SUCCESS,
+                            None => self.report_uncaught(ctx),
+                        };
+                        ctx.pop();
+                        status
+                    }
+                }
+            }
+        };
+        let _ = self.stdout.lock().unwrap().flush();
+        let _ = self.stderr.lock().unwrap().flush();
+        // process.exit() wins, e.g. over an error thrown by a native function it was called from
+        let exit_code = *exit_code.lock().unwrap();
+        exit_code.unwrap_or(status)
+    }
+
+    /// Prints the error on stack top with its stack trace and returns its exit code.
+    fn report_uncaught(&self, ctx: &DukContext) -> i32 {
+        let mut trace = None;
+        let mut status = EXIT_UNCAUGHT_ERROR;
+        if ctx.is_object(-1) {
+            if ctx.get_prop_string(-1, "stack") && ctx.is_string(-1) {
+                trace = Some(ctx.get_string(-1).to_string());
+            }
+            ctx.pop();
+            if ctx.get_prop_string(-1, "name") && ctx.is_string(-1) && ctx.get_string(-1) == "SyntaxError" {
+                status = EXIT_SYNTAX_ERROR;
+            }
+            ctx.pop();
+        }
+        let trace = trace.unwrap_or_else(|| ctx.safe_to_lstring(-1));
+        self.report(&format!("Uncaught {trace}"));
+        status
+    }
+
+    fn report(&self, msg: &str) {
+        let _ = writeln!(self.stderr.lock().unwrap(), "{msg}");
+    }
+
+    fn push_process(&self, ctx: &DukContext, filename: &str, exit_code: Arc<Mutex<Option<i32>>>) {
+        ctx.push_object();
+
+        ctx.push_array();
+        let argv = ["kg-js", filename].into_iter().chain(self.args.iter().map(String::as_str));
+        for (i, arg) in argv.enumerate() {
+            ctx.push_string(arg);
+            ctx.put_prop_index(-2, i as u32);
+        }
+        ctx.put_prop_string(-2, "argv");
+
+        ctx.push_object();
+        for (name, value) in std::env::vars() {
+            if self.env.matches(&name) {
+                ctx.push_string(&value);
+                ctx.put_prop_string(-2, &name);
+            }
+        }
+        ctx.put_prop_string(-2, "env");
+
+        ctx.push_closure(1, move |ctx| {
+            let code = if ctx.is_number(0) { ctx.get_number(0) as i32 } else { 0 };
+            exit_code.lock().unwrap().get_or_insert(code);
+            // stops the script, skipping catch and finally blocks
+            ctx.terminate_execution();
+            Err(JsError::from(format!("process.exit({code})")))
+        });
+        ctx.put_prop_string(-2, "exit");
+
+        push_writer(ctx, self.stdout.clone());
+        ctx.put_prop_string(-2, "stdout");
+        push_writer(ctx, self.stderr.clone());
+        ctx.put_prop_string(-2, "stderr");
+
+        ctx.push_object();
+        let stdin = self.stdin.clone();
+        ctx.push_closure(0, move |ctx| {
+            let mut input = String::new();
+            stdin.lock().unwrap().read_to_string(&mut input)
+                .map_err(|err| JsError::from(format!("cannot read stdin: {err}")))?;
+            ctx.push_string(&input);
+            Ok(Return::Top)
+        });
+        ctx.put_prop_string(-2, "read");
+        let stdin = self.stdin.clone();
+        ctx.push_closure(0, move |ctx| {
+            let mut line = String::new();
+            let n = stdin.lock().unwrap().read_line(&mut line)
+                .map_err(|err| JsError::from(format!("cannot read stdin: {err}")))?;
+            if n == 0 {
+                ctx.push_null();
+                return Ok(Return::Top);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            ctx.push_string(line.strip_suffix('\r').unwrap_or(line));
+            Ok(Return::Top)
+        });
+        ctx.put_prop_string(-2, "readLine");
+        ctx.put_prop_string(-2, "stdin");
+    }
+}
+
+/// Pushes an object with a `write(s)` method writing to `writer`.
+fn push_writer(ctx: &DukContext, writer: SharedWriter) {
+    ctx.push_object();
+    ctx.push_closure(1, move |ctx| {
+        let s = ctx.safe_to_lstring(0);
+        writer.lock().unwrap().write_all(s.as_bytes())
+            .map_err(|err| JsError::from(format!("write failed: {err}")))?;
+        Ok(Return::Undefined)
+    });
+    ctx.put_prop_string(-2, "write");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writer collecting output for assertions.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn run(runner: Runner, code: &str) -> (i32, String, String) {
+        let (stdout, stderr) = (Output::default(), Output::default());
+        let runner = runner.stdout(stdout.clone()).stderr(stderr.clone());
+        let engine = JsEngine::new().unwrap();
+        let status = runner.run(&engine, "test.js", code);
+        assert_eq!(engine.get_top(), 0);
+        (status, stdout.text(), stderr.text())
+    }
+
+    #[test]
+    fn test_process() {
+        std::env::set_var("KG_JS_RUNNER_TEST", "visible");
+        let runner = Runner::new()
+            .args(["-v", "x y"])
+            .env_filter(EnvFilter::Only(vec!["KG_JS_RUNNER_*".to_string()]))
+            .stdin(io::Cursor::new("first\r\nsecond\nrest\n"));
+        let (status, stdout, stderr) = run(runner, "#!/usr/bin/env kg-js\n\
+            process.stdout.write(JSON.stringify(process.argv) + '\\n');\n\
+            process.stdout.write(JSON.stringify(process.env) + '\\n');\n\
+            process.stderr.write('log');\n\
+            var lines = [process.stdin.readLine(), process.stdin.readLine(), process.stdin.read()];\n\
+            process.stdout.write(JSON.stringify(lines) + ',' + process.stdin.readLine());");
+        assert_eq!(status, EXIT_SUCCESS);
+        assert_eq!(stdout, "[\"kg-js\",\"test.js\",\"-v\",\"x y\"]\n\
+            {\"KG_JS_RUNNER_TEST\":\"visible\"}\n\
+            [\"first\",\"second\",\"rest\\n\"],null");
+        assert_eq!(stderr, "log");
+
+        let (_, stdout, _) = run(Runner::new().env_filter(EnvFilter::None), "process.stdout.write(Object.keys(process.env).length)");
+        assert_eq!(stdout, "0");
+    }
+
+    #[test]
+    fn test_exit() {
+        let (status, stdout, stderr) = run(Runner::new(), "process.stdout.write('a'); process.exit(7); process.stdout.write('b');");
+        assert_eq!((status, stdout.as_str(), stderr.as_str()), (7, "a", ""));
+
+        // catch and finally blocks do not run, nor does code after them
+        let (status, stdout, _) = run(Runner::new(), "function f() {\n\
+              try { process.exit(5); } catch (e) { process.stdout.write('catch'); }\n\
+              finally { process.stdout.write('finally'); }\n\
+            }\n\
+            try { f(); } finally { process.stdout.write('outer'); }\n\
+            process.stdout.write('after');");
+        assert_eq!((status, stdout.as_str()), (5, ""));
+        let (status, stdout, _) = run(Runner::new(), "[1, 2].forEach(function(i) { process.stdout.write(i); process.exit(i); })");
+        assert_eq!((status, stdout.as_str()), (1, "1"));
+        let (status, _, _) = run(Runner::new(), "process.exit()");
+        assert_eq!(status, EXIT_SUCCESS);
+    }
+
+    #[test]
+    fn test_engine_usable_after_exit() {
+        let engine = JsEngine::new().unwrap();
+        let runner = Runner::new().stdout(Output::default());
+        assert_eq!(runner.run(&engine, "test.js", "process.exit(3)"), 3);
+        assert!(!engine.is_terminating());
+        engine.eval("try { 1 + 1 } finally {}").unwrap();
+        assert_eq!(engine.get_number(-1), 2.0);
+    }
+
+    #[test]
+    fn test_uncaught_errors() {
+        let (status, _, stderr) = run(Runner::new(), "function fail() {\n  throw new TypeError('bad value');\n}\nfail();");
+        assert_eq!(status, EXIT_UNCAUGHT_ERROR);
+        assert!(stderr.starts_with("Uncaught TypeError: bad value\n    at fail (test.js:2)"), "{stderr}");
+        assert!(stderr.contains("(test.js:4)"));
+
+        let (status, _, stderr) = run(Runner::new(), "throw 'plain'");
+        assert_eq!((status, stderr.as_str()), (EXIT_UNCAUGHT_ERROR, "Uncaught plain\n"));
+
+        let (status, _, stderr) = run(Runner::new(), "var x = ;");
+        assert_eq!(status, EXIT_SYNTAX_ERROR);
+        assert!(stderr.starts_with("Uncaught SyntaxError"));
+
+        let (status, _, _) = run(Runner::new(), "eval('1 +')");
+        assert_eq!(status, EXIT_SYNTAX_ERROR);
+
+        let map = JsEngine::new().unwrap()
+            .parse_source_map(r#"{"version": 3, "sources": ["test.ts"], "mappings": ";AAEE;;AAII"}"#).unwrap();
+        let (status, _, stderr) = run(Runner::new().source_map(map), "function fail() {\n  throw new TypeError('bad value');\n}\nfail();");
+        assert_eq!(status, EXIT_UNCAUGHT_ERROR);
+        assert!(stderr.starts_with("Uncaught TypeError: bad value\n    at fail (test.ts:3:3)"), "{stderr}");
+        assert!(stderr.contains("(test.ts:7:7)"), "{stderr}");
+
+        let stderr = Output::default();
+        let engine = JsEngine::new().unwrap();
+        let status = Runner::new().stderr(stderr.clone()).run_file(&engine, "/nonexistent/script.js");
+        assert_eq!(status, EXIT_IO_ERROR);
+        assert!(stderr.text().starts_with("kg-js: cannot read /nonexistent/script.js"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use super::*;
+
+/// Source of unique stash keys, so keys are not reused by a later script.
+static SCRIPT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Source of realm ids, which key the instances of a script.
+static REALM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Hidden property of the global stash of a realm holding its id.
+const REALM_ID_PROP: &str = "script_realm_id";
+
+/// Compiled script that can be run any number of times without recompiling.
+///
+/// The compiled function is referenced from the heap stash until the script is dropped.
+/// Functions bind global variables of the realm they are created in, so the script is
+/// instantiated from its bytecode once for every other realm it runs in. Instances are kept in
+/// the same stash entry and keep their realm alive until [`Script::release`] or until the script
+/// is dropped. Like a [`Realm`], a script does not borrow its engine; running it after the engine
+/// is dropped fails.
+pub struct Script {
+    heap: HeapHandle,
+    bytecode: Vec<u8>,
+    stash_key: String,
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script")
+            .field("stash_key", &self.stash_key)
+            .field("bytecode_len", &self.bytecode.len())
+            .finish()
+    }
+}
+
+impl Script {
+    /// Compiles `code` in the global environment of `engine`.
+    pub fn compile(engine: &JsEngine, code: &str, options: &CompileOptions) -> Result<Self, JsError> {
+        // the function is kept alive by the stash entry, which a poisoned engine does not write
+        engine.check_poisoned()?;
+        engine.check_stack(4)?;
+        engine.compile_with(code, options)?;
+        let bytecode = match engine.dump_function(-1) {
+            Ok(bytecode) => bytecode,
+            Err(err) => {
+                engine.pop();
+                return Err(err);
+            }
+        };
+        let stash_key = format!("script:{}", SCRIPT_ID.fetch_add(1, Ordering::Relaxed));
+
+        // [ func ] the compiled function is the instance of the engine's realm
+        let realm_id = realm_id(engine);
+        engine.push_heap_stash();
+        engine.push_object();
+        engine.dup(-3);
+        engine.put_prop_string(-2, &realm_id);
+        engine.put_prop_string(-2, &stash_key);
+        engine.pop_n(2);
+
+        Ok(Script { heap: engine.handle(), bytecode, stash_key })
+    }
+
+    /// Runs the script in the realm of `ctx` and pushes its result.
+    pub fn run(&self, ctx: &DukContext) -> Result<(), JsError> {
+        ctx.check_stack(4)?;
+        ctx.push_undefined();
+        self.call(ctx)
+    }
+
+    /// Runs the script in the realm of `ctx` with `this` bound to the value at `this_index`,
+    /// and pushes its result.
+    pub fn run_with_this(&self, ctx: &DukContext, this_index: i32) -> Result<(), JsError> {
+        ctx.check_stack(4)?;
+        ctx.dup(this_index);
+        self.call(ctx)
+    }
+
+    /// Releases the instance of the realm of `ctx`. The script is instantiated again when it
+    /// runs in that realm later.
+    pub fn release(&self, ctx: &DukContext) {
+        if !self.heap.owns(ctx) || ctx.is_poisoned() || ctx.check_stack(3).is_err() {
+            return;
+        }
+        let realm_id = realm_id(ctx);
+        ctx.push_heap_stash();
+        if ctx.get_prop_string(-1, &self.stash_key) {
+            ctx.del_prop_string(-1, &realm_id);
+        }
+        ctx.pop_n(2);
+    }
+
+    /// Returns the bytecode of the compiled script.
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    fn call(&self, ctx: &DukContext) -> Result<(), JsError> {
+        // [ this ]
+        if let Err(err) = self.push_instance(ctx) {
+            ctx.pop();
+            return Err(err);
+        }
+        ctx.swap(-1, -2);
+        let res = ctx.pcall_method(0);
+        ctx.propagate_js_error(res)
+    }
+
+    fn push_instance(&self, ctx: &DukContext) -> Result<(), JsError> {
+        if !self.heap.owns(ctx) {
+            return Err(JsError::from("script belongs to another engine".to_string()));
+        }
+        ctx.check_poisoned()?;
+        let realm_id = realm_id(ctx);
+        ctx.push_heap_stash();
+        ctx.get_prop_string(-1, &self.stash_key);
+        ctx.remove(-2);
+        // [ instances ]
+        if !ctx.get_prop_string(-1, &realm_id) {
+            ctx.pop();
+            // bytecode was dumped from a function of the same heap
+            if let Err(err) = unsafe { ctx.load_function(&self.bytecode) } {
+                ctx.pop();
+                return Err(err);
+            }
+            ctx.dup(-1);
+            ctx.put_prop_string(-3, &realm_id);
+        }
+        ctx.remove(-2);
+        Ok(())
+    }
+}
+
+/// Returns the id of the realm of `ctx`, assigning one on first use.
+fn realm_id(ctx: &DukContext) -> String {
+    ctx.push_global_stash();
+    let id = if ctx.get_hidden_prop(-1, REALM_ID_PROP) {
+        ctx.get_number(-1) as u64
+    } else {
+        let id = REALM_ID.fetch_add(1, Ordering::Relaxed);
+        ctx.push_number(id as f64);
+        ctx.put_hidden_prop(-3, REALM_ID_PROP);
+        id
+    };
+    ctx.pop_n(2);
+    id.to_string()
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let Some(engine) = self.heap.ctx() else {
+            return;
+        };
+        if engine.is_poisoned() {
+            return;
+        }
+        engine.push_heap_stash();
+        engine.del_prop_string(-1, &self.stash_key);
+        engine.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_number(script: &Script, ctx: &DukContext) -> f64 {
+        script.run(ctx).unwrap();
+        let n = ctx.get_number(-1);
+        ctx.pop();
+        n
+    }
+
+    #[test]
+    fn test_run_repeatedly() {
+        let engine = JsEngine::new().unwrap();
+        let script = Script::compile(&engine, "record.amount * rate", &CompileOptions::new()).unwrap();
+        assert_eq!(engine.get_top(), 0);
+        engine.eval("var rate = 2;").unwrap();
+        engine.pop();
+        for amount in 0..200 {
+            engine.push_object();
+            engine.push_i32(amount);
+            engine.put_prop_string(-2, "amount");
+            engine.put_global_string("record");
+            assert_eq!(run_number(&script, &engine), (amount * 2) as f64);
+        }
+
+        let script = Script::compile(&engine, "this.value + 1", &CompileOptions::new()).unwrap();
+        engine.eval("({ value: 41 })").unwrap();
+        script.run_with_this(&engine, -1).unwrap();
+        assert_eq!(engine.get_number(-1), 42.);
+        engine.pop_n(2);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_run_in_realms() {
+        let engine = JsEngine::new().unwrap();
+        let script = Script::compile(&engine, "var runs = (typeof runs === 'number' ? runs : 0) + 1; name + runs",
+            &CompileOptions::new().filename("realms.js")).unwrap();
+        let first = Realm::new(&engine).unwrap();
+        let second = Realm::new(&engine).unwrap();
+        engine.eval("var name = 'main';").unwrap();
+        engine.pop();
+        first.eval("var name = 'first';").unwrap();
+        first.pop();
+        second.eval("var name = 'second';").unwrap();
+        second.pop();
+
+        for (ctx, expected) in [(&*first, "first1"), (&*second, "second1"), (&*first, "first2"), (&engine, "main1")] {
+            script.run(ctx).unwrap();
+            assert_eq!(ctx.get_string(-1), expected);
+            ctx.pop();
+        }
+
+        engine.push_thread_new_globalenv();
+        let thread = engine.get_context(-1).unwrap();
+        thread.eval("var name = 'thread';").unwrap();
+        thread.pop();
+        script.run(&thread).unwrap();
+        assert_eq!(thread.get_string(-1), "thread1");
+        thread.pop();
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_errors_and_drop() {
+        let engine = JsEngine::new().unwrap();
+        assert!(Script::compile(&engine, "1 +", &CompileOptions::new()).is_err());
+        let script = Script::compile(&engine, "throw new RangeError('bad record')", &CompileOptions::new()).unwrap();
+        let err = script.run(&engine).unwrap_err();
+        assert!(err.to_string().contains("RangeError: bad record"));
+        assert_eq!(engine.get_top(), 0);
+
+        let other = JsEngine::new().unwrap();
+        assert!(script.run(&other).is_err());
+        assert_eq!(other.get_top(), 0);
+
+        let script = Script::compile(&engine, "var big = []; for (var i = 0; i < 10000; i++) big.push({i: i}); big.length",
+            &CompileOptions::new()).unwrap();
+        let realm = Realm::new(&engine).unwrap();
+        assert_eq!(run_number(&script, &realm), 10000.);
+        engine.gc();
+        let size = engine.heap_size();
+        let stash_key = script.stash_key.clone();
+        drop(realm);
+        drop(script);
+        engine.gc();
+        assert!(engine.heap_size() < size);
+        engine.push_heap_stash();
+        assert!(!engine.get_prop_string(-1, &stash_key));
+        engine.pop_n(2);
+    }
+
+    #[test]
+    fn test_release() {
+        let engine = JsEngine::new().unwrap();
+        let script = Script::compile(&engine, "var big = []; for (var i = 0; i < 10000; i++) big.push({i: i}); big.length",
+            &CompileOptions::new()).unwrap();
+        let gc_size = || {
+            engine.gc();
+            engine.heap_size()
+        };
+        let realm = Realm::new(&engine).unwrap();
+        assert_eq!(run_number(&script, &realm), 10000.);
+        let size = gc_size();
+        // the instance keeps the realm alive until released
+        drop(realm);
+        assert!(gc_size() > size - 100000);
+
+        let realm = Realm::new(&engine).unwrap();
+        assert_eq!(run_number(&script, &realm), 10000.);
+        let size = gc_size();
+        script.release(&realm);
+        drop(realm);
+        assert!(gc_size() < size - 100000);
+
+        script.release(&engine);
+        assert_eq!(run_number(&script, &engine), 10000.);
+        assert_eq!(engine.get_top(), 0);
+
+        let size = gc_size();
+        drop(script);
+        assert!(gc_size() < size - 100000);
+    }
+
+    #[test]
+    fn test_script_outlives_engine() {
+        let engine = JsEngine::new().unwrap();
+        let script = Script::compile(&engine, "1 + 1", &CompileOptions::new()).unwrap();
+        drop(engine);
+        let other = JsEngine::new().unwrap();
+        assert!(script.run(&other).is_err());
+        assert_eq!(other.get_top(), 0);
+        drop(script);
+    }
+}
@@ -78,7 +78,7 @@ impl<'a> Serializer for JsEngineSerializer<'a> {
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.ctx.push_u32(v as u32);
+        self.ctx.push_u32(v);
         Ok(())
     }
 
@@ -118,7 +118,7 @@ impl<'a> Serializer for JsEngineSerializer<'a> {
         Ok(())
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> where T: Serialize {
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
         value.serialize(self)
     }
 
@@ -137,11 +137,11 @@ impl<'a> Serializer for JsEngineSerializer<'a> {
         Ok(())
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> where T: Serialize {
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(self, _name: &'static str, _variant_index: u32, variant: &'static str, value: &T) -> Result<Self::Ok, Self::Error> where T: Serialize {
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _variant_index: u32, variant: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
         self.ctx.push_object();
         value.serialize(JsEngineSerializer { ctx: self.ctx, index: 0 })?;
         self.ctx.put_prop_string(-2, variant);
@@ -196,7 +196,7 @@ impl<'a> SerializeSeq for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
         value.serialize(JsEngineSerializer { ctx: self.ctx, index: 0 })?;
         self.ctx.put_prop_index(-2, self.index);
         self.index += 1;
@@ -212,7 +212,7 @@ impl<'a> SerializeTuple for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
         SerializeSeq::serialize_element(self, value)
     }
 
@@ -225,7 +225,7 @@ impl<'a> SerializeTupleVariant for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
         SerializeSeq::serialize_element(self, value)
     }
 
@@ -239,7 +239,7 @@ impl<'a> SerializeTupleStruct for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
         SerializeSeq::serialize_element(self, value)
     }
 
@@ -252,7 +252,7 @@ impl<'a> SerializeStruct for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
         value.serialize(JsEngineSerializer { ctx: self.ctx, index: 0 })?;
         self.ctx.put_prop_string(-2, key);
         Ok(())
@@ -267,7 +267,7 @@ impl<'a> SerializeStructVariant for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
         SerializeStruct::serialize_field(self, key, value)
     }
 
@@ -281,11 +281,11 @@ impl<'a> SerializeMap for JsEngineSerializer<'a> {
     type Ok = ();
     type Error = JsError;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
         key.serialize(JsEngineSerializer { ctx: self.ctx, index: 0 })
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
         value.serialize(JsEngineSerializer { ctx: self.ctx, index: 0 })?;
         self.ctx.put_prop(-3);
         Ok(())
@@ -0,0 +1,434 @@
+//! Heap snapshots in the format of V8 `.heapsnapshot` files.
+//!
+//! [`HeapSnapshot::write_json`] writes the JSON format loaded by the Memory panel of Chrome
+//! DevTools:
+//!
+//! ```text
+//! {
+//!   "snapshot": { "meta": { "node_fields": [...], "node_types": [...], "edge_fields": [...],
+//!                           "edge_types": [...], ... },
+//!                 "node_count": N, "edge_count": E, "trace_function_count": 0 },
+//!   "nodes": [type, name, id, self_size, edge_count, trace_node_id, ...],
+//!   "edges": [type, name_or_index, to_node, ...],
+//!   "strings": [...],
+//!   ...
+//! }
+//! ```
+//!
+//! Nodes and edges are flattened into arrays of integers described by `meta`. Types are indexes
+//! into `node_types[0]` and `edge_types[0]`, names are indexes into `strings`, except for
+//! `element` and `hidden` edges, whose name is an array index. The edges of a node follow the
+//! edges of the previous node, and `to_node` is the offset of the target node in `nodes`. The
+//! first node is a synthetic root referencing the GC roots of the heap.
+//!
+//! Duktape values are mapped as follows:
+//!
+//! | Duktape value                 | node type  | node name                                     |
+//! |-------------------------------|------------|-----------------------------------------------|
+//! | function                      | `closure`  | function name, `(anonymous)` without name     |
+//! | `RegExp` object               | `regexp`   | `RegExp`                                      |
+//! | scope (`DecEnv`, `ObjEnv`)    | `hidden`   | Duktape class                                 |
+//! | other object                  | `object`   | constructor name for plain objects, otherwise |
+//! |                               |            | Duktape class, e.g. `Array` or `Thread`       |
+//! | string                        | `string`   | string data                                   |
+//! | symbol                        | `symbol`   | `Symbol(description)`                         |
+//! | buffer, e.g. bytecode         | `native`   | `Buffer`                                      |
+//!
+//! Edges are `property` edges named by property key, `element` edges for the array part of
+//! objects, `internal` edges for references held by Duktape, e.g. `__proto__` or
+//! `lexical scope`, and `hidden` edges for value stack slots and function constants.
+//! Accessors are `property` edges named `get key` and `set key`, hidden symbol keys are
+//! `internal` edges named `[[name]]`.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_void};
+use super::*;
+
+const NODE_TYPES: [&str; 13] = [
+    "hidden", "array", "string", "object", "code", "closure", "regexp", "number", "native",
+    "synthetic", "concatenated string", "sliced string", "symbol",
+];
+const EDGE_TYPES: [&str; 7] = ["context", "element", "property", "internal", "hidden", "shortcut", "weak"];
+const NODE_FIELD_COUNT: usize = 6;
+
+/// Type of a [`HeapNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeapNodeKind {
+    /// Synthetic root node, the first node of a snapshot.
+    Root,
+    Object,
+    String,
+    Symbol,
+    Buffer,
+}
+
+/// Type of a [`HeapEdge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeapEdgeKind {
+    /// Property named by its key.
+    Property,
+    /// Value in the array part of an object, named by its index.
+    Element,
+    /// Reference held by Duktape, e.g. `__proto__`, or a property with a hidden symbol key.
+    Internal,
+    /// Indexed reference held by Duktape, e.g. a value stack slot.
+    Hidden,
+}
+
+/// Heap allocated value in a [`HeapSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapNode {
+    pub kind: HeapNodeKind,
+    /// Address of the value, only valid as an identity while the value is alive.
+    pub address: usize,
+    /// Duktape class of an object.
+    pub class: Option<&'static str>,
+    /// Function name for functions, constructor name for plain objects, otherwise the
+    /// Duktape class, or the data of a string.
+    pub name: String,
+    /// Size of the value, computed like in [`DukContext::inspect_value`].
+    pub self_size: usize,
+    pub edges: Vec<HeapEdge>,
+}
+
+/// Reference from a [`HeapNode`] to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapEdge {
+    pub kind: HeapEdgeKind,
+    /// Property key or label, the index for [`HeapEdgeKind::Element`] and [`HeapEdgeKind::Hidden`].
+    pub name: String,
+    /// Index of the referenced node in [`HeapSnapshot::nodes`].
+    pub to: usize,
+}
+
+/// Snapshot of all values in a heap and their references, see [`DukContext::heap_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    /// Nodes in heap order, starting with the [`HeapNodeKind::Root`] node.
+    pub nodes: Vec<HeapNode>,
+}
+
+impl HeapSnapshot {
+    /// Returns the first node named `name`.
+    pub fn find(&self, name: &str) -> Option<&HeapNode> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    /// Returns the number of edges of all nodes.
+    pub fn edge_count(&self) -> usize {
+        self.nodes.iter().map(|node| node.edges.len()).sum()
+    }
+
+    /// Writes the snapshot in the `.heapsnapshot` JSON format of V8, which can be loaded in the
+    /// Memory panel of Chrome DevTools.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut strings = StringTable::default();
+        write!(out, "{{\"snapshot\":{{\"meta\":{{\"node_fields\":[\"type\",\"name\",\"id\",\"self_size\",\"edge_count\",\"trace_node_id\"],")?;
+        write!(out, "\"node_types\":[{},\"string\",\"number\",\"number\",\"number\",\"number\"],", json_list(&NODE_TYPES))?;
+        write!(out, "\"edge_fields\":[\"type\",\"name_or_index\",\"to_node\"],")?;
+        write!(out, "\"edge_types\":[{},\"string_or_number\",\"node\"],", json_list(&EDGE_TYPES))?;
+        write!(out, "\"trace_function_info_fields\":[],\"trace_node_fields\":[],\"sample_fields\":[],\"location_fields\":[]}},")?;
+        write!(out, "\"node_count\":{},\"edge_count\":{},\"trace_function_count\":0}},", self.nodes.len(), self.edge_count())?;
+
+        write!(out, "\n\"nodes\":[")?;
+        for (index, node) in self.nodes.iter().enumerate() {
+            let node_type = match node.kind {
+                HeapNodeKind::Root => "synthetic",
+                HeapNodeKind::String => "string",
+                HeapNodeKind::Symbol => "symbol",
+                HeapNodeKind::Buffer => "native",
+                HeapNodeKind::Object => match node.class {
+                    Some("Function") => "closure",
+                    Some("RegExp") => "regexp",
+                    Some("DecEnv" | "ObjEnv") => "hidden",
+                    _ => "object",
+                },
+            };
+            let sep = if index == 0 { "" } else { ",\n" };
+            write!(out, "{sep}{},{},{},{},{},0", type_index(&NODE_TYPES, node_type), strings.index(&node.name),
+                   index * 2 + 1, node.self_size, node.edges.len())?;
+        }
+
+        write!(out, "],\n\"edges\":[")?;
+        let mut first = true;
+        for edge in self.nodes.iter().flat_map(|node| &node.edges) {
+            let (edge_type, name) = match edge.kind {
+                HeapEdgeKind::Property => ("property", strings.index(&edge.name)),
+                HeapEdgeKind::Internal => ("internal", strings.index(&edge.name)),
+                HeapEdgeKind::Element => ("element", edge.name.parse().unwrap_or_default()),
+                HeapEdgeKind::Hidden => ("hidden", edge.name.parse().unwrap_or_default()),
+            };
+            let sep = if first { "" } else { ",\n" };
+            first = false;
+            write!(out, "{sep}{},{},{}", type_index(&EDGE_TYPES, edge_type), name, edge.to * NODE_FIELD_COUNT)?;
+        }
+
+        write!(out, "],\n\"trace_function_infos\":[],\"trace_tree\":[],\"samples\":[],\"locations\":[],\n\"strings\":[")?;
+        for (index, s) in strings.strings.iter().enumerate() {
+            if index > 0 {
+                out.write_all(b",\n")?;
+            }
+            write_json_string(&mut out, s)?;
+        }
+        out.write_all(b"]}\n")
+    }
+
+    /// Returns the snapshot in the `.heapsnapshot` JSON format.
+    pub fn to_json(&self) -> String {
+        let mut out = Vec::new();
+        self.write_json(&mut out).expect("writing to a vector cannot fail");
+        String::from_utf8(out).expect("snapshot JSON is valid UTF-8")
+    }
+}
+
+impl DukContext {
+    /// Takes a snapshot of all values in the heap, including garbage not collected yet.
+    ///
+    /// The heap is walked without allocating or running any code, so the snapshot can be taken
+    /// while the engine is paused, e.g. from a native function.
+    pub fn heap_snapshot(&self) -> HeapSnapshot {
+        let mut walk = Walk {
+            nodes: vec![HeapNode {
+                kind: HeapNodeKind::Root,
+                address: 0,
+                class: None,
+                name: "(GC roots)".to_string(),
+                self_size: 0,
+                edges: Vec::new(),
+            }],
+            edges: vec![Vec::new()],
+        };
+        let visitor = duk_api_heap_visitor {
+            udata: &mut walk as *mut Walk as *mut c_void,
+            node: walk_node,
+            edge: walk_edge,
+        };
+        unsafe { duk_api_heap_walk(self.ctx, &visitor) };
+        walk.finish()
+    }
+}
+
+/// Nodes and their edges with target addresses, collected during a heap walk.
+struct Walk {
+    nodes: Vec<HeapNode>,
+    edges: Vec<Vec<(HeapEdgeKind, String, usize)>>,
+}
+
+impl Walk {
+    fn finish(mut self) -> HeapSnapshot {
+        let indexes: HashMap<usize, usize> = self.nodes.iter().enumerate().map(|(i, node)| (node.address, i)).collect();
+        for (node, edges) in self.nodes.iter_mut().zip(self.edges) {
+            node.edges = edges.into_iter()
+                .filter_map(|(kind, name, to)| indexes.get(&to).map(|&to| HeapEdge { kind, name, to }))
+                .collect();
+        }
+
+        // name plain objects by the constructor of their prototype, like V8 does
+        let constructor_names: Vec<Option<String>> = self.nodes.iter()
+            .map(|node| {
+                let proto = edge_target(node, HeapEdgeKind::Internal, "__proto__")?;
+                let constructor = edge_target(&self.nodes[proto], HeapEdgeKind::Property, "constructor")?;
+                let constructor = &self.nodes[constructor];
+                (node.class == Some("Object") && constructor.class == Some("Function") && constructor.name != "(anonymous)")
+                    .then(|| constructor.name.clone())
+            })
+            .collect();
+        for (node, name) in self.nodes.iter_mut().zip(constructor_names) {
+            if let Some(name) = name {
+                node.name = name;
+            }
+        }
+        HeapSnapshot { nodes: self.nodes }
+    }
+}
+
+fn edge_target(node: &HeapNode, kind: HeapEdgeKind, name: &str) -> Option<usize> {
+    node.edges.iter().find(|edge| edge.kind == kind && edge.name == name).map(|edge| edge.to)
+}
+
+extern "C" fn walk_node(udata: *mut c_void, ptr: *mut c_void, node_type: u32, class: u32, size: usize,
+                        name: *const c_char, name_len: usize) {
+    let walk = unsafe { &mut *(udata as *mut Walk) };
+    let name = unsafe { bytes(name, name_len) };
+    let (kind, class, name) = match node_type {
+        DUK_API_NODE_STRING => {
+            let name = name.unwrap_or_default();
+            match key_name(name) {
+                (HeapEdgeKind::Property, name) => (HeapNodeKind::String, None, name),
+                (_, name) => (HeapNodeKind::Symbol, None, name),
+            }
+        }
+        DUK_API_NODE_OBJECT => {
+            let class = class_name(class as usize);
+            let name = match name {
+                Some(name) => String::from_utf8_lossy(name).into_owned(),
+                None if class == "Function" => "(anonymous)".to_string(),
+                None => class.to_string(),
+            };
+            (HeapNodeKind::Object, Some(class), name)
+        }
+        _ => (HeapNodeKind::Buffer, None, "Buffer".to_string()),
+    };
+    walk.nodes.push(HeapNode { kind, address: ptr as usize, class, name, self_size: size, edges: Vec::new() });
+    walk.edges.push(Vec::new());
+}
+
+extern "C" fn walk_edge(udata: *mut c_void, from: *mut c_void, to: *mut c_void, edge_type: u32,
+                        name: *const c_char, name_len: usize, index: usize) {
+    let walk = unsafe { &mut *(udata as *mut Walk) };
+    let name = unsafe { bytes(name, name_len) }.unwrap_or_default();
+    let (kind, name) = match edge_type {
+        DUK_API_EDGE_PROPERTY => key_name(name),
+        DUK_API_EDGE_GETTER => (HeapEdgeKind::Property, format!("get {}", key_name(name).1)),
+        DUK_API_EDGE_SETTER => (HeapEdgeKind::Property, format!("set {}", key_name(name).1)),
+        DUK_API_EDGE_ELEMENT => (HeapEdgeKind::Element, index.to_string()),
+        DUK_API_EDGE_INTERNAL => (HeapEdgeKind::Internal, String::from_utf8_lossy(name).into_owned()),
+        DUK_API_EDGE_HIDDEN => (HeapEdgeKind::Hidden, index.to_string()),
+        _ => return,
+    };
+    // edges follow the node they belong to, GC roots come first
+    let edges = if from.is_null() { &mut walk.edges[0] } else { walk.edges.last_mut().unwrap() };
+    edges.push((kind, name, to as usize));
+}
+
+unsafe fn bytes<'a>(ptr: *const c_char, len: usize) -> Option<&'a [u8]> {
+    (!ptr.is_null()).then(|| std::slice::from_raw_parts(ptr as *const u8, len))
+}
+
+/// Renders a property key; symbols are `Symbol(description)`, hidden symbols `[[name]]`.
+fn key_name(key: &[u8]) -> (HeapEdgeKind, String) {
+    match key.split_first() {
+        Some((0x80, rest)) => (HeapEdgeKind::Property, format!("Symbol({})", String::from_utf8_lossy(rest))),
+        Some((0x81, rest)) => {
+            let desc = rest.split(|b| *b == 0xFF).next().unwrap_or_default();
+            (HeapEdgeKind::Property, format!("Symbol({})", String::from_utf8_lossy(desc)))
+        }
+        Some((0x82 | 0xFF, rest)) => (HeapEdgeKind::Internal, format!("[[{}]]", String::from_utf8_lossy(rest))),
+        _ => (HeapEdgeKind::Property, String::from_utf8_lossy(key).into_owned()),
+    }
+}
+
+fn type_index(types: &[&str], name: &str) -> usize {
+    types.iter().position(|t| *t == name).unwrap_or_default()
+}
+
+fn json_list(items: &[&str]) -> String {
+    format!("[{}]", items.iter().map(|item| format!("\"{item}\"")).collect::<Vec<_>>().join(","))
+}
+
+fn write_json_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[derive(Default)]
+struct StringTable {
+    strings: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn index(&mut self, s: &str) -> usize {
+        if let Some(&index) = self.indexes.get(s) {
+            return index;
+        }
+        self.strings.push(s.to_string());
+        self.indexes.insert(s.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target<'a>(snapshot: &'a HeapSnapshot, node: &HeapNode, name: &str) -> &'a HeapNode {
+        let edge = node.edges.iter().find(|edge| edge.name == name).unwrap();
+        &snapshot.nodes[edge.to]
+    }
+
+    #[test]
+    fn test_heap_snapshot() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval(r#"
+            function Session(user) { this.user = user; this.tags = ['a', 'b']; }
+            var cache = { current: new Session('alice-session-user') };
+            cache[Symbol('meta')] = {};
+            Object.defineProperty(cache, 'size', { get: function () { return 1; } });
+        "#).unwrap();
+        engine.pop();
+        let stats = engine.heap_stats();
+        let top = engine.get_top();
+        let snapshot = engine.heap_snapshot();
+        assert_eq!(engine.get_top(), top);
+
+        let root = &snapshot.nodes[0];
+        assert_eq!(root.kind, HeapNodeKind::Root);
+        assert!(root.edges.iter().any(|edge| edge.name == "heap thread"));
+        let objects = snapshot.nodes.iter().filter(|node| node.kind == HeapNodeKind::Object).count();
+        assert_eq!(objects, stats.objects.count);
+
+        let session = snapshot.find("Session").unwrap();
+        assert_eq!(session.class, Some("Object"));
+        assert_eq!(target(&snapshot, session, "user").name, "alice-session-user");
+        let tags = target(&snapshot, session, "tags");
+        assert_eq!(tags.class, Some("Array"));
+        assert!(tags.edges.iter().any(|edge| edge.kind == HeapEdgeKind::Element && edge.name == "1"));
+
+        let cache = snapshot.nodes.iter()
+            .find(|node| node.edges.iter().any(|edge| edge.name == "current" && snapshot.nodes[edge.to].name == "Session"))
+            .unwrap();
+        assert!(cache.edges.iter().any(|edge| edge.name == "Symbol(meta)"));
+        assert_eq!(target(&snapshot, cache, "get size").class, Some("Function"));
+        assert_eq!(target(&snapshot, cache, "__proto__").class, Some("Object"));
+    }
+
+    #[test]
+    fn test_heap_snapshot_from_native_function() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_closure(1, |ctx| {
+            let snapshot = ctx.heap_snapshot();
+            let found = snapshot.find(ctx.get_string(0)).is_some();
+            ctx.push_boolean(found);
+            Ok(Return::Top)
+        });
+        engine.put_global_string("inSnapshot");
+        engine.eval("var marker = 'snapshot-marker-string'; inSnapshot(marker)").unwrap();
+        assert!(engine.get_boolean(-1));
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_heap_snapshot_json() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var text = 'quote \" and\\nnewline';").unwrap();
+        engine.pop();
+        let snapshot = engine.heap_snapshot();
+        let json = snapshot.to_json();
+        assert!(json.contains("\"quote \\\" and\\nnewline\""));
+
+        // validate the layout by parsing the output with the engine
+        engine.json_decode(&json, JsonFormat::Json).unwrap();
+        engine.put_global_string("heap");
+        engine.eval("var s = heap.snapshot, f = s.meta.node_fields.length; \
+            heap.nodes.length / f === s.node_count && heap.edges.length / 3 === s.edge_count && \
+            heap.strings[heap.nodes[1]] === '(GC roots)' && \
+            heap.edges.every(function (v, i) { return i % 3 !== 2 || v % f === 0 && v / f < s.node_count; })").unwrap();
+        assert!(engine.get_boolean(-1));
+        engine.pop();
+        assert_eq!(engine.get_top(), 0);
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use super::*;
+
+/// Hidden property marking an `Error.prototype` whose `stack` getter applies source maps.
+const STACK_REWRITE_PROP: &str = "source_map_stack";
+
+/// Replaces the inherited `stack` getter of the error prototype `proto`, keeping the setter.
+const STACK_GETTER: &str = "function (rewrite, proto) {
+    var desc = Object.getOwnPropertyDescriptor(proto, 'stack');
+    if (!desc || typeof desc.get !== 'function') return;
+    var getter = desc.get;
+    Object.defineProperty(proto, 'stack', {
+        get: function () {
+            var stack = getter.call(this);
+            return typeof stack === 'string' ? rewrite(stack) : stack;
+        }
+    });
+}";
+
+/// Position in an original source file, with 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OriginalPosition {
+    pub source: String,
+    pub line: u32,
+    pub column: u32,
+    pub name: Option<String>,
+}
+
+impl std::fmt::Display for OriginalPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Mapping {
+    column: u32,
+    /// Source index, original line, original column and name index, all 0-based.
+    original: Option<(u32, u32, u32, Option<u32>)>,
+}
+
+/// Parsed [source map v3](https://sourcemaps.info/spec.html), mapping positions in generated
+/// code back to the original sources.
+///
+/// Index maps with `sections` are not supported.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceMap {
+    file: Option<String>,
+    sources: Vec<String>,
+    names: Vec<String>,
+    /// Mappings by generated line, sorted by generated column.
+    lines: Vec<Vec<Mapping>>,
+}
+
+impl SourceMap {
+    /// Reads a source map from the object at `obj_index`, e.g. decoded with
+    /// [`DukContext::json_decode`]. Source paths are prefixed with `sourceRoot`.
+    fn from_object(ctx: &DukContext, obj_index: i32) -> Result<Self, JsError> {
+        let obj_index = ctx.normalize_index(obj_index);
+        if !ctx.is_object(obj_index) || ctx.is_array(obj_index) {
+            return Err(invalid("expected an object"));
+        }
+        let string = |name: &str| {
+            ctx.get_prop_string(obj_index, name);
+            let value = (ctx.is_string(-1) && !ctx.is_symbol(-1)).then(|| ctx.get_string(-1).to_string());
+            ctx.pop();
+            value
+        };
+
+        ctx.get_prop_string(obj_index, "version");
+        let version = ctx.is_number(-1).then(|| ctx.get_number(-1));
+        ctx.pop();
+        if version != Some(3.) {
+            return Err(invalid("unsupported version, expected 3"));
+        }
+        if ctx.has_prop_string(obj_index, "sections") {
+            return Err(invalid("index maps are not supported"));
+        }
+        let file = string("file");
+        let root = match string("sourceRoot") {
+            Some(root) if !root.is_empty() => root.trim_end_matches('/').to_string() + "/",
+            _ => String::new(),
+        };
+        let sources = strings(ctx, obj_index, "sources")?.into_iter()
+            .map(|s| format!("{root}{s}"))
+            .collect();
+        let names = if ctx.has_prop_string(obj_index, "names") {
+            strings(ctx, obj_index, "names")?
+        } else {
+            Vec::new()
+        };
+        let Some(mappings) = string("mappings") else {
+            return Err(invalid("missing mappings"));
+        };
+
+        let mut map = SourceMap { file, sources, names, lines: Vec::new() };
+        map.decode_mappings(&mappings)?;
+        Ok(map)
+    }
+
+    fn decode_mappings(&mut self, mappings: &str) -> Result<(), JsError> {
+        // fields other than the generated column are relative to the previous segment in the map
+        let (mut source, mut line, mut column, mut name) = (0i64, 0i64, 0i64, 0i64);
+        for line_mappings in mappings.split(';') {
+            let mut segments = Vec::new();
+            let mut gen_column = 0i64;
+            for segment in line_mappings.split(',').filter(|s| !s.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                gen_column += fields[0];
+                let original = match fields.len() {
+                    1 => None,
+                    4 | 5 => {
+                        source += fields[1];
+                        line += fields[2];
+                        column += fields[3];
+                        let name = if fields.len() == 5 {
+                            name += fields[4];
+                            if name < 0 || name as usize >= self.names.len() {
+                                return Err(invalid("name index out of range"));
+                            }
+                            Some(name as u32)
+                        } else {
+                            None
+                        };
+                        if source < 0 || source as usize >= self.sources.len() {
+                            return Err(invalid("source index out of range"));
+                        }
+                        if line < 0 || column < 0 {
+                            return Err(invalid("negative original position"));
+                        }
+                        Some((source as u32, line as u32, column as u32, name))
+                    }
+                    _ => return Err(invalid(&format!("segment {segment:?} has {} fields", fields.len()))),
+                };
+                if gen_column < 0 {
+                    return Err(invalid("negative generated column"));
+                }
+                segments.push(Mapping { column: gen_column as u32, original });
+            }
+            segments.sort_by_key(|m| m.column);
+            self.lines.push(segments);
+        }
+        Ok(())
+    }
+
+    /// Returns the generated file name recorded in the map.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// Returns the original position of the 1-based `line` and `column` in generated code.
+    ///
+    /// Columns before the first mapping of the line, e.g. when only the line is known as in
+    /// Duktape stack traces, resolve to the first mapping of the line.
+    pub fn lookup(&self, line: u32, column: u32) -> Option<OriginalPosition> {
+        let segments = self.lines.get(line.checked_sub(1)? as usize)?;
+        let column = column.saturating_sub(1);
+        let mapping = match segments.iter().rposition(|m| m.column <= column) {
+            Some(i) => &segments[i],
+            None => segments.iter().find(|m| m.original.is_some())?,
+        };
+        let (source, line, column, name) = mapping.original?;
+        Some(OriginalPosition {
+            source: self.sources[source as usize].clone(),
+            line: line + 1,
+            column: column + 1,
+            name: name.map(|n| self.names[n as usize].clone()),
+        })
+    }
+}
+
+impl DukContext {
+    /// Parses a source map from its JSON text. Source paths are prefixed with `sourceRoot`.
+    pub fn parse_source_map(&self, json: &str) -> Result<SourceMap, JsError> {
+        self.check_stack(2)?;
+        match self.json_decode(json, JsonFormat::Json) {
+            Ok(()) => {}
+            Err(err) if err.is_poisoned() => return Err(err),
+            Err(err) => return Err(invalid(&String::from(err))),
+        }
+        let map = SourceMap::from_object(self, -1);
+        self.pop();
+        map
+    }
+
+    /// Registers `map` for code compiled with `filename`, replacing a map registered before.
+    ///
+    /// Maps are shared by all realms of the heap. Stack traces of errors reported as
+    /// [`JsError`] are rewritten to original positions, and so is the `stack` property of errors
+    /// in the realm of this context and in realms that later compile code of `filename`.
+    pub fn register_source_map<M: Into<Arc<SourceMap>>>(&self, filename: &str, map: M) -> Result<(), JsError> {
+        self.check_stack(4)?;
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        unsafe { (*udata).source_maps.insert(filename.to_string(), map.into()) };
+        self.install_stack_rewrite()
+    }
+
+    /// Returns the source map registered for `filename`.
+    pub fn source_map(&self, filename: &str) -> Option<Arc<SourceMap>> {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        unsafe { (*udata).source_maps.get(filename).cloned() }
+    }
+
+    /// Rewrites `file:line` locations of stack trace frames in files with a registered source
+    /// map to `source:line:column`.
+    pub fn map_stack(&self, stack: &str) -> String {
+        self.map_stack_frames(stack).unwrap_or_else(|| stack.to_string())
+    }
+
+    /// Returns the rewritten stack trace, `None` if no frame was mapped.
+    pub(crate) fn map_stack_frames(&self, stack: &str) -> Option<String> {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        let maps = unsafe { &(*udata).source_maps };
+        if maps.is_empty() {
+            return None;
+        }
+        let mut mapped = false;
+        let lines: Vec<String> = stack.split('\n').map(|line| match frame_location(line) {
+            Some((start, end)) => match map_location(maps, &line[start..end]) {
+                Some(position) => {
+                    mapped = true;
+                    format!("{}{}{}", &line[..start], position, &line[end..])
+                }
+                None => {
+                    // already rewritten by the `stack` getter
+                    mapped |= is_mapped_location(&line[start..end]);
+                    line.to_string()
+                }
+            },
+            None => line.to_string(),
+        }).collect();
+        mapped.then(|| lines.join("\n"))
+    }
+
+    /// Returns the mapped stack trace of the error at the top of the stack.
+    pub(crate) fn mapped_error_stack(&self) -> Option<String> {
+        let udata = unsafe { duk_api_get_heap_udata(self.ctx) } as *mut Userdata;
+        if unsafe { (*udata).source_maps.is_empty() || duk_get_error_code(self.ctx, -1) == 0 } {
+            return None;
+        }
+        // the `stack` getter runs script code and may throw
+        let res = unsafe { duk_api_get_error_stack(self.ctx) };
+        let stack = if res == DUK_EXEC_SUCCESS && self.is_string(-1) {
+            self.map_stack_frames(self.get_string(-1))
+        } else {
+            None
+        };
+        self.pop();
+        stack
+    }
+
+    pub(crate) fn install_stack_rewrite(&self) -> Result<(), JsError> {
+        self.check_stack(4)?;
+        self.push_global_object();
+        // scripts may have deleted or replaced Error
+        let found = self.get_prop_string(-1, "Error") && self.is_object(-1);
+        if found {
+            self.get_prop_string(-1, "prototype");
+            self.remove(-2);
+        }
+        self.remove(-2);
+        // [ proto ]
+        if !found || !self.is_object(-1) || self.has_hidden_prop(-1, STACK_REWRITE_PROP) {
+            self.pop();
+            return Ok(());
+        }
+
+        if let Err(err) = self.compile_with(STACK_GETTER, &CompileOptions::new().mode(CompileMode::Function)) {
+            self.pop();
+            return Err(err);
+        }
+        self.push_closure(1, |ctx| {
+            let stack = ctx.get_string(0).to_string();
+            let stack = ctx.map_stack(&stack);
+            ctx.push_string(&stack);
+            Ok(Return::Top)
+        });
+        self.dup(-3);
+        let res = self.pcall(2);
+        if let Err(err) = self.propagate_js_error(res) {
+            self.pop();
+            return Err(err);
+        }
+        self.pop();
+        // marked only once installed, so that a failed installation is retried
+        self.push_boolean(true);
+        self.put_hidden_prop(-2, STACK_REWRITE_PROP);
+        self.pop();
+        Ok(())
+    }
+}
+
+/// Returns the byte range of the location in a stack trace line like
+/// `    at fn (file.js:12) preventsyield`.
+fn frame_location(line: &str) -> Option<(usize, usize)> {
+    let rest = line.trim_start().strip_prefix("at ")?;
+    let start = line.len() - rest.len() + rest.find(" (")? + 2;
+    let end = start + line[start..].find(')')?;
+    Some((start, end))
+}
+
+/// Maps a `file:line` location.
+fn map_location(maps: &HashMap<String, Arc<SourceMap>>, location: &str) -> Option<OriginalPosition> {
+    let (file, line) = location.rsplit_once(':')?;
+    maps.get(file)?.lookup(line.parse().ok()?, 0)
+}
+
+/// Duktape reports `file:line` locations, only mapped locations have a column.
+fn is_mapped_location(location: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match location.rsplit_once(':').and_then(|(rest, column)| Some((rest.rsplit_once(':')?.1, column))) {
+        Some((line, column)) => is_number(line) && is_number(column),
+        None => false,
+    }
+}
+
+fn invalid(msg: &str) -> JsError {
+    JsError::from(format!("invalid source map: {msg}"))
+}
+
+/// Reads the array of strings in property `name` of the object at `obj_index`.
+fn strings(ctx: &DukContext, obj_index: i32, name: &str) -> Result<Vec<String>, JsError> {
+    ctx.get_prop_string(obj_index, name);
+    let items = if ctx.is_array(-1) {
+        use DukType::{DUK_TYPE_NULL, DUK_TYPE_STRING};
+        (0..ctx.get_length(-1) as u32).map(|i| {
+            ctx.get_prop_index(-1, i);
+            let item = match ctx.get_type(-1) {
+                DUK_TYPE_STRING if !ctx.is_symbol(-1) => Ok(ctx.get_string(-1).to_string()),
+                // sources may be null when unknown
+                DUK_TYPE_NULL => Ok(String::new()),
+                _ => Err(invalid(&format!("{name} must contain strings"))),
+            };
+            ctx.pop();
+            item
+        }).collect()
+    } else {
+        Err(invalid(&format!("{name} must be an array")))
+    };
+    ctx.pop();
+    items
+}
+
+/// Decodes the base64 VLQ fields of a mapping segment.
+fn decode_vlq(segment: &str) -> Result<Vec<i64>, JsError> {
+    let mut fields = Vec::with_capacity(5);
+    let (mut value, mut shift) = (0i64, 0u32);
+    for b in segment.bytes() {
+        let digit = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(invalid(&format!("invalid character {:?} in mappings", b as char))),
+        } as i64;
+        if shift > 32 {
+            return Err(invalid("mapping value too large"));
+        }
+        value |= (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+        } else {
+            fields.push(if value & 1 == 1 { -(value >> 1) } else { value >> 1 });
+            value = 0;
+            shift = 0;
+        }
+    }
+    if shift != 0 {
+        return Err(invalid("truncated mapping segment"));
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Maps `app.js` below to lines 1, 3 and 7 of `src/app.ts`.
+    const MAP: &str = r#"{
+        "version": 3,
+        "file": "app.js",
+        "sourceRoot": "src",
+        "sources": ["app.ts"],
+        "names": ["fail"],
+        "mappings": "AAAAA;IAEI;;AAIJA"
+    }"#;
+
+    const APP: &str = "function fail(msg) {\n    throw new Error(msg);\n}\nfail('boom');\n";
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let engine = JsEngine::new().unwrap();
+        let map = engine.parse_source_map(MAP).unwrap();
+        assert_eq!(map.file(), Some("app.js"));
+        assert_eq!(map.sources(), ["src/app.ts"]);
+        let pos = map.lookup(2, 10).unwrap();
+        assert_eq!(pos.to_string(), "src/app.ts:3:5");
+        assert_eq!(pos.name, None);
+        // columns before the first mapping resolve to the first mapping of the line
+        assert_eq!(map.lookup(2, 1).unwrap().to_string(), "src/app.ts:3:5");
+        let pos = map.lookup(4, 1).unwrap();
+        assert_eq!(pos.to_string(), "src/app.ts:7:1");
+        assert_eq!(pos.name.as_deref(), Some("fail"));
+        assert_eq!(map.lookup(3, 1), None);
+        assert_eq!(map.lookup(0, 1), None);
+        assert_eq!(map.lookup(10, 1), None);
+
+        assert_eq!(decode_vlq("2HwBD").unwrap(), [123, 24, -1]);
+        for json in ["", "[]", r#"{"version": 2, "sources": [], "mappings": ""}"#,
+                     r#"{"version": 3, "sources": [], "mappings": "AAAA"}"#,
+                     r#"{"version": 3, "sources": ["a"], "mappings": "A*"}"#,
+                     r#"{"version": 3, "sources": ["aé"], "mappings": "AAAA"} x"#] {
+            assert!(engine.parse_source_map(json).unwrap_err().to_string().contains("invalid source map"), "{json}");
+        }
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_mapped_stack() {
+        let engine = JsEngine::new().unwrap();
+        let options = CompileOptions::new().filename("app.js").source_map(engine.parse_source_map(MAP).unwrap());
+        let err = engine.eval_with(APP, &options).unwrap_err().to_string();
+        assert!(err.starts_with("Error: Error: boom\n"), "{err}");
+        assert!(err.contains("at fail (src/app.ts:3:5)"), "{err}");
+        assert!(err.contains("at eval (src/app.ts:7:1)"), "{err}");
+        assert_eq!(engine.get_top(), 0);
+
+        // scripts see the rewritten stack, frames of other files are unchanged
+        engine.eval_file("other.js", "function check() { try { fail('x'); } catch (e) { return e.stack; } }\ncheck()").unwrap();
+        let stack = engine.get_string(-1).to_string();
+        engine.pop();
+        assert!(stack.contains("at fail (src/app.ts:3:5)"), "{stack}");
+        assert!(stack.contains("at check (other.js:1)"), "{stack}");
+
+        // errors without mapped frames keep their message
+        let err = engine.eval_file("other.js", "null.x").unwrap_err();
+        assert!(!err.to_string().contains('\n'), "{err}");
+        assert_eq!(engine.map_stack("    at f (other.js:2)"), "    at f (other.js:2)");
+        assert!(engine.source_map("app.js").is_some());
+        assert_eq!(engine.get_top(), 0);
+
+        // a throwing `stack` getter falls back to the message
+        let err = engine.eval_file("other.js", "var e = new Error('own');\n\
+            Object.defineProperty(e, 'stack', { get: function () { throw 1; } });\nthrow e").unwrap_err();
+        assert_eq!(err.to_string(), "Error: Error: own");
+        assert!(!engine.is_poisoned());
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_stack_rewrite_retried() {
+        let engine = JsEngine::new().unwrap();
+        let map = engine.parse_source_map(MAP).unwrap();
+        engine.eval("var E = Error; delete this.Error;").unwrap();
+        engine.pop();
+        engine.register_source_map("app.js", map.clone()).unwrap();
+        engine.eval("var define = Object.defineProperty; this.Error = E; delete Object.defineProperty;").unwrap();
+        engine.pop();
+        assert!(engine.register_source_map("app.js", map.clone()).is_err());
+        assert_eq!(engine.get_top(), 0);
+
+        engine.eval("Object.defineProperty = define;").unwrap();
+        engine.pop();
+        engine.register_source_map("app.js", map).unwrap();
+        let err = engine.eval_file("app.js", "function fail(msg) {\n    throw new Error(msg);\n}\nfail('x')").unwrap_err().to_string();
+        assert!(err.contains("at fail (src/app.ts:3:5)"), "{err}");
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_mapped_stack_in_realm() {
+        let engine = JsEngine::new().unwrap();
+        engine.register_source_map("app.js", engine.parse_source_map(MAP).unwrap()).unwrap();
+        let realm = Realm::new(&engine).unwrap();
+        realm.eval_file("app.js", "function fail(msg) {\n    throw new Error(msg);\n}\n").unwrap();
+        realm.pop();
+        realm.eval_file("other.js", "try { fail('x'); } catch (e) { e.stack }").unwrap();
+        let stack = realm.get_string(-1).to_string();
+        realm.pop();
+        assert!(stack.contains("at fail (src/app.ts:3:5)"), "{stack}");
+    }
+}
@@ -0,0 +1,235 @@
+use super::*;
+
+/// Well-known symbols, see [`DukContext::push_well_known_symbol`].
+///
+/// Duktape honours `Symbol.hasInstance`, `Symbol.isConcatSpreadable`, `Symbol.toPrimitive` and
+/// `Symbol.toStringTag`. It has no iteration protocol and does not define `Symbol.iterator` or
+/// `Symbol.asyncIterator`, which are provided for hosts defining them for scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WellKnownSymbol {
+    AsyncIterator,
+    HasInstance,
+    IsConcatSpreadable,
+    Iterator,
+    ToPrimitive,
+    ToStringTag,
+}
+
+impl WellKnownSymbol {
+    /// Returns the description, e.g. `Symbol.iterator`.
+    pub fn description(self) -> &'static str {
+        match self {
+            WellKnownSymbol::AsyncIterator => "Symbol.asyncIterator",
+            WellKnownSymbol::HasInstance => "Symbol.hasInstance",
+            WellKnownSymbol::IsConcatSpreadable => "Symbol.isConcatSpreadable",
+            WellKnownSymbol::Iterator => "Symbol.iterator",
+            WellKnownSymbol::ToPrimitive => "Symbol.toPrimitive",
+            WellKnownSymbol::ToStringTag => "Symbol.toStringTag",
+        }
+    }
+}
+
+impl DukContext {
+    pub(crate) fn push_bytes(&self, bytes: &[u8]) {
+        unsafe {
+            duk_push_lstring(self.ctx, bytes.as_ptr() as *const c_char, bytes.len());
+        }
+    }
+
+    /// Pushes a new unique symbol created by the built-in `Symbol(description)`.
+    pub fn push_symbol(&self, description: &str) -> Result<(), JsError> {
+        self.check_poisoned()?;
+        self.push_string(description);
+        let res = unsafe { duk_api_push_symbol(self.ctx) };
+        self.resume_panic();
+        self.propagate_js_error(if res == DUK_EXEC_SUCCESS { Ok(()) } else { Err(res) })
+    }
+
+    /// Pushes the symbol registered under `key`, like `Symbol.for(key)`.
+    pub fn push_global_symbol(&self, key: &str) {
+        self.push_bytes(&[b"\x80", key.as_bytes()].concat());
+    }
+
+    /// Pushes the hidden symbol `name`.
+    ///
+    /// Properties keyed by hidden symbols cannot be read, listed or modified by scripts,
+    /// so they can hold host-private state on script objects.
+    pub fn push_hidden_symbol(&self, name: &str) {
+        self.push_bytes(&[b"\xFF", name.as_bytes()].concat());
+    }
+
+    pub fn push_well_known_symbol(&self, symbol: WellKnownSymbol) {
+        self.push_bytes(&[b"\x81", symbol.description().as_bytes(), b"\xFF"].concat());
+    }
+
+    /// Returns `true` for hidden symbols, see [`DukContext::push_hidden_symbol`].
+    pub fn is_hidden_symbol(&self, index: i32) -> bool {
+        self.is_symbol(index) && matches!(self.symbol_bytes(index).first(), Some(0x82 | 0xFF))
+    }
+
+    /// Returns the description of the symbol at `index`, or the name of a hidden symbol.
+    /// Returns `None` for other values and for symbols created without description.
+    pub fn get_symbol_description(&self, index: i32) -> Option<String> {
+        if !self.is_symbol(index) {
+            return None;
+        }
+        let bytes = self.symbol_bytes(index);
+        match bytes.split_first() {
+            // global symbol, the rest is the registry key
+            Some((0x80, rest)) => Some(String::from_utf8_lossy(rest).into_owned()),
+            // local or well-known symbol, the description ends at 0xFF followed by a unique
+            // suffix; a trailing 0xFF marks an undefined description
+            Some((0x81, rest)) => {
+                if rest.len() > 1 && rest.first() == Some(&0xFF) && rest.last() == Some(&0xFF) {
+                    return None;
+                }
+                let desc = rest.split(|b| *b == 0xFF).next().unwrap_or_default();
+                Some(String::from_utf8_lossy(desc).into_owned())
+            }
+            Some((_, rest)) => Some(String::from_utf8_lossy(rest).into_owned()),
+            None => None,
+        }
+    }
+
+    fn symbol_bytes(&self, index: i32) -> &[u8] {
+        unsafe {
+            let mut len: usize = 0;
+            let ptr = duk_get_lstring(self.ctx, index, Some(&mut len)) as *const u8;
+            std::slice::from_raw_parts(ptr, len)
+        }
+    }
+
+    /// Gets the property of the object at `obj_index` keyed by the symbol at `symbol_index`
+    /// and pushes it.
+    pub fn get_prop_symbol(&self, obj_index: i32, symbol_index: i32) -> bool {
+        let obj_index = self.normalize_index(obj_index);
+        self.dup(symbol_index);
+        self.get_prop(obj_index)
+    }
+
+    /// Pops the value on stack top into the property of the object at `obj_index` keyed by the
+    /// symbol at `symbol_index`.
+    pub fn put_prop_symbol(&self, obj_index: i32, symbol_index: i32) {
+        let obj_index = self.normalize_index(obj_index);
+        self.dup(symbol_index);
+        self.swap(-1, -2);
+        self.put_prop(obj_index);
+    }
+
+    /// Gets the hidden property `name` of the object at `obj_index` and pushes it.
+    pub fn get_hidden_prop(&self, obj_index: i32, name: &str) -> bool {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_hidden_symbol(name);
+        self.get_prop(obj_index)
+    }
+
+    /// Pops the value on stack top into the hidden property `name` of the object at `obj_index`.
+    pub fn put_hidden_prop(&self, obj_index: i32, name: &str) {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_hidden_symbol(name);
+        self.swap(-1, -2);
+        self.put_prop(obj_index);
+    }
+
+    pub fn has_hidden_prop(&self, obj_index: i32, name: &str) -> bool {
+        let obj_index = self.normalize_index(obj_index);
+        self.push_hidden_symbol(name);
+        self.has_prop(obj_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eval_string;
+
+    #[test]
+    fn test_symbols() {
+        let engine = JsEngine::new().unwrap();
+        engine.push_global_object();
+        engine.push_symbol("tag").unwrap();
+        assert!(engine.is_symbol(-1));
+        assert_eq!(engine.get_type(-1), DukType::DUK_TYPE_STRING);
+        assert_eq!(engine.get_symbol_description(-1).as_deref(), Some("tag"));
+        engine.put_prop_string(0, "sym");
+
+        // unique even with equal descriptions
+        engine.push_symbol("tag").unwrap();
+        engine.put_prop_string(0, "other");
+        engine.push_global_symbol("app.key");
+        engine.put_prop_string(0, "global");
+        engine.pop();
+
+        assert_eq!(eval_string(&engine, "typeof sym + ',' + String(sym) + ',' + (sym === other)"), "symbol,Symbol(tag),false");
+        assert_eq!(eval_string(&engine, "global === Symbol.for('app.key')"), "true");
+
+        engine.eval("var o = {}; o[sym] = 1; o").unwrap();
+        engine.eval("sym").unwrap();
+        assert!(engine.get_prop_symbol(0, 1));
+        assert_eq!(engine.get_number(-1), 1.);
+        engine.pop();
+        engine.push_string("by symbol");
+        engine.put_prop_symbol(0, 1);
+        engine.set_top(0);
+        assert_eq!(eval_string(&engine, "o[sym]"), "by symbol");
+    }
+
+    #[test]
+    fn test_push_symbol_ignores_script_bindings() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("Symbol = function() { throw new Error('replaced'); }").unwrap();
+        engine.pop();
+        engine.push_symbol("tag").unwrap();
+        assert!(engine.is_symbol(-1));
+        assert_eq!(engine.get_symbol_description(-1).as_deref(), Some("tag"));
+        engine.pop();
+    }
+
+    #[test]
+    fn test_hidden_symbols() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var o = { visible: 1 }; o").unwrap();
+        engine.push_string("secret");
+        engine.put_hidden_prop(-2, "state");
+        engine.pop();
+
+        assert_eq!(eval_string(&engine, "Object.getOwnPropertyNames(o).concat(Object.getOwnPropertySymbols(o)).length"), "1");
+        assert_eq!(eval_string(&engine, "JSON.stringify(o)"), "{\"visible\":1}");
+        assert_eq!(eval_string(&engine, "o[Symbol.for('state')] = 'x'; o[Symbol('state')] = 'y'; 'state' in o"), "false");
+
+        engine.eval("o").unwrap();
+        assert!(engine.has_hidden_prop(-1, "state"));
+        assert!(engine.get_hidden_prop(-1, "state"));
+        assert_eq!(engine.get_string(-1), "secret");
+        engine.push_hidden_symbol("state");
+        assert!(engine.is_hidden_symbol(-1));
+        assert_eq!(engine.get_symbol_description(-1).as_deref(), Some("state"));
+        engine.pop_n(3);
+    }
+
+    #[test]
+    fn test_well_known_symbols() {
+        let engine = JsEngine::new().unwrap();
+        engine.eval("var o = {}; o").unwrap();
+        engine.push_well_known_symbol(WellKnownSymbol::ToPrimitive);
+        engine.push_closure(1, |ctx| {
+            ctx.push_i32(if ctx.get_string(0) == "number" { 42 } else { 0 });
+            Ok(Return::Top)
+        });
+        engine.put_prop_symbol(0, 1);
+        engine.push_well_known_symbol(WellKnownSymbol::ToStringTag);
+        engine.push_string("Host");
+        engine.put_prop_symbol(0, 2);
+        engine.set_top(0);
+
+        assert_eq!(eval_string(&engine, "(o * 1) + ',' + Object.prototype.toString.call(o)"), "42,[object Host]");
+
+        engine.push_well_known_symbol(WellKnownSymbol::Iterator);
+        assert_eq!(engine.get_symbol_description(-1).as_deref(), Some("Symbol.iterator"));
+        assert!(!engine.is_hidden_symbol(-1));
+        engine.pop();
+        engine.eval("Symbol()").unwrap();
+        assert_eq!(engine.get_symbol_description(-1), None);
+        engine.pop();
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by unit tests.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use super::*;
+
+/// Evaluates `code` and returns its result converted to a string.
+pub(crate) fn eval_string(ctx: &DukContext, code: &str) -> String {
+    ctx.eval(code).unwrap();
+    let s = ctx.safe_to_lstring(-1);
+    ctx.pop();
+    s
+}
+
+pub(crate) fn eval_bool(ctx: &DukContext, code: &str) -> bool {
+    ctx.eval(code).unwrap();
+    let res = ctx.get_boolean(-1);
+    ctx.pop();
+    res
+}
+
+/// Sets the flag when dropped, e.g. by a finalizer of the heap.
+pub(crate) struct DropFlag(pub(crate) Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,245 @@
+//! Integration with the [`tracing`] ecosystem, enabled with the `tracing` feature.
+//!
+//! Console calls are emitted as events with target `kg_js::console`, carrying fields:
+//!
+//! | field     | value                                                           |
+//! |-----------|-----------------------------------------------------------------|
+//! | `console` | console function, e.g. `Log` or `Warn`                          |
+//! | `file`    | file name of the calling script, if compiled with one           |
+//! | `line`    | line number of the call                                         |
+//! | `label`   | label of the global environment, see [`DukContext::set_label`]  |
+//!
+//! `file` and `line` are the original position if the script has a source map, see
+//! [`DukContext::register_source_map`].
+//!
+//! The event level follows [`ConsoleFunc::level`]. Events are emitted in addition to the
+//! [`JsInterop::console`] callback, so the default implementation keeps logging the message
+//! through the `log` crate.
+//!
+//! [`DukContext::eval`], [`DukContext::eval_file`] and the protected call functions run inside
+//! `js.eval` and `js.call` spans carrying the `label` (and `file` for scripts).
+
+use tracing::{Level, Span};
+use super::*;
+
+/// Target of console events.
+pub const CONSOLE_TARGET: &str = "kg_js::console";
+
+const LABEL_PROP: &str = "label";
+
+impl DukContext {
+    /// Assigns a label identifying the global environment of this context in tracing output.
+    ///
+    /// Engines and realms have separate global environments, so each can be labelled separately.
+    pub fn set_label(&self, label: &str) {
+        self.push_global_object();
+        self.push_string(label);
+        self.put_hidden_prop(-2, LABEL_PROP);
+        self.pop();
+    }
+
+    /// Returns the label assigned with [`DukContext::set_label`].
+    pub fn label(&self) -> Option<String> {
+        self.push_global_object();
+        let found = self.get_hidden_prop(-1, LABEL_PROP);
+        let label = if found { Some(self.get_string(-1).to_string()) } else { None };
+        self.pop_n(2);
+        label
+    }
+}
+
+macro_rules! console_event {
+    ($level:expr, $ctx:expr, $func:expr, $msg:expr) => {
+        if tracing::enabled!(target: CONSOLE_TARGET, $level) {
+            // level -1 is the native console function, -2 its caller
+            let (file, line) = match $ctx.callstack_entry(-2) {
+                Some(CallFrame { original: Some(original), .. }) => (Some(original.source), Some(original.line)),
+                Some(CallFrame { filename, line, .. }) => (filename, Some(line)),
+                None => (None, None),
+            };
+            let label = $ctx.label();
+            tracing::event!(target: CONSOLE_TARGET, $level,
+                console = ?$func,
+                file = file.as_deref(),
+                line = line,
+                label = label.as_deref(),
+                "{}", $msg());
+        }
+    };
+}
+
+pub(crate) fn console_event<M: FnOnce() -> String>(ctx: &DukContext, func: ConsoleFunc, msg: M) {
+    match func.level() {
+        log::Level::Error => console_event!(Level::ERROR, ctx, func, msg),
+        log::Level::Warn => console_event!(Level::WARN, ctx, func, msg),
+        log::Level::Info => console_event!(Level::INFO, ctx, func, msg),
+        log::Level::Debug => console_event!(Level::DEBUG, ctx, func, msg),
+        log::Level::Trace => console_event!(Level::TRACE, ctx, func, msg),
+    }
+}
+
+pub(crate) fn eval_span(ctx: &DukContext, file: Option<&str>) -> Span {
+    let span = tracing::debug_span!("js.eval", label = tracing::field::Empty, file);
+    if !span.is_disabled() {
+        if let Some(label) = ctx.label() {
+            span.record("label", label.as_str());
+        }
+    }
+    span
+}
+
+pub(crate) fn call_span(ctx: &DukContext) -> Span {
+    let span = tracing::debug_span!("js.call", label = tracing::field::Empty);
+    if !span.is_disabled() {
+        if let Some(label) = ctx.label() {
+            span.record("label", label.as_str());
+        }
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use super::*;
+
+    /// Records events as `level target [span fields] field=value...` lines.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<String>>>,
+        current: Arc<Mutex<Vec<u64>>>,
+        lines: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields(span.metadata().name().to_string());
+            span.record(&mut fields);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(fields.0);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = Fields(String::new());
+            values.record(&mut fields);
+            spans[span.into_u64() as usize - 1].push_str(&fields.0);
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(format!("{} {}", event.metadata().level(), event.metadata().target()));
+            if let Some(id) = self.current.lock().unwrap().last() {
+                fields.0.push_str(&format!(" [{}]", self.spans.lock().unwrap()[*id as usize - 1]));
+            }
+            event.record(&mut fields);
+            self.lines.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, span: &Id) {
+            self.current.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _span: &Id) {
+            self.current.lock().unwrap().pop();
+        }
+    }
+
+    fn record<F: FnOnce()>(f: F) -> Vec<String> {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), f);
+        let lines = recorder.lines.lock().unwrap();
+        lines.clone()
+    }
+
+    #[test]
+    fn test_label() {
+        let engine = JsEngine::new().unwrap();
+        assert_eq!(engine.label(), None);
+        engine.set_label("tenant-1");
+        assert_eq!(engine.label().as_deref(), Some("tenant-1"));
+        let realm = Realm::new(&engine).unwrap();
+        assert_eq!(realm.label(), None);
+        assert_eq!(engine.get_top(), 0);
+    }
+
+    #[test]
+    fn test_console_events() {
+        let engine = JsEngine::new().unwrap();
+        engine.init_console();
+        engine.set_label("engine");
+        let realm = Realm::with_options(&engine, RealmOptions { console: true, ..Default::default() }).unwrap();
+        realm.set_label("tenant");
+
+        let lines = record(|| {
+            engine.eval_file("main.js", "\nconsole.warn('first');").unwrap();
+            realm.eval_file("tenant.js", "function f() {\n  console.log('x', 1);\n}\nf();").unwrap();
+        });
+        assert_eq!(lines, vec![
+            "WARN kg_js::console [js.eval file=main.js label=engine] message=first console=Warn file=main.js line=2 label=engine",
+            "DEBUG kg_js::console [js.eval file=tenant.js label=tenant] message=x 1 console=Log file=tenant.js line=2 label=tenant",
+        ]);
+    }
+
+    #[derive(Debug, Default)]
+    struct ConsoleInterop {
+        messages: Vec<String>,
+    }
+
+    impl JsInterop for ConsoleInterop {
+        fn call(&mut self, _ctx: &mut DukContext, _func_name: &str) -> Result<Return, JsError> {
+            Ok(Return::Undefined)
+        }
+
+        fn console(&mut self, _func: ConsoleFunc, msg: &str) {
+            self.messages.push(msg.to_string());
+        }
+    }
+
+    #[test]
+    fn test_console_events_keep_interop_output() {
+        let engine = JsEngine::with_interop(ConsoleInterop::default()).unwrap();
+        engine.init_console();
+        let lines = record(|| {
+            engine.eval("console.warn('both')").unwrap();
+        });
+        assert_eq!(lines.len(), 1);
+        assert_eq!(engine.interop_as::<ConsoleInterop>().messages, vec!["both"]);
+    }
+
+    #[test]
+    fn test_call_span() {
+        let engine = JsEngine::new().unwrap();
+        engine.init_console_structured();
+        engine.eval("(function(a) { console.info(a); })").unwrap();
+        engine.push_string("arg");
+        let lines = record(|| {
+            engine.pcall(1).unwrap();
+        });
+        assert_eq!(lines, vec![
+            "INFO kg_js::console [js.call] message=arg console=Info file=eval line=1",
+        ]);
+    }
+}